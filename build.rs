@@ -1,8 +1,20 @@
+#[cfg(feature = "ffi")]
 use std::path::PathBuf;
+#[cfg(feature = "ffi")]
 extern crate bindgen;
+#[cfg(feature = "ffi")]
 extern crate cc;
 
 fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    // the models run natively through rust_lib unless the C predictors are asked for
+    #[cfg(feature = "ffi")]
+    build_ffi();
+}
+
+#[cfg(feature = "ffi")]
+fn build_ffi() {
     let lief_lib = std::env::var("LIEF_LIB_PATH")
         .unwrap_or_else(|_| "/usr/lib/".to_string());
     let lief_include = std::env::var("LIEF_INCLUDE_PATH")
@@ -1,4 +1,5 @@
 #!/bin/bash
 /usr/local/bin/sentinel check-unauthorized-changes &
 /usr/local/bin/sentinel analyze-process-behaviors &
+/usr/local/bin/sentinel quarantine verify --every 6h &
 wait
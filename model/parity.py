@@ -0,0 +1,97 @@
+"""Writes parity.json, the probabilities the Rust predictor has to reproduce.
+
+Scores feature vectors built around the split thresholds of elf/model.json and
+exe/model.json with libxgboost, the way the C predictors did (float32 DMatrix,
+missing = 0). Without the xgboost package it falls back to walking the JSON
+trees in float32 and records that in "source", rerun with xgboost installed to
+replace those values with libxgboost's.
+"""
+import json
+import math
+import random
+import struct
+
+ROWS = 32
+
+
+def f32(x):
+    return struct.unpack("f", struct.pack("f", x))[0]
+
+
+def number(value):
+    return float(str(value).strip("[]"))
+
+
+def features(model, rng):
+    learner = model["learner"]
+    num_feature = int(learner["learner_model_param"]["num_feature"])
+    thresholds = [[] for _ in range(num_feature)]
+    for tree in learner["gradient_booster"]["model"]["trees"]:
+        for node, left in enumerate(tree["left_children"]):
+            if left != -1:
+                thresholds[tree["split_indices"][node]].append(tree["split_conditions"][node])
+
+    rows = []
+    for _ in range(ROWS):
+        row = []
+        for candidates in thresholds:
+            if not candidates or rng.random() < 0.1:
+                row.append(0.0)  # missing
+                continue
+            value = rng.choice(candidates)
+            row.append(f32(value * rng.choice([0.5, 0.999, 1.0, 1.001, 2.0])))
+        rows.append(row)
+    return rows
+
+
+def reference(model, rows):
+    learner = model["learner"]
+    base_score = f32(number(learner["learner_model_param"]["base_score"]))
+    base_margin = f32(-math.log(f32(1.0 / base_score - 1.0)))
+    trees = learner["gradient_booster"]["model"]["trees"]
+
+    predictions = []
+    for row in rows:
+        margin = base_margin
+        for tree in trees:
+            node = 0
+            while tree["left_children"][node] != -1:
+                value = row[tree["split_indices"][node]]
+                if value == 0.0:
+                    go_left = tree["default_left"][node]
+                else:
+                    go_left = value < f32(tree["split_conditions"][node])
+                node = tree["left_children"][node] if go_left else tree["right_children"][node]
+            margin = f32(margin + f32(tree["split_conditions"][node]))
+        predictions.append(f32(1.0 / (1.0 + math.exp(-margin))))
+    return predictions
+
+
+def main():
+    try:
+        import numpy as np
+        import xgboost as xgb
+    except ImportError:
+        xgb = None
+
+    rng = random.Random(1)
+    parity = {"source": f"xgboost {xgb.__version__}" if xgb else "reference"}
+    for format in ["elf", "exe"]:
+        with open(f"{format}/model.json") as f:
+            model = json.load(f)
+        rows = features(model, rng)
+        if xgb:
+            booster = xgb.Booster()
+            booster.load_model(f"{format}/model.json")
+            matrix = xgb.DMatrix(np.array(rows, dtype=np.float32), missing=0.0)
+            predictions = [float(p) for p in booster.predict(matrix)]
+        else:
+            predictions = reference(model, rows)
+        parity[format] = [{"features": row, "probability": p} for row, p in zip(rows, predictions)]
+
+    with open("parity.json", "w") as f:
+        json.dump(parity, f, indent=1)
+
+
+if __name__ == "__main__":
+    main()
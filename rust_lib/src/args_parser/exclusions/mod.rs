@@ -0,0 +1,101 @@
+use std::fmt;
+use chrono::{DateTime, Local};
+use clap::Subcommand;
+use rusqlite::{Connection, Result};
+
+use crate::args_parser::file_scanner::PathFilter;
+
+#[derive(Subcommand, Clone)]
+pub enum ExclusionCommands {
+    /// Exclude a glob from every scan, e.g. `node_modules`, `*.vmdk` or `/srv/vendor/**`
+    Add {
+        pattern: String,
+    },
+    Remove {
+        pattern: String,
+    },
+    List,
+}
+
+#[derive(Debug, Clone)]
+pub struct ScanExclusion {
+    pub id: i64,
+    pub pattern: String,
+    pub added_date: Option<DateTime<Local>>,
+}
+
+#[derive(Debug)]
+pub enum ExclusionError {
+    /// Not a glob `scan-dir` could match, nothing was stored
+    Pattern(String, globset::Error),
+    Db(rusqlite::Error),
+}
+
+impl fmt::Display for ExclusionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExclusionError::Pattern(pattern, e) => write!(f, "Invalid pattern {pattern:?}: {e}"),
+            ExclusionError::Db(e) => write!(f, "Couldn't store exclusion: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ExclusionError {}
+
+impl From<rusqlite::Error> for ExclusionError {
+    fn from(e: rusqlite::Error) -> Self {
+        ExclusionError::Db(e)
+    }
+}
+
+/// The `scan_exclusions` table, honored by every `scan-dir`
+pub struct ExclusionList {
+    db: Connection,
+}
+
+impl ExclusionList {
+    pub fn from_db(conn: Connection) -> Self {
+        Self { db: conn }
+    }
+
+    /// Returns false if the pattern was already excluded
+    pub fn add(&self, pattern: &str) -> std::result::Result<bool, ExclusionError> {
+        PathFilter::new(&[], &[pattern.to_string()])
+            .map_err(|e| ExclusionError::Pattern(pattern.to_string(), e))?;
+
+        let inserted = self.db.execute(
+            "INSERT OR IGNORE INTO scan_exclusions (pattern, added_date) VALUES ($1, $2)",
+            [pattern.to_string(), Local::now().to_rfc3339()],
+        )?;
+        Ok(inserted > 0)
+    }
+
+    /// Returns false if there was no such pattern
+    pub fn remove(&self, pattern: &str) -> Result<bool> {
+        let removed = self.db.execute("DELETE FROM scan_exclusions WHERE pattern = $1", [pattern])?;
+        Ok(removed > 0)
+    }
+
+    pub fn list(&self) -> Result<Vec<ScanExclusion>> {
+        list(&self.db)
+    }
+
+    /// What every scan skips, read from a connection the scan goes on using
+    pub fn patterns(conn: &Connection) -> Result<Vec<String>> {
+        Ok(list(conn)?.into_iter().map(|exclusion| exclusion.pattern).collect())
+    }
+}
+
+fn list(db: &Connection) -> Result<Vec<ScanExclusion>> {
+    let mut stmt = db.prepare("SELECT id, pattern, added_date FROM scan_exclusions ORDER BY id")?;
+    stmt.query_map([], |row| {
+        Ok(ScanExclusion {
+            id: row.get(0)?,
+            pattern: row.get(1)?,
+            added_date: row.get::<_, String>(2).ok()
+                .and_then(|date| DateTime::parse_from_rfc3339(&date).ok())
+                .map(|date| date.with_timezone(&Local)),
+        })
+    })?
+    .collect()
+}
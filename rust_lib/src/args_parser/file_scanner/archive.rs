@@ -0,0 +1,424 @@
+// In-memory unpacking of the containers malware usually travels in: zip, tar, gzip, xz and zstd streams,
+// .deb (ar) and .rpm (cpio) packages. Uncompressed formats are sliced, not copied, so only what
+// gets decompressed counts against the size budget.
+use std::{fmt, io::{self, Cursor, Read}};
+
+/// Below this, any compression ratio is fine: a few KB of zeros legitimately compress a thousandfold
+const RATIO_FLOOR: u64 = 1 << 20;
+
+/// Compressed stream extensions, stripped to name what they decompress to
+const STREAM_EXTENSIONS: [(&str, &str); 6] = [("gz", ""), ("tgz", ".tar"), ("xz", ""), ("txz", ".tar"), ("zst", ""), ("tzst", ".tar")];
+
+/// How far `FileScanner` goes into an archive before giving up on it, to defuse archive bombs
+#[derive(Debug, Clone, Copy)]
+pub struct ArchiveLimits {
+    /// How many containers deep to unpack, 0 doesn't open archives at all. A compressed stream is part of
+    /// the container it compresses, `x.tar.gz` is one deep
+    pub max_depth: usize,
+    /// Bytes decompressed out of one file on disk, nested archives included
+    pub max_size: u64,
+    /// Largest decompressed to compressed size ratio of any member or stream past `RATIO_FLOOR`
+    pub max_ratio: u64,
+    /// Files in one file on disk, nested archives included
+    pub max_entries: usize,
+}
+
+impl Default for ArchiveLimits {
+    fn default() -> Self {
+        Self { max_depth: 3, max_size: 512 << 20, max_ratio: 100, max_entries: 10_000 }
+    }
+}
+
+#[derive(Debug)]
+pub enum ArchiveError {
+    /// Truncated or corrupt
+    Io(io::Error),
+    Zip(zip::result::ZipError),
+    Corrupt(&'static str),
+    TooLarge(u64),
+    Ratio(u64),
+    TooManyEntries(usize),
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArchiveError::Io(e) => write!(f, "{e}"),
+            ArchiveError::Zip(e) => write!(f, "{e}"),
+            ArchiveError::Corrupt(format) => write!(f, "corrupt {format}"),
+            ArchiveError::TooLarge(max) => write!(f, "more than {max} bytes once decompressed, possible archive bomb"),
+            ArchiveError::Ratio(max) => write!(f, "compression ratio over {max}, possible archive bomb"),
+            ArchiveError::TooManyEntries(max) => write!(f, "more than {max} files, possible archive bomb"),
+        }
+    }
+}
+
+impl std::error::Error for ArchiveError {}
+
+impl From<io::Error> for ArchiveError {
+    fn from(e: io::Error) -> Self {
+        ArchiveError::Io(e)
+    }
+}
+
+impl From<zip::result::ZipError> for ArchiveError {
+    fn from(e: zip::result::ZipError) -> Self {
+        ArchiveError::Zip(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Container {
+    Zip,
+    Tar,
+    Gzip,
+    Xz,
+    Zstd,
+    /// .deb packages
+    Ar,
+    Rpm,
+}
+
+impl Container {
+    /// Sniffed from the magic bytes, extensions lie
+    pub fn of(data: &[u8]) -> Option<Self> {
+        match data {
+            [b'P', b'K', 3, 4, ..] | [b'P', b'K', 5, 6, ..] => Some(Container::Zip),
+            [0x1f, 0x8b, ..] => Some(Container::Gzip),
+            [0xfd, b'7', b'z', b'X', b'Z', 0, ..] => Some(Container::Xz),
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Some(Container::Zstd),
+            [0xed, 0xab, 0xee, 0xdb, ..] => Some(Container::Rpm),
+            _ if data.starts_with(b"!<arch>\n") => Some(Container::Ar),
+            _ if data.get(257..262) == Some(b"ustar") => Some(Container::Tar),
+            _ => None,
+        }
+    }
+}
+
+/// Hands every file inside `data` to `visit` with its path in the archive, `!`-separated for nested archives:
+/// `inner.zip!bin/evil`. Containers are visited too, then opened. Compressed streams are transparent,
+/// `x.tar.gz` lists the files of the tar. `name` is the file name of `data`, it names what a bare stream holds.
+///
+/// Stops at the first limit exceeded, what was visited until then stays visited.
+pub fn unpack(name: &str, data: &[u8], limits: &ArchiveLimits, visit: &mut dyn FnMut(&str, &[u8])) -> Result<(), ArchiveError> {
+    let mut unpacker = Unpacker { limits, remaining: limits.max_size, entries: 0, visit };
+    unpacker.container(name, "", data, 1)
+}
+
+struct Unpacker<'a> {
+    limits: &'a ArchiveLimits,
+    /// Bytes left to decompress
+    remaining: u64,
+    entries: usize,
+    visit: &'a mut dyn FnMut(&str, &[u8]),
+}
+
+impl Unpacker<'_> {
+    /// `prefix` is the path of the container followed by `!`, empty for the file on disk
+    fn container(&mut self, name: &str, prefix: &str, data: &[u8], depth: usize) -> Result<(), ArchiveError> {
+        let Some(container) = Container::of(data) else { return Ok(()) };
+        match container {
+            Container::Gzip | Container::Xz | Container::Zstd => {
+                let decoded = self.decompress(container, data)?;
+                let name = stream_name(name);
+                // a stream is part of the container it compresses, `x.tar.gz` is one deep like `x.tar`.
+                // A stream in a stream counts, or a gzip quine would be decompressed until the size budget runs out.
+                let inner_depth = match Container::of(&decoded) {
+                    Some(Container::Gzip | Container::Xz | Container::Zstd) => depth + 1,
+                    _ => depth,
+                };
+                if inner_depth <= self.limits.max_depth && Container::of(&decoded).is_some() {
+                    self.container(&name, prefix, &decoded, inner_depth)
+                } else {
+                    self.entry(&format!("{prefix}{name}"), &decoded, depth)
+                }
+            }
+            Container::Zip => {
+                let mut zip = zip::ZipArchive::new(Cursor::new(data))?;
+                for i in 0..zip.len() {
+                    let file = match zip.by_index(i) {
+                        Ok(file) => file,
+                        // encrypted members and unsupported compression methods
+                        Err(zip::result::ZipError::UnsupportedArchive(_)) => continue,
+                        Err(e) => return Err(e.into()),
+                    };
+                    if !file.is_file() || file.encrypted() {
+                        continue;
+                    }
+                    let path = format!("{prefix}{}", file.name());
+                    let compressed = file.compressed_size();
+                    let content = self.read(file, Some(compressed))?;
+                    self.entry(&path, &content, depth)?;
+                }
+                Ok(())
+            }
+            Container::Tar => {
+                let mut tar = tar::Archive::new(Cursor::new(data));
+                let mut files = vec![];
+                for entry in tar.entries()? {
+                    let entry = entry?;
+                    if !entry.header().entry_type().is_file() {
+                        continue;
+                    }
+                    let start = entry.raw_file_position() as usize;
+                    let end = start.checked_add(entry.size() as usize).filter(|end| *end <= data.len())
+                        .ok_or(ArchiveError::Corrupt("tar"))?;
+                    files.push((String::from_utf8_lossy(&entry.path_bytes()).to_string(), start..end));
+                }
+                for (path, range) in files {
+                    self.entry(&format!("{prefix}{}", path.trim_start_matches("./")), &data[range], depth)?;
+                }
+                Ok(())
+            }
+            Container::Ar => {
+                for (path, content) in ar_members(data)? {
+                    self.entry(&format!("{prefix}{path}"), content, depth)?;
+                }
+                Ok(())
+            }
+            Container::Rpm => {
+                let payload = rpm_payload(data)?;
+                let cpio = match Container::of(payload) {
+                    Some(compression @ (Container::Gzip | Container::Xz | Container::Zstd)) => self.decompress(compression, payload)?,
+                    _ => payload.to_vec(),
+                };
+                for (path, content) in cpio_files(&cpio)? {
+                    self.entry(&format!("{prefix}{}", path.trim_start_matches("./")), content, depth)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn entry(&mut self, path: &str, data: &[u8], depth: usize) -> Result<(), ArchiveError> {
+        self.entries += 1;
+        if self.entries > self.limits.max_entries {
+            return Err(ArchiveError::TooManyEntries(self.limits.max_entries));
+        }
+        (self.visit)(path, data);
+        if depth < self.limits.max_depth {
+            let name = path.rsplit(['/', '!']).next().unwrap_or(path);
+            self.container(name, &format!("{path}!"), data, depth + 1)?;
+        }
+        Ok(())
+    }
+
+    fn decompress(&mut self, stream: Container, data: &[u8]) -> Result<Vec<u8>, ArchiveError> {
+        let compressed = Some(data.len() as u64);
+        match stream {
+            Container::Gzip => self.read(flate2::read::MultiGzDecoder::new(data), compressed),
+            Container::Xz => self.read(xz2::read::XzDecoder::new_multi_decoder(data), compressed),
+            Container::Zstd => {
+                let decoder = ruzstd::decoding::StreamingDecoder::new(data)
+                    .map_err(|_| ArchiveError::Corrupt("zstd stream"))?;
+                self.read(decoder, compressed)
+            }
+            _ => unreachable!("Only called on compressed streams"),
+        }
+    }
+
+    /// Reads until the size budget or the ratio limit runs out
+    fn read(&mut self, reader: impl Read, compressed: Option<u64>) -> Result<Vec<u8>, ArchiveError> {
+        let ratio_cap = compressed.map_or(u64::MAX, |compressed| compressed.saturating_mul(self.limits.max_ratio).max(RATIO_FLOOR));
+        let cap = self.remaining.min(ratio_cap);
+        let mut content = vec![];
+        reader.take(cap.saturating_add(1)).read_to_end(&mut content)?;
+        if content.len() as u64 > cap {
+            return Err(if cap == self.remaining {
+                ArchiveError::TooLarge(self.limits.max_size)
+            } else {
+                ArchiveError::Ratio(self.limits.max_ratio)
+            });
+        }
+        self.remaining -= content.len() as u64;
+        Ok(content)
+    }
+}
+
+/// `x.tar.gz` holds `x.tar`, `x.tgz` holds `x.tar`
+fn stream_name(name: &str) -> String {
+    if let Some((stem, extension)) = name.rsplit_once('.')
+        && let Some((_, suffix)) = STREAM_EXTENSIONS.iter().find(|(stream, _)| extension.eq_ignore_ascii_case(stream))
+    {
+        return format!("{stem}{suffix}");
+    }
+    name.to_string()
+}
+
+/// Members of a System V / GNU ar archive, the format of .deb packages
+fn ar_members(data: &[u8]) -> Result<Vec<(String, &[u8])>, ArchiveError> {
+    const HEADER: usize = 60;
+    let mut members = vec![];
+    let mut pos = 8;
+    while pos + HEADER <= data.len() {
+        let header = &data[pos..pos + HEADER];
+        if &header[58..60] != b"`\n" {
+            return Err(ArchiveError::Corrupt("ar archive"));
+        }
+        let field = |range: std::ops::Range<usize>| String::from_utf8_lossy(&header[range]).trim().to_string();
+        let size = field(48..58).parse::<usize>().map_err(|_| ArchiveError::Corrupt("ar archive"))?;
+        let start = pos + HEADER;
+        let end = start.checked_add(size).filter(|end| *end <= data.len()).ok_or(ArchiveError::Corrupt("ar archive"))?;
+        // GNU ar ends names with a slash, `/` and `//` are its symbol and long name tables
+        let name = field(0..16);
+        if name != "/" && name != "//" {
+            members.push((name.trim_end_matches('/').to_string(), &data[start..end]));
+        }
+        // members are aligned on 2 bytes
+        pos = end + end % 2;
+    }
+    Ok(members)
+}
+
+/// What follows the lead and the two headers of an .rpm: a compressed cpio archive
+fn rpm_payload(data: &[u8]) -> Result<&[u8], ArchiveError> {
+    const LEAD: usize = 96;
+    let header_end = |start: usize| -> Option<usize> {
+        let header = data.get(start..start + 16)?;
+        if header[..3] != [0x8e, 0xad, 0xe8] {
+            return None;
+        }
+        let index_count = u32::from_be_bytes(header[8..12].try_into().ok()?) as usize;
+        let store_size = u32::from_be_bytes(header[12..16].try_into().ok()?) as usize;
+        start.checked_add(16)?.checked_add(index_count.checked_mul(16)?)?.checked_add(store_size)
+    };
+    let signature_end = header_end(LEAD).ok_or(ArchiveError::Corrupt("rpm signature header"))?;
+    // the signature header is padded to 8 bytes, the main header isn't
+    let header_start = signature_end.next_multiple_of(8);
+    let payload_start = header_end(header_start).ok_or(ArchiveError::Corrupt("rpm header"))?;
+    data.get(payload_start..).ok_or(ArchiveError::Corrupt("rpm payload"))
+}
+
+/// Regular files of a `newc` cpio archive, the format of .rpm payloads
+fn cpio_files(data: &[u8]) -> Result<Vec<(String, &[u8])>, ArchiveError> {
+    const HEADER: usize = 110;
+    let corrupt = || ArchiveError::Corrupt("cpio archive");
+    let mut files = vec![];
+    let mut pos = 0;
+    while pos + HEADER <= data.len() {
+        let header = &data[pos..pos + HEADER];
+        if &header[..6] != b"070701" && &header[..6] != b"070702" {
+            return Err(corrupt());
+        }
+        // 13 fields of 8 hex digits after the magic: mode is the 2nd, the file size the 7th, the name size the 12th
+        let field = |index: usize| {
+            let start = 6 + index * 8;
+            std::str::from_utf8(&header[start..start + 8]).ok().and_then(|hex| usize::from_str_radix(hex, 16).ok()).ok_or_else(corrupt)
+        };
+        let (mode, size, name_size) = (field(1)?, field(6)?, field(11)?);
+        let name_end = (pos + HEADER).checked_add(name_size).filter(|end| *end <= data.len()).ok_or_else(corrupt)?;
+        let name = String::from_utf8_lossy(&data[pos + HEADER..name_end]).trim_end_matches('\0').to_string();
+        if name == "TRAILER!!!" {
+            break;
+        }
+        let start = name_end.next_multiple_of(4);
+        let end = start.checked_add(size).filter(|end| *end <= data.len()).ok_or_else(corrupt)?;
+        if mode & 0o170000 == 0o100000 {
+            files.push((name, &data[start..end]));
+        }
+        pos = end.next_multiple_of(4);
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use super::*;
+
+    fn tar(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(vec![]);
+        for (path, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o755);
+            builder.append_data(&mut header, path, *content).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(vec![]));
+        for (path, content) in files {
+            writer.start_file(*path, zip::write::SimpleFileOptions::default()).unwrap();
+            writer.write_all(content).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn xz(data: &[u8]) -> Vec<u8> {
+        let mut encoder = xz2::write::XzEncoder::new(vec![], 6);
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// A .deb: an ar archive of `debian-binary`, `control.tar.gz` and `data.tar.xz`
+    fn deb(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut ar = b"!<arch>\n".to_vec();
+        for (name, content) in [("debian-binary", b"2.0\n".to_vec()), ("control.tar.gz", gzip(&tar(&[("./control", b"Package: x")]))), ("data.tar.xz", xz(&tar(files)))] {
+            ar.extend(format!("{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n", name, 0, 0, 0, 100644, content.len()).as_bytes());
+            ar.extend(&content);
+            if content.len() % 2 == 1 {
+                ar.push(b'\n');
+            }
+        }
+        ar
+    }
+
+    fn unpacked(name: &str, data: &[u8], limits: &ArchiveLimits) -> (Vec<String>, Result<(), ArchiveError>) {
+        let mut paths = vec![];
+        let result = unpack(name, data, limits, &mut |path, _| paths.push(path.to_string()));
+        (paths, result)
+    }
+
+    #[test]
+    fn unpacks_nested_archives_within_limits() {
+        let inner = gzip(&tar(&[("./bin/evil", b"\x7fELF"), ("README", b"hi")]));
+        let outer = zip(&[("pkg/inner.tar.gz", &inner), ("notes.txt", b"text")]);
+        let limits = ArchiveLimits::default();
+
+        let (paths, result) = unpacked("outer.zip", &outer, &limits);
+        result.unwrap();
+        assert_eq!(paths, ["pkg/inner.tar.gz", "pkg/inner.tar.gz!bin/evil", "pkg/inner.tar.gz!README", "notes.txt"]);
+
+        let (paths, _) = unpacked("outer.zip", &outer, &ArchiveLimits { max_depth: 1, ..limits });
+        assert_eq!(paths, ["pkg/inner.tar.gz", "notes.txt"]);
+        assert_eq!(unpacked("x.gz", &gzip(b"plain"), &limits).0, ["x"]);
+
+        let bomb = gzip(&vec![0; 4 << 20]);
+        assert!(matches!(unpacked("bomb.gz", &bomb, &limits).1, Err(ArchiveError::Ratio(100))));
+        assert!(matches!(unpacked("bomb.gz", &bomb, &ArchiveLimits { max_size: 1 << 20, ..limits }).1, Err(ArchiveError::TooLarge(_))));
+        let (paths, result) = unpacked("outer.zip", &outer, &ArchiveLimits { max_entries: 2, ..limits });
+        assert_eq!(paths.len(), 2);
+        assert!(matches!(result, Err(ArchiveError::TooManyEntries(2))));
+    }
+
+    #[test]
+    fn compressed_streams_dont_count_as_containers() {
+        let outer = zip(&[("pool/tool.deb", &deb(&[("./usr/bin/tool", b"\x7fELF")]))]);
+        // the zip, the .deb and the tar in data.tar.xz
+        let (paths, result) = unpacked("outer.zip", &outer, &ArchiveLimits::default());
+        result.unwrap();
+        assert_eq!(paths, [
+            "pool/tool.deb",
+            "pool/tool.deb!debian-binary",
+            "pool/tool.deb!control.tar.gz",
+            "pool/tool.deb!control.tar.gz!control",
+            "pool/tool.deb!data.tar.xz",
+            "pool/tool.deb!data.tar.xz!usr/bin/tool",
+        ]);
+        let (paths, _) = unpacked("outer.zip", &outer, &ArchiveLimits { max_depth: 2, ..ArchiveLimits::default() });
+        assert_eq!(paths.len(), 4);
+
+        // a stream in a stream is another level
+        let twice = gzip(&gzip(&tar(&[("evil", b"\x7fELF")])));
+        assert_eq!(unpacked("x.tar.gz.gz", &twice, &ArchiveLimits { max_depth: 1, ..ArchiveLimits::default() }).0, ["x.tar.gz"]);
+        assert_eq!(unpacked("x.tar.gz.gz", &twice, &ArchiveLimits { max_depth: 2, ..ArchiveLimits::default() }).0, ["evil"]);
+    }
+}
@@ -0,0 +1,243 @@
+use std::{fs::Metadata, os::unix::fs::MetadataExt, sync::{Arc, Mutex, atomic::{AtomicUsize, Ordering}}};
+use chrono::Local;
+use rusqlite::{Connection, OptionalExtension};
+
+use super::{FileSignature, LibraryTraits, ScriptKind};
+
+/// Identifies a file without reading it. Any write changes mtime or ctime,
+/// a file replaced by another one gets another inode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileKey {
+    pub dev: u64,
+    pub inode: u64,
+    pub size: u64,
+    /// Nanoseconds since the epoch
+    pub mtime: i64,
+    /// Nanoseconds since the epoch
+    pub ctime: i64,
+}
+
+impl From<&Metadata> for FileKey {
+    fn from(metadata: &Metadata) -> Self {
+        Self {
+            dev: metadata.dev(),
+            inode: metadata.ino(),
+            size: metadata.size(),
+            mtime: metadata.mtime() * 1_000_000_000 + metadata.mtime_nsec(),
+            ctime: metadata.ctime() * 1_000_000_000 + metadata.ctime_nsec(),
+        }
+    }
+}
+
+/// What the models and the rules made of a file last time. The verdict itself is rebuilt from it,
+/// so a cached file still follows the thresholds of the current `--safety-aggressiveness`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedScan {
+    /// None for files that aren't executables
+    pub signature: Option<FileSignature>,
+    pub model: String,
+    pub score: Option<f32>,
+    pub entropy: Option<f32>,
+    pub is_malware: bool,
+    /// YARA rules that matched
+    pub rules: Vec<String>,
+    /// ClamAV body signatures that matched
+    pub body_signatures: Vec<String>,
+    /// Left empty for anything but libraries
+    pub library: LibraryTraits,
+}
+
+/// The `scan_cache` table of `scanner.db`, shared by the worker threads.
+/// Rows of other model versions are dropped when it's opened, and new rows are only written by `flush`.
+/// Opened read-only, as anyone but root gets it, it's only looked up: other users' scans never make it into root's cache.
+pub struct ScanCache {
+    db: Arc<Mutex<Connection>>,
+    read_only: bool,
+    model_version: String,
+    pending: Mutex<Vec<(FileKey, String, CachedScan)>>,
+    hits: AtomicUsize,
+}
+
+const COLUMNS: &str = "signature, model, score, entropy, is_malware, rules, body_signatures, constructor, interposed";
+
+impl ScanCache {
+    pub fn open(conn: Connection, model_version: &str) -> rusqlite::Result<Self> {
+        Self::open_shared(Arc::new(Mutex::new(conn)), model_version)
+    }
+
+    /// On the connection a scan shares with its hash reputation store
+    pub fn open_shared(db: Arc<Mutex<Connection>>, model_version: &str) -> rusqlite::Result<Self> {
+        let read_only = {
+            let conn = db.lock().unwrap();
+            let read_only = conn.is_readonly(rusqlite::MAIN_DB)?;
+            if !read_only {
+                conn.execute("DELETE FROM scan_cache WHERE model_version != $1", [model_version])?;
+            }
+            read_only
+        };
+        Ok(Self {
+            db,
+            read_only,
+            model_version: model_version.to_string(),
+            pending: Mutex::new(vec![]),
+            hits: AtomicUsize::new(0),
+        })
+    }
+
+    /// The file is unchanged since it was scanned, with the SHA-256 it had then
+    pub fn get(&self, key: &FileKey) -> Option<(String, CachedScan)> {
+        let cached = self.db.lock().unwrap().query_row(
+            &format!("SELECT {COLUMNS}, sha256 FROM scan_cache
+                    WHERE dev = $1 AND inode = $2 AND size = $3 AND mtime = $4 AND ctime = $5 AND model_version = $6"),
+            rusqlite::params![key.dev as i64, key.inode as i64, key.size as i64, key.mtime, key.ctime, self.model_version],
+            |row| Ok((row.get(9)?, from_row(row)?)),
+        ).optional().ok()??;
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(cached)
+    }
+
+    /// The same bytes were scanned before, under another name or before a `touch`
+    pub fn get_by_hash(&self, sha256: &str) -> Option<CachedScan> {
+        let cached = self.db.lock().unwrap().query_row(
+            &format!("SELECT {COLUMNS} FROM scan_cache WHERE sha256 = $1 AND model_version = $2 LIMIT 1"),
+            rusqlite::params![sha256, self.model_version],
+            from_row,
+        ).optional().ok()??;
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(cached)
+    }
+
+    /// Kept until `flush`, one transaction per scan instead of one per file
+    pub fn insert(&self, key: FileKey, sha256: String, scan: CachedScan) {
+        self.pending.lock().unwrap().push((key, sha256, scan));
+    }
+
+    pub fn flush(&self) -> rusqlite::Result<()> {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        if self.read_only {
+            return Ok(());
+        }
+        let mut db = self.db.lock().unwrap();
+        let tx = db.transaction()?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT OR REPLACE INTO scan_cache
+                        (dev, inode, size, mtime, ctime, sha256, model_version, signature, model, score, entropy, is_malware, rules, body_signatures,
+                        constructor, interposed, scanned_date)
+                        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17)",
+            )?;
+            let scanned_date = Local::now().to_rfc3339();
+            for (key, sha256, scan) in &pending {
+                stmt.execute(rusqlite::params![
+                    key.dev as i64,
+                    key.inode as i64,
+                    key.size as i64,
+                    key.mtime,
+                    key.ctime,
+                    sha256,
+                    self.model_version,
+                    scan.signature.map(FileSignature::as_str),
+                    scan.model,
+                    scan.score,
+                    scan.entropy,
+                    scan.is_malware,
+                    scan.rules.join(","),
+                    scan.body_signatures.join("\n"),
+                    scan.library.constructor,
+                    scan.library.interposed.join(","),
+                    scanned_date,
+                ])?;
+            }
+        }
+        tx.commit()
+    }
+
+    /// How many files were answered from the cache so far
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+}
+
+fn from_row(row: &rusqlite::Row) -> rusqlite::Result<CachedScan> {
+    Ok(CachedScan {
+        signature: row.get::<_, Option<String>>(0)?.as_deref().and_then(parse_signature),
+        model: row.get(1)?,
+        score: row.get(2)?,
+        entropy: row.get(3)?,
+        is_malware: row.get(4)?,
+        // rule names are identifiers, they can't contain a comma. Signature names can, but not a newline.
+        rules: row.get::<_, String>(5)?.split(',').filter(|rule| !rule.is_empty()).map(str::to_string).collect(),
+        body_signatures: row.get::<_, String>(6)?.lines().map(str::to_string).collect(),
+        library: LibraryTraits {
+            constructor: row.get(7)?,
+            interposed: row.get::<_, String>(8)?.split(',').filter(|name| !name.is_empty()).map(str::to_string).collect(),
+        },
+    })
+}
+
+fn parse_signature(name: &str) -> Option<FileSignature> {
+    match name {
+        "exe" => Some(FileSignature::Exe),
+        "elf" => Some(FileSignature::Elf),
+        "so" => Some(FileSignature::So),
+        "dll" => Some(FileSignature::Dll),
+        "macho" => Some(FileSignature::MachO),
+        _ => ScriptKind::from_name(name).map(FileSignature::Script),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache(conn: Connection, model_version: &str) -> ScanCache {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS scan_cache (
+                    dev INTEGER NOT NULL, inode INTEGER NOT NULL, size INTEGER NOT NULL, mtime INTEGER NOT NULL, ctime INTEGER NOT NULL,
+                    sha256 TEXT NOT NULL, model_version TEXT NOT NULL, signature TEXT, model TEXT NOT NULL,
+                    score REAL, entropy REAL, is_malware INTEGER NOT NULL, rules TEXT NOT NULL DEFAULT '', body_signatures TEXT NOT NULL DEFAULT '',
+                    constructor INTEGER NOT NULL DEFAULT 0, interposed TEXT NOT NULL DEFAULT '', scanned_date TEXT NOT NULL,
+                    PRIMARY KEY (dev, inode)
+                )",
+            [],
+        ).unwrap();
+        ScanCache::open(conn, model_version).unwrap()
+    }
+
+    #[test]
+    fn hits_unchanged_files_until_the_model_changes() {
+        let db = std::env::temp_dir().join(format!("sentinel_scan_cache_{}.db", std::process::id()));
+        let key = FileKey { dev: 1, inode: 2, size: 3, mtime: 4, ctime: 5 };
+        let scan = CachedScan {
+            signature: Some(FileSignature::So),
+            model: "elf".to_string(),
+            score: Some(0.5),
+            entropy: Some(6.0),
+            is_malware: true,
+            rules: vec!["upx".to_string()],
+            body_signatures: vec![],
+            library: LibraryTraits { constructor: true, interposed: vec!["readdir".to_string(), "open".to_string()] },
+        };
+
+        let old = cache(Connection::open(&db).unwrap(), "v1");
+        old.insert(key, "abc".to_string(), scan.clone());
+        assert_eq!(old.get(&key), None);
+        old.flush().unwrap();
+        assert_eq!(old.get(&key), Some(("abc".to_string(), scan.clone())));
+        assert_eq!(old.get(&FileKey { mtime: 40, ..key }), None);
+        assert_eq!(old.get_by_hash("abc"), Some(scan.clone()));
+        assert_eq!(old.hits(), 2);
+
+        // read-only, it neither drops the other version's rows nor writes its own
+        let read_only = ScanCache::open(Connection::open_with_flags(&db, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY).unwrap(), "v2").unwrap();
+        read_only.insert(FileKey { inode: 20, ..key }, "def".to_string(), scan);
+        read_only.flush().unwrap();
+        assert!(old.get(&key).is_some());
+        assert_eq!(old.get(&FileKey { inode: 20, ..key }), None);
+
+        let new = cache(Connection::open(&db).unwrap(), "v2");
+        assert_eq!(new.get(&key), None);
+        assert_eq!(cache(Connection::open(&db).unwrap(), "v1").get(&key), None);
+        std::fs::remove_file(&db).unwrap();
+    }
+}
@@ -0,0 +1,106 @@
+use std::path::Path;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+
+/// `--include` / `--exclude` globs plus the stored exclusions.
+///
+/// Patterns without a `/` match any file or directory name (`node_modules`, `*.vmdk`),
+/// patterns with one match the whole path (`/srv/vendor/**`, relative ones anywhere below the scan).
+/// A trailing `/` is ignored, so `target/` works like it does in a .gitignore.
+#[derive(Debug, Clone)]
+pub struct PathFilter {
+    include: Globs,
+    exclude: Globs,
+}
+
+#[derive(Debug, Clone)]
+struct Globs {
+    names: GlobSet,
+    paths: GlobSet,
+}
+
+impl Globs {
+    fn new(patterns: &[String]) -> Result<Self, globset::Error> {
+        let mut names = GlobSetBuilder::new();
+        let mut paths = GlobSetBuilder::new();
+
+        for pattern in patterns {
+            let pattern = pattern.trim_end_matches('/');
+            if !pattern.contains('/') {
+                names.add(GlobBuilder::new(pattern).build()?);
+            } else if pattern.starts_with('/') {
+                paths.add(GlobBuilder::new(pattern).literal_separator(true).build()?);
+            } else {
+                paths.add(GlobBuilder::new(&format!("**/{pattern}")).literal_separator(true).build()?);
+            }
+        }
+
+        Ok(Self {
+            names: names.build()?,
+            paths: paths.build()?,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.names.is_empty() && self.paths.is_empty()
+    }
+
+    fn is_match(&self, path: &Path) -> bool {
+        path.file_name().is_some_and(|name| self.names.is_match(name)) || self.paths.is_match(path)
+    }
+}
+
+impl PathFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self, globset::Error> {
+        Ok(Self {
+            include: Globs::new(include)?,
+            exclude: Globs::new(exclude)?,
+        })
+    }
+
+    /// Excluded directories are pruned, their content is never walked
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        self.exclude.is_match(path)
+    }
+
+    /// Without any include pattern every file is included. Only applies to files,
+    /// directories are always walked so their content can still match.
+    pub fn is_included(&self, path: &Path) -> bool {
+        self.include.is_empty() || self.include.is_match(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(include: &[&str], exclude: &[&str]) -> PathFilter {
+        let strings = |patterns: &[&str]| patterns.iter().map(|p| p.to_string()).collect::<Vec<String>>();
+        PathFilter::new(&strings(include), &strings(exclude)).unwrap()
+    }
+
+    #[test]
+    fn name_and_path_patterns() {
+        let filter = filter(&[], &["target/", "*.vmdk", "/opt/vendor/*", "cache/tmp"]);
+        assert!(filter.is_excluded(Path::new("/home/me/project/target")));
+        assert!(filter.is_excluded(Path::new("/var/vm/disk.vmdk")));
+        assert!(filter.is_excluded(Path::new("/opt/vendor/tool")));
+        assert!(!filter.is_excluded(Path::new("/opt/vendor/tool/bin")));
+        assert!(filter.is_excluded(Path::new("/home/me/cache/tmp")));
+        assert!(!filter.is_excluded(Path::new("/home/me/project/src/target.rs")));
+    }
+
+    #[test]
+    fn includes_default_to_everything() {
+        assert!(filter(&[], &[]).is_included(Path::new("/bin/ls")));
+
+        let filter = filter(&["*.exe", "/usr/local/bin/**"], &[]);
+        assert!(filter.is_included(Path::new("/mnt/share/setup.exe")));
+        assert!(filter.is_included(Path::new("/usr/local/bin/tools/run")));
+        assert!(!filter.is_included(Path::new("/usr/bin/ls")));
+    }
+
+    #[test]
+    fn rejects_invalid_globs() {
+        assert!(PathFilter::new(&[], &["[unclosed".to_string()]).is_err());
+    }
+}
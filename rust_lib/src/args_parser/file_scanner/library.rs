@@ -0,0 +1,138 @@
+// What sets a malicious shared library apart from the thousands of harmless ones: code that runs as soon as
+// it's loaded, and libc functions it replaces for the whole process. Together they're an LD_PRELOAD rootkit.
+use std::{fs, os::unix::fs::MetadataExt, path::Path};
+use goblin::{Object, elf::{Elf, section_header::SHN_UNDEF, sym}, pe::PE};
+
+/// libc functions rootkits and credential stealers hook to hide files, processes and connections or to grab passwords
+const HOOKED_LIBC_FUNCTIONS: [&str; 34] = [
+    "readdir", "readdir64", "opendir", "fopen", "fopen64", "open", "open64", "openat", "stat", "stat64", "lstat", "lstat64",
+    "__xstat", "__xstat64", "__lxstat", "__lxstat64", "fstatat", "access", "unlink", "unlinkat", "rename", "execve",
+    "kill", "accept", "connect", "recvfrom", "read", "write", "fgets", "getpwnam", "getspnam", "crypt",
+    "pam_authenticate", "pcap_loop",
+];
+
+/// File names of the libraries that legitimately define those: libc itself, PAM and the sanitizer runtimes
+const INTERPOSING_LIBRARIES: [&str; 9] = [
+    "libc.so", "libpthread.so", "librt.so", "ld-linux", "libpam.so", "libasan.so", "libtsan.so", "liblsan.so", "libfakeroot",
+];
+
+/// Where the system installs them, only root writes there
+const SYSTEM_LIBRARY_DIRS: [&str; 6] = ["/lib", "/lib32", "/lib64", "/usr/lib", "/usr/lib32", "/usr/lib64"];
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LibraryTraits {
+    /// Runs its own code when loaded: `.init_array` entries besides the compiler's or `.ctors` for ELF libraries,
+    /// TLS callbacks for DLLs
+    pub constructor: bool,
+    /// libc functions it exports while resolving the originals with `dlsym`, so every call goes through it first
+    pub interposed: Vec<String>,
+}
+
+impl LibraryTraits {
+    /// Empty for anything that isn't a library. `path` is where `data` was read from, it tells the system's
+    /// own interposing libraries apart
+    pub fn of(path: &Path, data: &[u8]) -> Self {
+        match Object::parse(data) {
+            Ok(Object::Elf(elf)) if elf.is_lib => Self::elf(&elf, path),
+            Ok(Object::PE(pe)) if pe.is_lib => Self::pe(&pe),
+            _ => Self::default(),
+        }
+    }
+
+    fn elf(elf: &Elf, path: &Path) -> Self {
+        let pointer_size = if elf.is_64 { 8 } else { 4 };
+        let init_array = elf.dynamic.as_ref().map_or(0, |dynamic| dynamic.info.init_arraysz / pointer_size);
+        // .ctors starts with -1 and ends with 0, modern toolchains leave it out altogether
+        let ctors = elf.section_headers.iter()
+            .find(|section| elf.shdr_strtab.get_at(section.sh_name) == Some(".ctors"))
+            .map_or(0, |section| (section.sh_size as usize / pointer_size).saturating_sub(2));
+        // the first .init_array entry is frame_dummy, gcc and clang always put it there
+        let constructor = init_array > 1 || ctors > 0;
+
+        if is_system_interposer(path) {
+            return Self { constructor, interposed: vec![] };
+        }
+        let symbols = elf.dynsyms.iter()
+            .filter(|symbol| symbol.is_function() && matches!(symbol.st_bind(), sym::STB_GLOBAL | sym::STB_WEAK))
+            .filter_map(|symbol| Some((elf.dynstrtab.get_at(symbol.st_name)?, symbol.st_shndx == SHN_UNDEF as usize)))
+            .collect::<Vec<(&str, bool)>>();
+        let resolves_originals = symbols.iter().any(|(name, undefined)| *undefined && matches!(*name, "dlsym" | "dlvsym"));
+        let interposed = match resolves_originals {
+            true => symbols.iter()
+                .filter(|(name, undefined)| !undefined && HOOKED_LIBC_FUNCTIONS.contains(name))
+                .map(|(name, _)| name.to_string())
+                .collect(),
+            false => vec![],
+        };
+        Self { constructor, interposed }
+    }
+
+    fn pe(pe: &PE) -> Self {
+        Self {
+            constructor: pe.tls_data.as_ref().is_some_and(|tls| !tls.callbacks.is_empty()),
+            interposed: vec![],
+        }
+    }
+}
+
+/// One of `INTERPOSING_LIBRARIES`, really installed in a system library directory. The soname is whatever
+/// the library says it is, and anyone can name a file `libc.so.6` in their home.
+fn is_system_interposer(path: &Path) -> bool {
+    let Ok(real) = path.canonicalize() else { return false };
+    let named = real.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| INTERPOSING_LIBRARIES.iter().any(|library| name.starts_with(library)));
+    let root_only = |path: &Path| fs::metadata(path).is_ok_and(|metadata| metadata.uid() == 0 && metadata.mode() & 0o022 == 0);
+    named && SYSTEM_LIBRARY_DIRS.iter().any(|dir| real.starts_with(dir)) && real.ancestors().all(root_only)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, process::Command};
+    use super::*;
+
+    /// `source` built as a shared library named `name`
+    fn shared_library(dir: &Path, name: &str, source: &str) -> PathBuf {
+        let (source_path, library) = (dir.join(format!("{name}.c")), dir.join(name));
+        fs::write(&source_path, source).unwrap();
+        let status = Command::new("cc")
+            .args(["-shared", "-fPIC", "-o"])
+            .arg(&library)
+            .arg(&source_path)
+            .args(["-ldl", &format!("-Wl,-soname,{name}")])
+            .status()
+            .expect("Couldn't run cc");
+        assert!(status.success());
+        library
+    }
+
+    #[test]
+    fn finds_constructors_and_libc_interposition() {
+        let dir = std::env::temp_dir().join(format!("sentinel_library_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let hook = "#define _GNU_SOURCE
+            #include <dlfcn.h>
+            #include <dirent.h>
+            __attribute__((constructor)) static void init(void) {}
+            struct dirent *readdir(DIR *dir) {
+                struct dirent *(*original)(DIR *) = (struct dirent *(*)(DIR *)) dlsym(RTLD_NEXT, \"readdir\");
+                return original(dir);
+            }";
+
+        let plain = shared_library(&dir, "libplain.so", "int answer(void) { return 42; }");
+        assert_eq!(LibraryTraits::of(&plain, &fs::read(&plain).unwrap()), LibraryTraits::default());
+
+        let rootkit = shared_library(&dir, "librootkit.so", hook);
+        let expected = LibraryTraits { constructor: true, interposed: vec!["readdir".to_string()] };
+        assert_eq!(LibraryTraits::of(&rootkit, &fs::read(&rootkit).unwrap()), expected);
+        // naming itself after libc doesn't help outside the system library directories
+        let disguised = shared_library(&dir, "libc.so.6", hook);
+        assert_eq!(LibraryTraits::of(&disguised, &fs::read(&disguised).unwrap()), expected);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let maps = fs::read_to_string("/proc/self/maps").unwrap();
+        let libc = maps.split_whitespace().find(|field| field.starts_with('/') && field.contains("/libc.so")).unwrap();
+        assert!(is_system_interposer(Path::new(libc)));
+        assert!(!is_system_interposer(&disguised));
+    }
+}
@@ -1,14 +1,50 @@
+mod archive;
+mod cache;
+mod filter;
+mod library;
+mod pipeline;
+mod report;
+mod response;
+mod script;
+
+pub use archive::{ArchiveError, ArchiveLimits, Container};
+pub use cache::{CachedScan, FileKey, ScanCache};
+pub use filter::PathFilter;
+pub use library::LibraryTraits;
+pub use report::{ReportFormat, ScanSummary};
+pub use response::{QuarantineMode, Responder, Response, ResponseAction};
+pub use script::ScriptKind;
+use pipeline::ReadBudget;
+
 use crate::args_parser::Commands::ScanDir;
 use crate::args_parser::Args;
+use crate::args_parser::quarantine::Quarantinizer;
+use crate::args_parser::reputation::{FileHashes, HashReputation, HashVerdict, KnownHash};
+use crate::args_parser::signatures::BodySignatures;
+use crate::palette;
+use crate::rules::RuleSet;
+#[cfg(not(feature = "ffi"))]
+use crate::{features::{FeatureError, FeatureVector}, xgboost::{ModelError, ModelRegistry}};
+use chrono::Local;
 use clap::Subcommand;
-use goblin::Object;
+use goblin::{Object, mach::Mach};
+use rusqlite::Connection;
+#[cfg(feature = "ffi")]
+use sha2::{Digest, Sha256};
+#[cfg(feature = "ffi")]
 use std::ffi::CString;
-use std::fs::{self};
+use std::fmt;
+use std::fs::{self, File};
+#[cfg(feature = "ffi")]
 use std::os::raw::c_char;
-use std::panic;
+#[cfg(feature = "ffi")]
+use std::os::unix::{ffi::OsStringExt, fs::OpenOptionsExt};
 use std::path::Path;
-use std::{env::home_dir, io::{self}, path::PathBuf};
+use std::{env::home_dir, io::{self, Read, Write}, path::PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
+#[cfg(feature = "ffi")]
 #[link(name = "lief_wrapper")]
 #[link(name = "predict")]
 #[link(name = "xgboost")]
@@ -19,6 +55,39 @@ unsafe extern "C" {
     fn predict_malware_pe(filepath: *const c_char, model_path: *const c_char, show_pred: bool) -> bool;
 }
 
+/// Where `make install` puts the models, the C predictors load them from files
+#[cfg(feature = "ffi")]
+const MODEL_DIR: &str = "/usr/local/share/sentinel/model";
+
+/// How much of a file is read to tell whether it's worth reading whole: enough for a shebang line and a tar header
+const SNIFF_LEN: usize = 4096;
+
+/// How many bytes of file contents the workers read into memory at once, all of them together.
+/// Raised to `--max-file-size` if that's bigger, so the biggest file still fits.
+const READ_BUDGET: u64 = 1 << 30;
+
+/// ELF, PE, then Mach-O in both byte orders, 32 and 64-bit, and universal binaries
+const EXECUTABLE_MAGIC: [&[u8]; 8] = [
+    b"\x7fELF", b"MZ",
+    &[0xfe, 0xed, 0xfa, 0xce], &[0xfe, 0xed, 0xfa, 0xcf], &[0xce, 0xfa, 0xed, 0xfe], &[0xcf, 0xfa, 0xed, 0xfe],
+    &[0xca, 0xfe, 0xba, 0xbe], &[0xca, 0xfe, 0xba, 0xbf],
+];
+
+/// Java class files start like universal binaries, with a version where the architecture count goes
+const MAX_FAT_ARCHES: usize = 20;
+
+/// Kernel pseudo filesystems, nothing in there is an executable on disk
+/// and reading some of their files blocks or never ends
+const PSEUDO_FILESYSTEMS: [&str; 3] = ["/proc", "/sys", "/dev"];
+
+/// Global entropy above which an executable is almost certainly packed or encrypted
+#[cfg(not(feature = "ffi"))]
+const PACKED_ENTROPY: f32 = 7.2;
+
+/// Part of the cache version, bumped whenever files that used to be skipped get classified,
+/// so their cached "not an executable" goes away
+const CLASSIFIER_VERSION: u32 = 3;
+
 #[derive(Debug, Clone, Copy)]
 pub enum Aggressiveness {
     Chill,
@@ -47,7 +116,9 @@ impl std::str::FromStr for Aggressiveness {
 
 #[derive(Debug, Clone, Copy)]
 pub enum Colorblindness {
-    Protanopia
+    Protanopia,
+    Deuteranopia,
+    Tritanopia,
 }
 
 impl std::str::FromStr for Colorblindness {
@@ -56,9 +127,72 @@ impl std::str::FromStr for Colorblindness {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s.to_lowercase().as_str() {
             "protanopia" => Ok(Self::Protanopia),
+            "deuteranopia" => Ok(Self::Deuteranopia),
+            "tritanopia" => Ok(Self::Tritanopia),
             _ => Err(
                 format!("Invalid colorblindness: {s}.
-                    Use [Protanopia]"))
+                    Use [Protanopia, Deuteranopia, Tritanopia]"))
+        }
+    }
+}
+
+/// What `--safety-aggressiveness` turns into: how sure the models have to be,
+/// and which heuristics run on top of them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SafetyPolicy {
+    pub elf_threshold: f32,
+    pub pe_threshold: f32,
+    /// Scripts are scored by their indicators, see `ScriptKind::score`
+    pub script_threshold: f32,
+    /// Flag packed or encrypted executables no matter what the model says
+    pub entropy_heuristic: bool,
+    /// Flag libraries hooking libc functions, the way LD_PRELOAD rootkits do
+    pub interposition_heuristic: bool,
+    /// Flag libraries running code as soon as they're loaded, so are plenty of C++ libraries
+    pub constructor_heuristic: bool,
+}
+
+impl From<Aggressiveness> for SafetyPolicy {
+    fn from(aggressiveness: Aggressiveness) -> Self {
+        // normal keeps the thresholds the C predictors had hard-coded
+        let (elf_threshold, pe_threshold, script_threshold, entropy_heuristic, interposition_heuristic, constructor_heuristic) = match aggressiveness {
+            Aggressiveness::Chill => (0.8, 0.6, 0.9, false, false, false),
+            Aggressiveness::Cautious => (0.65, 0.4, 0.8, false, false, false),
+            Aggressiveness::Normal => (0.49, 0.2, 0.6, false, true, false),
+            Aggressiveness::Aggressive => (0.35, 0.12, 0.4, true, true, false),
+            Aggressiveness::Hardcore => (0.2, 0.05, 0.25, true, true, true),
+        };
+        Self { elf_threshold, pe_threshold, script_threshold, entropy_heuristic, interposition_heuristic, constructor_heuristic }
+    }
+}
+
+impl SafetyPolicy {
+    pub fn threshold(&self, signature: FileSignature) -> f32 {
+        match signature {
+            FileSignature::Exe | FileSignature::Dll => self.pe_threshold,
+            FileSignature::Elf | FileSignature::So | FileSignature::MachO => self.elf_threshold,
+            FileSignature::Script(_) => self.script_threshold,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Heuristic {
+    HighEntropy,
+    /// The libc functions a library hooks
+    Interposition(Vec<String>),
+    Constructor,
+    /// What a script does that made it score over the threshold
+    Script(Vec<String>),
+}
+
+impl fmt::Display for Heuristic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Heuristic::HighEntropy => write!(f, "high entropy (packed or encrypted)"),
+            Heuristic::Interposition(functions) => write!(f, "hooks libc {}", functions.join(", ")),
+            Heuristic::Constructor => write!(f, "runs code when loaded (constructor)"),
+            Heuristic::Script(indicators) => write!(f, "suspicious script: {}", indicators.join(", ")),
         }
     }
 }
@@ -71,113 +205,1091 @@ pub enum FileCommands {
 
         #[arg(short, long)]
         safety_aggressiveness: Aggressiveness,
-
-        #[arg(short, long)]
-        colorblindness: Option<Colorblindness>,
     }
 }
 
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WalkOptions {
+    /// `None` scans everything below the directory
+    pub max_depth: Option<usize>,
+    pub follow_symlinks: bool,
+    pub one_file_system: bool,
+}
+
 pub struct FileScanner {
     // args: Args,
     file: PathBuf,
     show_pred: bool,
-    // response_aggressiveness: Aggressiveness,
-    // safety_aggressiveness: Aggressiveness,
+    jobs: usize,
+    walk_options: WalkOptions,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    filter: PathFilter,
+    no_cache: bool,
+    analyzer: Analyzer,
+    responder: Responder,
+    /// `None` without `--report-format` or `--output`
+    report: Option<ReportFormat>,
+    /// Standard output when `None`
+    output: Option<PathBuf>,
+}
+
+/// The part of the scanner shared by the worker threads:
+/// signature check, feature extraction, prediction, heuristics, rules and body signatures
+struct Analyzer {
+    #[cfg(not(feature = "ffi"))]
+    models: ModelRegistry,
+    #[cfg(feature = "ffi")]
+    model_dir: PathBuf,
+    safety: SafetyPolicy,
+    #[cfg(feature = "ffi")]
+    show_pred: bool,
+    cache: Option<ScanCache>,
+    /// Never empty, looked up for every file
+    reputation: Option<HashReputation>,
+    /// Matched against every file, executable or not. Empty without `--rules`
+    rules: RuleSet,
+    /// Imported ClamAV signatures, matched against every file too
+    body_signatures: BodySignatures,
+    archive_limits: ArchiveLimits,
+    /// Bigger files are reported as skipped instead of being read
+    max_file_size: u64,
+    /// Shared by the workers, however many there are
+    read_budget: ReadBudget,
+    /// Hash every file even without hash lists or a cache, reports show it
+    always_hash: bool,
 }
 
 impl FileScanner {
     pub fn new(args: Args) -> Self {
         let commands = args.clone().command.unwrap();
-        let (file, show_pred) = match commands {
-            ScanDir { dir, show_pred, .. } => {
-                let dir = dir.unwrap_or_else(|| home_dir().expect("Couldn't get home directory"));
-                (dir, show_pred)
+        match commands {
+            ScanDir {
+                dir, show_pred, jobs, max_depth, follow_symlinks, one_file_system, include, exclude,
+                quarantine, dry_run, no_cache, rules, archive_depth, archive_max_size, archive_max_ratio,
+                model_dir, max_file_size, report_format, output, scan,
+            } => {
+                // without `scan`, keep the old behavior: normal thresholds and only reporting
+                let (safety, mut response) = match scan {
+                    Some(FileCommands::Scan { response_aggressiveness, safety_aggressiveness, .. }) => {
+                        (safety_aggressiveness.into(), response_aggressiveness.into())
+                    }
+                    None => (Aggressiveness::Normal.into(), ResponseAction::Report),
+                };
+                // --quarantine never asks, but still kills on hardcore
+                if quarantine.is_some() {
+                    response = response.max(ResponseAction::Quarantine);
+                }
+                let report = report_format
+                    .or_else(|| output.as_deref().and_then(ReportFormat::from_extension))
+                    .or(output.as_ref().map(|_| ReportFormat::Json));
+
+                Self {
+                    // args,
+                    file: dir.unwrap_or_else(|| home_dir().expect("Couldn't get home directory")),
+                    show_pred,
+                    jobs: jobs.map_or_else(num_cpus::get, |jobs| jobs.get()),
+                    walk_options: WalkOptions { max_depth, follow_symlinks, one_file_system },
+                    filter: PathFilter::new(&include, &exclude)
+                        .unwrap_or_else(|e| panic!("Invalid --include or --exclude pattern\nError: {e}")),
+                    include,
+                    exclude,
+                    no_cache,
+                    analyzer: Analyzer {
+                        #[cfg(not(feature = "ffi"))]
+                        models: match &model_dir {
+                            Some(model_dir) => ModelRegistry::load(model_dir)
+                                .unwrap_or_else(|e| panic!("Couldn't load the models from {model_dir:?}\nError: {e}")),
+                            None => ModelRegistry::embedded()
+                                .unwrap_or_else(|e| panic!("Couldn't load the built-in models\nError: {e}")),
+                        },
+                        #[cfg(feature = "ffi")]
+                        model_dir: model_dir.unwrap_or_else(|| PathBuf::from(MODEL_DIR)),
+                        safety,
+                        #[cfg(feature = "ffi")]
+                        show_pred,
+                        cache: None,
+                        reputation: None,
+                        rules: RuleSet::load(&rules)
+                            .unwrap_or_else(|e| panic!("Couldn't load the rules\nError: {e}")),
+                        body_signatures: BodySignatures::default(),
+                        archive_limits: ArchiveLimits {
+                            max_depth: archive_depth,
+                            max_size: archive_max_size,
+                            max_ratio: archive_max_ratio,
+                            ..ArchiveLimits::default()
+                        },
+                        max_file_size,
+                        read_budget: ReadBudget::new(max_file_size.max(READ_BUDGET)),
+                        always_hash: report.is_some(),
+                    },
+                    // a report on the standard output keeps it to itself
+                    responder: Responder::new(response, quarantine.unwrap_or_default(), dry_run, report.is_some() && output.is_none()),
+                    report,
+                    output,
+                }
             }
             _ => panic!("How did you even get here..?")
-        };
+        }
+    }
+
+    /// Adds the stored exclusions on top of the --exclude patterns
+    pub fn with_exclusions(mut self, patterns: Vec<String>) -> Result<Self, globset::Error> {
+        self.exclude.extend(patterns);
+        self.filter = PathFilter::new(&self.include, &self.exclude)?;
+        Ok(self)
+    }
+
+    /// Where detections go when the response aggressiveness asks for quarantine
+    pub fn with_quarantinizer(mut self, quarantinizer: Quarantinizer) -> Self {
+        self.responder.set_quarantinizer(quarantinizer);
+        self
+    }
+
+    /// Flags known bad hashes and skips known good ones before the models see them.
+    /// An empty store is left out, every file would be read and hashed for nothing.
+    pub fn with_reputation(mut self, reputation: HashReputation) -> Self {
+        self.analyzer.reputation = (!reputation.is_empty()).then_some(reputation);
+        self
+    }
+
+    /// Flags files matching an imported ClamAV body signature
+    pub fn with_signatures(mut self, body_signatures: BodySignatures) -> Self {
+        self.analyzer.body_signatures = body_signatures;
+        self
+    }
 
-        Self {
-            // args,
-            file,
-            show_pred,
-            // response_aggressiveness: Aggressiveness::Normal,
-            // safety_aggressiveness: Aggressiveness::Normal,
+    /// Skips files unchanged since they were last scanned with the same models, hash lists, rules and signatures, unless `--no-cache`.
+    /// Archives are opened again on every scan, only what's in them can be answered from the cache.
+    /// Goes after `with_reputation` and `with_signatures`, the cache is only valid for what it was filled with.
+    pub fn with_cache(mut self, db: Arc<Mutex<Connection>>) -> rusqlite::Result<Self> {
+        let Some(mut version) = self.analyzer.model_version() else { return Ok(self) };
+        if let Some(reputation) = &self.analyzer.reputation {
+            version = format!("{version}+{}", reputation.version()?);
+        }
+        if !self.analyzer.rules.is_empty() {
+            version = format!("{version}+{}", self.analyzer.rules.version());
+        }
+        if !self.analyzer.body_signatures.is_empty() {
+            version = format!("{version}+{}", self.analyzer.body_signatures.version());
+        }
+        version = format!("{version}+classifier{CLASSIFIER_VERSION}");
+        // archives were cached as plain files before they were opened
+        if self.analyzer.archive_limits.max_depth > 0 {
+            version = format!("{version}+archives");
         }
+        if !self.no_cache {
+            self.analyzer.cache = Some(ScanCache::open_shared(db, &version)?);
+        }
+        Ok(self)
     }
 
-    pub fn scan_files(&self) -> io::Result<()> {
-        println!("Scanning directory: {:?}", &self.file);
-        let mut malwares_count = 0;
-        for entry in walkdir::WalkDir::new(&self.file).max_depth(3) {
-            let entry = match entry {
-                Ok(file) => file,
-                Err(ref e) if e.io_error().is_some_and(|err| err.kind() == io::ErrorKind::PermissionDenied) => {
-                    eprintln!("Permission denied when accessing {:?}", e.path());
-                    continue;
+    /// Returns how many malwares were found
+    pub fn scan_files(&mut self) -> io::Result<usize> {
+        let palette = palette::current();
+        // a report on the standard output keeps it to itself
+        let mut console: Box<dyn Write> = match (self.report, &self.output) {
+            (Some(_), None) => Box::new(io::stderr()),
+            _ => Box::new(io::stdout()),
+        };
+        let (started, timer) = (Local::now(), Instant::now());
+        writeln!(console, "Scanning directory: {:?}", self.file)?;
+
+        let walker = walk(&self.file, self.walk_options, &self.filter);
+        let analyzer = &self.analyzer;
+        let responder = &mut self.responder;
+        let results = pipeline::run(walker, self.jobs, |path| analyzer.scan_file(path), |result| {
+            if responder.mode == QuarantineMode::Immediate
+                && let Some(reason) = result.reason()
+            {
+                responder.respond(&result.path, &reason, result.score(), result.killable());
+            }
+        });
+
+        let mut detections = vec![];
+        let mut failed_count = 0;
+        let mut skipped_count = 0;
+        for result in &results {
+            match &result.outcome {
+                FileOutcome::Scanned(verdict) => {
+                    if let (true, Some(score)) = (self.show_pred, verdict.score) {
+                        writeln!(console, "{:?} {}", result.location(), palette.info(&format!("Certainity: {score:.6}")))?;
+                    }
+                    if verdict.is_malware {
+                        let heuristics = verdict.known_bad.iter().map(|known| format!(" ({known})"))
+                            .chain(verdict.heuristics.iter().map(|h| format!(" ({h})")))
+                            .chain(verdict.rules.iter().map(|rule| format!(" (rule {rule})")))
+                            .chain(verdict.body_signatures.iter().map(|signature| format!(" (signature {signature})")))
+                            .collect::<String>();
+                        writeln!(console, "{}", palette.danger(&format!("{:?} is a malware{heuristics}", result.location())))?;
+                        detections.push(result);
+                    }
+                }
+                FileOutcome::Skipped(reason) => {
+                    writeln!(console, "{}", palette.warning(&format!("{:?} {reason}", result.location())))?;
+                    skipped_count += 1;
                 }
-                Err(e) => {
-                    eprintln!("An unexpected error occured: {e}");
-                    continue;
+                FileOutcome::Failed(e) => {
+                    eprintln!("{}", palette.warning(&e.to_string()));
+                    failed_count += 1;
                 }
-            };
-            let file_path = entry.path();
+            }
+        }
 
-            if !file_path.is_file() {
-                continue;
+        writeln!(console, "Scanning ended")?;
+        if let Some(cache) = &self.analyzer.cache {
+            if let Err(e) = cache.flush() {
+                eprintln!("{}", palette.warning(&format!("Couldn't update the scan cache: {e}")));
             }
+            writeln!(console, "{}", palette.info(&format!("Reused the verdicts of {} already scanned files", cache.hits())))?;
+        }
+        let found = format!("Found {} possible malwares.", detections.len());
+        writeln!(console, "{}", if detections.is_empty() { palette.safe(&found) } else { palette.danger(&found) })?;
+        if failed_count > 0 {
+            writeln!(console, "{}", palette.warning(&format!("Couldn't scan {failed_count} files.")))?;
+        }
+        if skipped_count > 0 {
+            writeln!(console, "{}", palette.warning(&format!("Skipped {skipped_count} files bigger than --max-file-size.")))?;
+        }
 
-            let c_file_path = CString::new(file_path.to_str().unwrap()).unwrap();
-            match check_file_signature(file_path) {
-                Some(FileSignature::Exe) => {
-                    let c_model_path = CString::new("model/exe/model.ubj").unwrap();
-                    let is_malware = unsafe {
-                        predict_malware_pe(c_file_path.as_ptr(), c_model_path.as_ptr(), self.show_pred)
-                    };
-                    handle_malware(file_path, is_malware, &mut malwares_count);
+        let found = detections.len();
+        // the report says what was done about them
+        if self.responder.mode == QuarantineMode::Batch {
+            for (result, reason) in detections.into_iter().filter_map(|result| Some((result, result.reason()?))) {
+                self.responder.respond(&result.path, &reason, result.score(), result.killable());
+            }
+            self.responder.finish();
+        }
+
+        if let Some(format) = self.report {
+            let cache_hits = self.analyzer.cache.as_ref().map_or(0, ScanCache::hits);
+            let summary = ScanSummary::new(&self.file, started, timer.elapsed(), &results, cache_hits);
+            let report = report::render(&results, &summary, self.responder.responses(), format);
+            match &self.output {
+                Some(output) => fs::write(output, report)
+                    .map_err(|e| io::Error::new(e.kind(), format!("Couldn't write the report to {:?}: {e}", output)))?,
+                None => io::stdout().write_all(report.as_bytes())?,
+            }
+        }
+
+        Ok(found)
+    }
+
+    /// Scans every file under the directory on `jobs` threads, sorted by path
+    pub fn scan(&self) -> Vec<FileResult> {
+        let walker = walk(&self.file, self.walk_options, &self.filter);
+        let analyzer = &self.analyzer;
+        let results = pipeline::run(walker, self.jobs, |path| analyzer.scan_file(path), |_| {});
+        if let Some(cache) = &analyzer.cache
+            && let Err(e) = cache.flush()
+        {
+            eprintln!("Couldn't update the scan cache: {e}");
+        }
+        results
+    }
+}
+
+/// The starting directory itself is always walked, even if it's excluded or a pseudo filesystem,
+/// but nothing in a pseudo filesystem is
+fn walk<'a>(root: &Path, options: WalkOptions, filter: &'a PathFilter) -> impl Iterator<Item = walkdir::Result<walkdir::DirEntry>> + Send + 'a {
+    // pseudo filesystems are told by where entries really are, `-d .` from `/` or through a symlink to `/` still skips them
+    let resolved_root = fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+    let given_root = root.to_path_buf();
+    let mut walker = walkdir::WalkDir::new(root)
+        .follow_links(options.follow_symlinks)
+        .same_file_system(options.one_file_system);
+    if let Some(max_depth) = options.max_depth {
+        walker = walker.max_depth(max_depth);
+    }
+
+    walker.into_iter().filter_entry(move |entry| {
+        if entry.depth() == 0 {
+            return true;
+        }
+        let path = entry.path();
+        let resolved = match options.follow_symlinks && entry.path_is_symlink() {
+            // followed symlinks land wherever they point
+            true => fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()),
+            false => resolved_root.join(path.strip_prefix(&given_root).unwrap_or(path)),
+        };
+        !is_pseudo_filesystem(&resolved)
+            && !filter.is_excluded(path)
+            && (entry.file_type().is_dir() || filter.is_included(path))
+    })
+}
+
+impl Analyzer {
+    /// Answers from the cache when the file (or the same bytes elsewhere) was already scanned,
+    /// otherwise analyzes it and remembers the result. Archives give a result for everything flagged in them.
+    /// Only the first bytes of files that can't be flagged are read, the others are read whole
+    /// once the read budget the workers share has room for them.
+    fn scan_file(&self, file_path: &Path) -> Vec<FileResult> {
+        let on_disk = |outcome: Option<FileOutcome>| {
+            outcome.map(|outcome| FileResult { path: file_path.to_path_buf(), entry: None, outcome }).into_iter().collect()
+        };
+        let io_failure = |e| on_disk(Some(FileOutcome::Failed(ScanError::Io(file_path.to_path_buf(), e))));
+        let key = match fs::metadata(file_path) {
+            Ok(metadata) => FileKey::from(&metadata),
+            Err(e) => return io_failure(e),
+        };
+        if let Some((sha256, cached)) = self.cache.as_ref().and_then(|cache| cache.get(&key)) {
+            return on_disk(self.cached_outcome(cached, Some(sha256)));
+        }
+
+        let file = match File::open(file_path) {
+            Ok(file) => file,
+            Err(e) => return io_failure(e),
+        };
+        let mut data = Vec::with_capacity(SNIFF_LEN);
+        if let Err(e) = (&file).take(SNIFF_LEN as u64).read_to_end(&mut data) {
+            return io_failure(e);
+        }
+        if !self.is_candidate(file_path, &data) {
+            return vec![];
+        }
+        if key.size > self.max_file_size {
+            return on_disk(Some(FileOutcome::Skipped(SkipReason::TooLarge(key.size))));
+        }
+
+        // read rather than mapped, a mapped file truncated while it's scanned brings the process down with SIGBUS
+        let _reservation = self.read_budget.reserve(key.size);
+        data.reserve(key.size.saturating_sub(data.len() as u64) as usize);
+        // one byte past the size it had, to tell it grew
+        if let Err(e) = (&file).take(key.size.saturating_sub(data.len() as u64) + 1).read_to_end(&mut data) {
+            return io_failure(e);
+        }
+        // the size is a feature, half a file or one still being written would be scored as something else
+        if data.len() as u64 != key.size {
+            return on_disk(Some(FileOutcome::Failed(ScanError::Changed(file_path.to_path_buf()))));
+        }
+        let data = data.as_slice();
+        if self.archive_limits.max_depth > 0 && Container::of(data).is_some() {
+            return self.scan_archive(file_path, data);
+        }
+        on_disk(self.analyze(file_path, data, Some(key)))
+    }
+
+    /// Whether anything could come out of reading the whole file, from its first `SNIFF_LEN` bytes.
+    /// Hash lists, rules and signatures apply to every file, without them only executables, scripts
+    /// and archives are worth it.
+    fn is_candidate(&self, file_path: &Path, prefix: &[u8]) -> bool {
+        self.reputation.is_some()
+            || !self.rules.is_empty()
+            || !self.body_signatures.is_empty()
+            || EXECUTABLE_MAGIC.iter().any(|magic| prefix.starts_with(magic))
+            || (self.archive_limits.max_depth > 0 && Container::of(prefix).is_some())
+            || ScriptKind::detect(file_path, prefix).is_some()
+    }
+
+    /// The archive itself goes through the hash lists, rules and signatures too, then everything in it.
+    /// Neither is remembered by file: the cache has no row for what's inside a file.
+    fn scan_archive(&self, file_path: &Path, data: &[u8]) -> Vec<FileResult> {
+        let result = |entry: Option<String>, outcome| FileResult { path: file_path.to_path_buf(), entry, outcome };
+        let mut results = self.analyze(file_path, data, None).map(|outcome| result(None, outcome)).into_iter().collect::<Vec<FileResult>>();
+
+        let name = file_path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+        let unpacked = archive::unpack(&name, data, &self.archive_limits, &mut |entry, content| {
+            let location = PathBuf::from(format!("{}!{entry}", file_path.display()));
+            if let Some(outcome) = self.analyze(&location, content, None) {
+                results.push(result(Some(entry.to_string()), outcome));
+            }
+        });
+        if let Err(e) = unpacked {
+            results.push(result(None, FileOutcome::Failed(ScanError::Archive(file_path.to_path_buf(), e))));
+        }
+        results
+    }
+
+    /// Hash lists, rules, signatures and models, in that order. Only remembered in the cache with a `key`,
+    /// but the same bytes scanned as another file are always answered from it.
+    fn analyze(&self, file_path: &Path, data: &[u8], key: Option<FileKey>) -> Option<FileOutcome> {
+        let hashes = match (&self.reputation, self.cache.is_some() || self.always_hash) {
+            (Some(reputation), _) => Some(reputation.hashes(data)),
+            (None, true) => Some(FileHashes::sha256(data)),
+            (None, false) => None,
+        };
+        let sha256 = hashes.as_ref().map(|hashes| hashes.sha256.clone());
+        let scanned = |verdict: Verdict| FileOutcome::Scanned(Verdict { sha256: sha256.clone(), ..verdict });
+        let remember = |scan: CachedScan| {
+            if let (Some(cache), Some(hashes), Some(key)) = (&self.cache, &hashes, key) {
+                cache.insert(key, hashes.sha256.clone(), scan);
+            }
+        };
+        let not_scanned = || CachedScan {
+            signature: None,
+            model: String::new(),
+            score: None,
+            entropy: None,
+            is_malware: false,
+            rules: vec![],
+            body_signatures: vec![],
+            library: LibraryTraits::default(),
+        };
+
+        if let (Some(reputation), Some(hashes)) = (&self.reputation, &hashes) {
+            match reputation.lookup(hashes) {
+                // known bad files aren't cached, they're flagged again on every scan
+                Ok(Some(known)) if known.verdict == HashVerdict::Bad => {
+                    return Some(scanned(self.known_bad(check_file_signature(file_path, data), known)));
+                }
+                Ok(Some(_)) => {
+                    remember(not_scanned());
+                    return None;
                 }
-                Some(FileSignature::Elf) => {
-                    let c_model_path = CString::new("model/elf/model.ubj").unwrap();
-                    let is_malware = unsafe {
-                        predict_malware_elf( c_file_path.as_ptr(), c_model_path.as_ptr(), self.show_pred)
-                    };
-                    handle_malware(file_path, is_malware, &mut malwares_count);
+                Ok(None) => {}
+                Err(e) => eprintln!("Couldn't look up {:?} in the hash reputation store: {e}", file_path),
+            }
+        }
+        if let (Some(cache), Some(hashes)) = (&self.cache, &hashes)
+            && let Some(cached) = cache.get_by_hash(&hashes.sha256)
+        {
+            remember(cached.clone());
+            return self.cached_outcome(cached, sha256);
+        }
+
+        let (rules, body_signatures) = (self.rules.scan(data), self.body_signatures.scan(data));
+        let Some(signature) = check_file_signature(file_path, data) else {
+            let verdict = self.unscored(None).with_matches(rules, body_signatures);
+            remember(CachedScan {
+                is_malware: verdict.is_malware,
+                rules: verdict.rules.clone(),
+                body_signatures: verdict.body_signatures.clone(),
+                ..not_scanned()
+            });
+            return verdict.is_malware.then(|| scanned(verdict));
+        };
+        // the indicators behind a script's score aren't kept in the cache, scripts are matched again every time
+        if let FileSignature::Script(kind) = signature {
+            return Some(scanned(self.script_verdict(kind, data).with_matches(rules, body_signatures)));
+        }
+        let library = match signature.is_library() {
+            true => LibraryTraits::of(file_path, data),
+            false => LibraryTraits::default(),
+        };
+        Some(match self.predict(file_path, data, signature, &library) {
+            Ok((verdict, entropy)) => {
+                let verdict = verdict.with_matches(rules, body_signatures);
+                remember(CachedScan {
+                    signature: Some(signature),
+                    model: verdict.model.clone(),
+                    score: verdict.score,
+                    entropy,
+                    is_malware: verdict.is_malware,
+                    rules: verdict.rules.clone(),
+                    body_signatures: verdict.body_signatures.clone(),
+                    library,
+                });
+                scanned(verdict)
+            }
+            Err(e) => FileOutcome::Failed(e),
+        })
+    }
+
+    fn script_verdict(&self, kind: ScriptKind, data: &[u8]) -> Verdict {
+        let (score, indicators) = kind.score(data);
+        let threshold = self.safety.threshold(FileSignature::Script(kind));
+        let is_malware = score > threshold;
+        Verdict {
+            model: format!("{} script indicators", kind.as_str()),
+            score: Some(score),
+            heuristics: if is_malware { vec![Heuristic::Script(indicators)] } else { vec![] },
+            is_malware,
+            ..self.unscored(Some(FileSignature::Script(kind)))
+        }
+    }
+
+    /// Flagged with certainty, the models aren't asked
+    fn known_bad(&self, signature: Option<FileSignature>, known: KnownHash) -> Verdict {
+        Verdict { known_bad: Some(known), is_malware: true, ..self.unscored(signature) }
+    }
+
+    /// A verdict the models had no say in
+    fn unscored(&self, signature: Option<FileSignature>) -> Verdict {
+        Verdict {
+            signature,
+            model: String::new(),
+            score: None,
+            threshold: signature.map_or(0.0, |signature| self.safety.threshold(signature)),
+            heuristics: vec![],
+            known_bad: None,
+            rules: vec![],
+            body_signatures: vec![],
+            is_malware: false,
+            sha256: None,
+        }
+    }
+
+    /// Non-executables were cached too, they stay unreported unless a rule or a signature matched them
+    fn cached_outcome(&self, cached: CachedScan, sha256: Option<String>) -> Option<FileOutcome> {
+        let Some(signature) = cached.signature else {
+            let verdict = Verdict { sha256, ..self.unscored(None).with_matches(cached.rules, cached.body_signatures) };
+            return verdict.is_malware.then_some(FileOutcome::Scanned(verdict));
+        };
+        #[cfg(not(feature = "ffi"))]
+        let verdict = self.verdict(signature, cached.model, cached.score, cached.entropy.unwrap_or_default(), &cached.library);
+        #[cfg(feature = "ffi")]
+        let verdict = self.ffi_verdict(signature, cached.model, cached.is_malware, &cached.library);
+        Some(FileOutcome::Scanned(Verdict { sha256, ..verdict.with_matches(cached.rules, cached.body_signatures) }))
+    }
+
+    /// Hashes the model files the way `ModelRegistry` does, `None` when there's nothing to tell versions apart with
+    #[cfg(not(feature = "ffi"))]
+    fn model_version(&self) -> Option<String> {
+        self.models.version().map(str::to_string)
+    }
+
+    #[cfg(feature = "ffi")]
+    fn model_version(&self) -> Option<String> {
+        let mut hasher = Sha256::new();
+        for signature in [FileSignature::Elf, FileSignature::Exe] {
+            hasher.update(fs::read(signature.model_path(&self.model_dir)?).ok()?);
+        }
+        if let Some(macho) = FileSignature::MachO.model_path(&self.model_dir) {
+            hasher.update(fs::read(macho).ok()?);
+        }
+        Some(hex::encode(hasher.finalize()))
+    }
+
+    /// The verdict and the global entropy, kept so the entropy heuristic can be re-evaluated from the cache
+    #[cfg(not(feature = "ffi"))]
+    fn predict(&self, file_path: &Path, data: &[u8], signature: FileSignature, library: &LibraryTraits) -> Result<(Verdict, Option<f32>), ScanError> {
+        let features = match signature {
+            FileSignature::Exe | FileSignature::Dll => FeatureVector::pe_from_bytes(data),
+            FileSignature::Elf | FileSignature::So => FeatureVector::elf_from_bytes(data),
+            FileSignature::MachO => FeatureVector::macho_from_bytes(data),
+            FileSignature::Script(_) => unreachable!("Scripts are scored by their indicators"),
+        }.map_err(|e| ScanError::Features(file_path.to_path_buf(), e))?;
+
+        // Mach-O binaries without a Mach-O model only go through the heuristics
+        let (score, model) = match signature.is_library() {
+            true => (self.models.predict_library(&features), self.models.library_name(&features)),
+            false => (self.models.predict(&features), self.models.name(&features)),
+        };
+        let score = score.map_err(|e| ScanError::Model(file_path.to_path_buf(), e))?;
+        Ok((self.verdict(signature, model.unwrap_or_default().to_string(), score, features.entropy(), library), Some(features.entropy())))
+    }
+
+    /// Applies the safety policy to what the model said, if it had a say
+    #[cfg(not(feature = "ffi"))]
+    fn verdict(&self, signature: FileSignature, model: String, score: Option<f32>, entropy: f32, library: &LibraryTraits) -> Verdict {
+        let threshold = self.safety.threshold(signature);
+
+        let mut heuristics = vec![];
+        if self.safety.entropy_heuristic && entropy > PACKED_ENTROPY {
+            heuristics.push(Heuristic::HighEntropy);
+        }
+        heuristics.extend(self.library_heuristics(library));
+
+        Verdict {
+            signature: Some(signature),
+            model,
+            score,
+            threshold,
+            is_malware: score.is_some_and(|score| score > threshold) || !heuristics.is_empty(),
+            heuristics,
+            known_bad: None,
+            rules: vec![],
+            body_signatures: vec![],
+            sha256: None,
+        }
+    }
+
+    /// Both need a library, they're not worth much alone
+    fn library_heuristics(&self, library: &LibraryTraits) -> Vec<Heuristic> {
+        let mut heuristics = vec![];
+        if self.safety.interposition_heuristic && !library.interposed.is_empty() {
+            heuristics.push(Heuristic::Interposition(library.interposed.clone()));
+        }
+        if self.safety.constructor_heuristic && library.constructor {
+            heuristics.push(Heuristic::Constructor);
+        }
+        heuristics
+    }
+
+    #[cfg(feature = "ffi")]
+    fn predict(&self, file_path: &Path, data: &[u8], signature: FileSignature, library: &LibraryTraits) -> Result<(Verdict, Option<f32>), ScanError> {
+        let Some(model_path) = signature.model_path(&self.model_dir) else {
+            // Mach-O binaries without a Mach-O model only go through the heuristics
+            return Ok((self.ffi_verdict(signature, String::new(), false, library), None));
+        };
+        // the C predictors open the file themselves, what was found in an archive is written out for them
+        let spilled = match file_path.is_file() {
+            true => None,
+            false => Some(spill(data).map_err(|e| ScanError::Io(file_path.to_path_buf(), e))?),
+        };
+        let c_file_path = CString::new(spilled.as_deref().unwrap_or(file_path).to_str().unwrap()).unwrap();
+        let c_model_path = CString::new(model_path.to_string_lossy().as_bytes()).unwrap();
+        // the C predictors only hand back the verdict with their own thresholds,
+        // the score is printed by them with --show-pred
+        let is_malware = unsafe {
+            match signature {
+                FileSignature::Exe | FileSignature::Dll => predict_malware_pe(c_file_path.as_ptr(), c_model_path.as_ptr(), self.show_pred),
+                // the ELF features are byte statistics, they're the Mach-O ones too
+                FileSignature::Elf | FileSignature::So | FileSignature::MachO => {
+                    predict_malware_elf(c_file_path.as_ptr(), c_model_path.as_ptr(), self.show_pred)
                 }
-                _ => continue
+                FileSignature::Script(_) => unreachable!("Scripts are scored by their indicators"),
             }
+        };
+        if let Some(spilled) = spilled.as_deref().and_then(Path::parent) {
+            let _ = fs::remove_dir_all(spilled);
         }
+        Ok((self.ffi_verdict(signature, model_path.to_string_lossy().to_string(), is_malware, library), None))
+    }
 
-        println!("Scanning ended");
-        println!("Found {malwares_count} possible malwares.");
+    /// What the C predictors said, with the library heuristics on top
+    #[cfg(feature = "ffi")]
+    fn ffi_verdict(&self, signature: FileSignature, model: String, predicted: bool, library: &LibraryTraits) -> Verdict {
+        let heuristics = self.library_heuristics(library);
+        Verdict {
+            model,
+            is_malware: predicted || !heuristics.is_empty(),
+            heuristics,
+            ..self.unscored(Some(signature))
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct FileResult {
+    pub path: PathBuf,
+    /// Where in the archive at `path` the file was found, `inner.tar.gz!bin/evil` for nested archives
+    pub entry: Option<String>,
+    pub outcome: FileOutcome,
+}
+
+impl FileResult {
+    /// `archive.zip!inner/path/bin` for files found in archives
+    pub fn location(&self) -> String {
+        match &self.entry {
+            Some(entry) => format!("{}!{entry}", self.path.display()),
+            None => self.path.display().to_string(),
+        }
+    }
+
+    /// Why the file on disk is a malware, `None` if it isn't. An archive is one because of what's in it
+    pub fn reason(&self) -> Option<String> {
+        let FileOutcome::Scanned(verdict) = &self.outcome else { return None };
+        if !verdict.is_malware {
+            return None;
+        }
+        Some(match &self.entry {
+            Some(entry) => format!("{entry}: {}", verdict.reason()),
+            None => verdict.reason(),
+        })
+    }
+
+    /// What the model scored the file, or the archive entry behind `reason`
+    pub fn score(&self) -> Option<f32> {
+        let FileOutcome::Scanned(verdict) = &self.outcome else { return None };
+        verdict.score
+    }
+
+    /// Whether the processes running or mapping it may be killed, see `Verdict::library_heuristics_only`
+    pub fn killable(&self) -> bool {
+        matches!(&self.outcome, FileOutcome::Scanned(verdict) if !verdict.library_heuristics_only())
+    }
+}
 
-        Ok(())
+#[derive(Debug)]
+pub enum FileOutcome {
+    Scanned(Verdict),
+    /// Worth scanning, but it wasn't
+    Skipped(SkipReason),
+    Failed(ScanError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    /// Over `--max-file-size`, with its size
+    TooLarge(u64),
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::TooLarge(size) => write!(f, "skipped: too large ({size} bytes)"),
+        }
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct Verdict {
+    /// None for files that aren't executables, only a known bad hash, a rule or a signature flags those
+    pub signature: Option<FileSignature>,
+    /// The model file that scored it
+    pub model: String,
+    /// `None` when the C predictors are used, they only return the verdict
+    pub score: Option<f32>,
+    pub threshold: f32,
+    /// Heuristics that fired, any of them makes the file a malware
+    pub heuristics: Vec<Heuristic>,
+    /// The hash reputation store knows it's bad, the models weren't asked
+    pub known_bad: Option<KnownHash>,
+    /// YARA rules that matched, any of them makes the file a malware
+    pub rules: Vec<String>,
+    /// ClamAV body signatures that matched, any of them makes the file a malware
+    pub body_signatures: Vec<String>,
+    pub is_malware: bool,
+    /// Only computed for hash lists, the cache and reports
+    pub sha256: Option<String>,
+}
+
+impl Verdict {
+    /// Why the file was flagged, e.g. `model/elf/model.ubj scored 0.912345 (threshold 0.49), high entropy (packed or encrypted)`
+    pub fn reason(&self) -> String {
+        let mut reasons = vec![];
+        if let Some(known) = &self.known_bad {
+            reasons.push(known.to_string());
+        }
+        if let Some(score) = self.score
+            && score > self.threshold
+        {
+            reasons.push(format!("{} scored {score:.6} (threshold {})", self.model, self.threshold));
+        }
+        reasons.extend(self.heuristics.iter().map(|h| h.to_string()));
+        if !self.rules.is_empty() {
+            reasons.push(format!("matched {}", self.rules.iter().map(|rule| format!("rule {rule}")).collect::<Vec<String>>().join(", ")));
+        }
+        if !self.body_signatures.is_empty() {
+            reasons.push(format!("matched {}", self.body_signatures.iter().map(|signature| format!("signature {signature}")).collect::<Vec<String>>().join(", ")));
+        }
+        // the C predictors don't give a score, only the model can have flagged it if nothing else did
+        if reasons.is_empty() && self.score.is_none() && !self.model.is_empty() {
+            reasons.push(format!("{} flagged it", self.model));
+        }
+        reasons.join(", ")
+    }
+
+    /// Flagged by the library heuristics and nothing else. Ordinary C++ libraries have constructors,
+    /// whatever maps them isn't killed over it
+    pub fn library_heuristics_only(&self) -> bool {
+        let model_flagged = self.score.is_some_and(|score| score > self.threshold);
+        !self.heuristics.is_empty()
+            && self.heuristics.iter().all(|h| matches!(h, Heuristic::Interposition(_) | Heuristic::Constructor))
+            && self.known_bad.is_none() && !model_flagged && self.rules.is_empty() && self.body_signatures.is_empty()
+    }
+
+    /// Adds the rules and signatures that matched, any of them flags the file
+    fn with_matches(mut self, rules: Vec<String>, body_signatures: Vec<String>) -> Self {
+        self.is_malware |= !rules.is_empty() || !body_signatures.is_empty();
+        self.rules = rules;
+        self.body_signatures = body_signatures;
+        self
+    }
+}
+
+#[derive(Debug)]
+pub enum ScanError {
+    Walk(walkdir::Error),
+    Io(PathBuf, io::Error),
+    /// What was in the archive until then was still scanned
+    Archive(PathBuf, ArchiveError),
+    /// Its size changed between the stat and the read
+    Changed(PathBuf),
+    #[cfg(not(feature = "ffi"))]
+    Features(PathBuf, FeatureError),
+    #[cfg(not(feature = "ffi"))]
+    Model(PathBuf, ModelError),
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScanError::Walk(e) if e.io_error().is_some_and(|err| err.kind() == io::ErrorKind::PermissionDenied) => {
+                write!(f, "Permission denied when accessing {:?}", e.path().unwrap_or(Path::new("")))
+            }
+            ScanError::Walk(e) if e.loop_ancestor().is_some() => {
+                write!(f, "Symlink loop at {:?}, not following it", e.path().unwrap_or(Path::new("")))
+            }
+            ScanError::Walk(e) => write!(f, "An unexpected error occured: {e}"),
+            ScanError::Io(path, e) => write!(f, "Couldn't read {:?}: {e}", path),
+            ScanError::Archive(path, e) => write!(f, "Stopped unpacking {:?}: {e}", path),
+            ScanError::Changed(path) => write!(f, "{:?} changed while it was read, scan it again", path),
+            #[cfg(not(feature = "ffi"))]
+            ScanError::Features(path, e) => write!(f, "Couldn't extract features from {:?}: {e}", path),
+            #[cfg(not(feature = "ffi"))]
+            ScanError::Model(path, e) => write!(f, "Couldn't score {:?}: {e}", path),
+        }
+    }
+}
+
+impl std::error::Error for ScanError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FileSignature {
     Exe,
     Elf,
+    /// ELF shared library
+    So,
+    /// PE DLL
+    Dll,
+    /// Executables and libraries, universal binaries too
+    MachO,
+    /// Recognized by shebang or extension
+    Script(ScriptKind),
+}
+
+impl FileSignature {
+    /// The C predictors have no library models, the executable ones score those too.
+    /// Mach-O binaries are only scored by a Mach-O model, None without one.
+    #[cfg(feature = "ffi")]
+    fn model_path(self, model_dir: &Path) -> Option<PathBuf> {
+        match self {
+            FileSignature::Exe | FileSignature::Dll => Some(model_dir.join("exe/model.ubj")),
+            FileSignature::Elf | FileSignature::So => Some(model_dir.join("elf/model.ubj")),
+            FileSignature::MachO => Some(model_dir.join("macho/model.ubj")).filter(|path| path.is_file()),
+            FileSignature::Script(_) => unreachable!("Scripts are scored by their indicators"),
+        }
+    }
+
+    /// What the cache and the reports call it
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FileSignature::Exe => "exe",
+            FileSignature::Elf => "elf",
+            FileSignature::So => "so",
+            FileSignature::Dll => "dll",
+            FileSignature::MachO => "macho",
+            FileSignature::Script(kind) => kind.as_str(),
+        }
+    }
+
+    pub fn is_library(self) -> bool {
+        matches!(self, FileSignature::So | FileSignature::Dll)
+    }
 }
 
-fn check_file_signature(file_path: &Path) -> Option<FileSignature> {
-    let buf = fs::read(file_path).ok()?;
-    match Object::parse(&buf).ok()? {
-        Object::Elf(elf) => {
+/// A temporary copy of a file found in an archive, for the C predictors. It goes in a directory of its own
+/// made by `mkdtemp`, only this user can enter it, so its path can't be guessed, swapped for a symlink or read.
+/// Removing the directory removes the copy.
+#[cfg(feature = "ffi")]
+fn spill(data: &[u8]) -> io::Result<PathBuf> {
+    let mut template = CString::new(std::env::temp_dir().join("sentinel_XXXXXX").into_os_string().into_vec())?.into_bytes_with_nul();
+    if unsafe { libc::mkdtemp(template.as_mut_ptr() as *mut c_char) }.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+    template.pop();
+    let path = PathBuf::from(std::ffi::OsString::from_vec(template)).join("entry");
+    let written = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&path)
+        .and_then(|mut file| file.write_all(data));
+    if let Err(e) = written {
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+        return Err(e);
+    }
+    Ok(path)
+}
+
+/// Anything below them too, a walk starting at `/proc/self` mustn't read `/proc/self/...`
+fn is_pseudo_filesystem(path: &Path) -> bool {
+    PSEUDO_FILESYSTEMS.iter().any(|pseudo| path.starts_with(pseudo))
+}
+
+/// Binaries by their headers, scripts by their shebang or, without one, by `path`'s extension
+fn check_file_signature(path: &Path, buf: &[u8]) -> Option<FileSignature> {
+    match Object::parse(buf) {
+        Ok(Object::Elf(elf)) => {
             if !elf.is_lib {
                 Some(FileSignature::Elf)
-            } else { None }
+            } else { Some(FileSignature::So) }
         }
-        Object::PE(pe) => {
+        Ok(Object::PE(pe)) => {
             if !pe.is_lib {
                 Some(FileSignature::Exe)
-            } else { None }
+            } else { Some(FileSignature::Dll) }
         }
-        _ => None,
+        Ok(Object::Mach(Mach::Fat(fat))) if fat.narches >= MAX_FAT_ARCHES => None,
+        Ok(Object::Mach(_)) => Some(FileSignature::MachO),
+        _ => ScriptKind::detect(path, buf).map(FileSignature::Script),
     }
 }
 
-fn handle_malware<T: AsRef<Path>>(file_path: T, is_malware: bool, malwares_count: &mut usize) {
-    if is_malware {
-        println!("{:?} is a malware", file_path.as_ref());
-        *malwares_count += 1;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{ffi::CString, os::unix::fs::symlink};
+
+    fn model_dir() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).ancestors().map(|dir| dir.join("model")).find(|dir| dir.is_dir()).unwrap()
+    }
+
+    fn analyzer(reputation: HashReputation) -> Analyzer {
+        Analyzer {
+            #[cfg(not(feature = "ffi"))]
+            models: ModelRegistry::load(model_dir()).unwrap(),
+            #[cfg(feature = "ffi")]
+            model_dir: model_dir(),
+            safety: Aggressiveness::Normal.into(),
+            #[cfg(feature = "ffi")]
+            show_pred: false,
+            cache: None,
+            reputation: Some(reputation),
+            rules: RuleSet::default(),
+            body_signatures: BodySignatures::default(),
+            archive_limits: ArchiveLimits::default(),
+            max_file_size: u64::MAX,
+            read_budget: ReadBudget::new(READ_BUDGET),
+            always_hash: false,
+        }
+    }
+
+    fn walked(root: &Path, options: WalkOptions) -> (Vec<PathBuf>, Vec<walkdir::Error>) {
+        let filter = PathFilter::new(&[], &[]).unwrap();
+        let (mut paths, mut errors) = (vec![], vec![]);
+        for entry in walk(root, options, &filter) {
+            match entry {
+                Ok(entry) => paths.push(entry.into_path()),
+                Err(e) => errors.push(e),
+            }
+        }
+        paths.sort();
+        (paths, errors)
+    }
+
+    #[test]
+    fn walks_within_depth_and_reports_symlink_loops() {
+        let root = std::env::temp_dir().join(format!("sentinel_walk_{}", std::process::id()));
+        fs::create_dir_all(root.join("a/b/c")).unwrap();
+        fs::write(root.join("a/b/c/deep"), b"").unwrap();
+        symlink("..", root.join("a/up")).unwrap();
+
+        let options = WalkOptions { max_depth: Some(2), follow_symlinks: false, one_file_system: false };
+        let (paths, errors) = walked(&root, options);
+        assert_eq!(paths, [root.clone(), root.join("a"), root.join("a/b"), root.join("a/up")]);
+        assert!(errors.is_empty());
+
+        let (paths, errors) = walked(&root, WalkOptions { max_depth: None, follow_symlinks: true, ..options });
+        assert!(paths.contains(&root.join("a/b/c/deep")));
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].loop_ancestor().is_some());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn skips_pseudo_filesystems_below_the_start() {
+        assert!(is_pseudo_filesystem(Path::new("/proc/self/maps")));
+        assert!(!is_pseudo_filesystem(Path::new("/procfs/bin")));
+        let options = WalkOptions { max_depth: Some(1), follow_symlinks: false, one_file_system: false };
+        assert_eq!(walked(Path::new("/proc/self"), options).0, [PathBuf::from("/proc/self")]);
+
+        // however the start or what's below it is reached
+        let root = std::env::temp_dir().join(format!("sentinel_pseudo_{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        symlink("/", root.join("slash")).unwrap();
+        symlink("/proc", root.join("proc")).unwrap();
+        let paths = walked(&root.join("slash"), options).0;
+        assert!(paths.contains(&root.join("slash/usr")) && !paths.contains(&root.join("slash/proc")));
+        let paths = walked(&root, WalkOptions { follow_symlinks: true, ..options }).0;
+        assert!(paths.contains(&root.join("slash")) && !paths.contains(&root.join("proc")));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn stays_on_one_file_system() {
+        let root = std::env::temp_dir().join(format!("sentinel_mount_{}", std::process::id()));
+        let mount_point = root.join("mnt");
+        fs::create_dir_all(&mount_point).unwrap();
+        let target = CString::new(mount_point.to_str().unwrap()).unwrap();
+        // only root can mount something to cross into
+        let mounted = unsafe { libc::mount(c"none".as_ptr(), target.as_ptr(), c"tmpfs".as_ptr(), 0, std::ptr::null()) } == 0;
+        if mounted {
+            fs::write(mount_point.join("elsewhere"), b"").unwrap();
+            let options = WalkOptions { max_depth: None, follow_symlinks: false, one_file_system: false };
+            assert!(walked(&root, options).0.contains(&mount_point.join("elsewhere")));
+            let (paths, _) = walked(&root, WalkOptions { one_file_system: true, ..options });
+            unsafe { libc::umount(target.as_ptr()) };
+            // the mount point is still listed, it's just not entered
+            assert!(paths.contains(&mount_point) && !paths.contains(&mount_point.join("elsewhere")));
+        } else {
+            eprintln!("Not root, --one-file-system wasn't tested");
+        }
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn library_heuristics_alone_dont_get_anything_killed() {
+        let verdict = Verdict {
+            signature: Some(FileSignature::Elf),
+            model: "model/elf_library/model.ubj".to_string(),
+            score: Some(0.1),
+            threshold: 0.49,
+            heuristics: vec![Heuristic::Constructor, Heuristic::Interposition(vec!["open".to_string()])],
+            known_bad: None,
+            rules: vec![],
+            body_signatures: vec![],
+            is_malware: true,
+            sha256: None,
+        };
+        assert!(verdict.library_heuristics_only());
+        assert!(!Verdict { score: Some(0.9), ..verdict.clone() }.library_heuristics_only());
+        assert!(!Verdict { rules: vec!["rootkit".to_string()], ..verdict.clone() }.library_heuristics_only());
+        assert!(!Verdict { heuristics: vec![Heuristic::Constructor, Heuristic::HighEntropy], ..verdict.clone() }.library_heuristics_only());
+        assert!(!Verdict { heuristics: vec![], ..verdict }.library_heuristics_only());
+    }
+
+    #[test]
+    fn skips_known_good_and_flags_known_bad_files() {
+        let dir = std::env::temp_dir().join(format!("sentinel_reputation_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (elf, text) = (dir.join("tool"), dir.join("notes.txt"));
+        let elf_bytes = [b"\x7fELF\x02\x01\x01".as_slice(), &[0; 120]].concat();
+        fs::write(&elf, &elf_bytes).unwrap();
+        fs::write(&text, b"nothing to see here").unwrap();
+        let sha256 = |data: &[u8]| hex::encode(<sha2::Sha256 as sha2::Digest>::digest(data));
+
+        // known good never reaches the models, a truncated ELF would fail there
+        let good = analyzer(HashReputation::in_memory(&[(&sha256(&elf_bytes), HashVerdict::Good, "vendor")]));
+        assert!(good.scan_file(&elf).is_empty());
+        assert!(good.scan_file(&text).is_empty());
+
+        let md5 = hex::encode(<md5::Md5 as md5::Digest>::digest(&elf_bytes));
+        let bad = analyzer(HashReputation::in_memory(&[
+            (&sha256(&elf_bytes), HashVerdict::Good, "vendor"),
+            (&md5, HashVerdict::Bad, "feed"),
+            (&sha256(b"nothing to see here"), HashVerdict::Bad, "feed"),
+        ]));
+        for path in [&elf, &text] {
+            let results = bad.scan_file(path);
+            let FileOutcome::Scanned(verdict) = &results[0].outcome else { panic!("{path:?} wasn't scanned") };
+            assert!(verdict.is_malware && verdict.known_bad.is_some(), "{path:?} wasn't flagged");
+            assert!(results[0].reason().unwrap().contains("known bad"));
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// A 64-bit x86 Mach-O executable without load commands, and some code for the byte statistics
+    #[cfg(not(feature = "ffi"))]
+    fn macho() -> Vec<u8> {
+        let header = [0xfeedfacf_u32, 0x0100_0007, 3, 2, 0, 0, 0, 0];
+        let mut data = header.iter().flat_map(|field| field.to_le_bytes()).collect::<Vec<u8>>();
+        data.extend(b"\x55\x48\x89\xe5\x31\xc0\x5d\xc3".repeat(512));
+        data
+    }
+
+    #[cfg(not(feature = "ffi"))]
+    #[test]
+    fn scores_mach_o_only_with_a_mach_o_model() {
+        let dir = std::env::temp_dir().join(format!("sentinel_macho_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("tool");
+        fs::write(&path, macho()).unwrap();
+
+        let mut analyzer = analyzer(HashReputation::in_memory(&[]));
+        let results = analyzer.scan_file(&path);
+        let FileOutcome::Scanned(verdict) = &results[0].outcome else { panic!("{path:?} wasn't scanned") };
+        assert_eq!((verdict.signature, verdict.score, verdict.model.as_str()), (Some(FileSignature::MachO), None, ""));
+        assert!(!verdict.is_malware);
+
+        let macho_model = crate::xgboost::Booster::from_file(model_dir().join("elf/model.ubj")).unwrap();
+        analyzer.models = analyzer.models.with_macho_model(Some(macho_model)).unwrap();
+        let results = analyzer.scan_file(&path);
+        let FileOutcome::Scanned(verdict) = &results[0].outcome else { panic!("{path:?} wasn't scanned") };
+        assert!(verdict.score.is_some());
+        assert_eq!(verdict.model, "Mach-O model");
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -0,0 +1,174 @@
+use std::{path::{Path, PathBuf}, sync::{Condvar, Mutex, mpsc}, thread};
+use walkdir::DirEntry;
+
+use super::{FileOutcome, FileResult, ScanError};
+
+/// How many bytes of file contents the workers hold at once, together.
+/// A worker waits for others to give theirs back before reading a file that doesn't fit.
+pub(super) struct ReadBudget {
+    total: u64,
+    available: Mutex<u64>,
+    released: Condvar,
+}
+
+/// Bytes taken from a `ReadBudget`, given back when dropped
+pub(super) struct Reservation<'a> {
+    budget: &'a ReadBudget,
+    bytes: u64,
+}
+
+impl ReadBudget {
+    pub(super) fn new(total: u64) -> Self {
+        Self { total, available: Mutex::new(total), released: Condvar::new() }
+    }
+
+    /// Waits until `bytes` are available. Never more than the whole budget, or it would wait forever
+    pub(super) fn reserve(&self, bytes: u64) -> Reservation<'_> {
+        let bytes = bytes.min(self.total);
+        let mut available = self.available.lock().unwrap();
+        while *available < bytes {
+            available = self.released.wait(available).unwrap();
+        }
+        *available -= bytes;
+        Reservation { budget: self, bytes }
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        *self.budget.available.lock().unwrap() += self.bytes;
+        self.budget.released.notify_all();
+    }
+}
+
+/// Walks on one thread and fans the files out to `jobs` workers running `scan`,
+/// which does the signature check, feature extraction and prediction.
+/// `scan` returns nothing for files that aren't worth reporting (not an executable),
+/// and a result for everything worth reporting in an archive.
+///
+/// The sink hands every result to `on_result` on the calling thread as soon as it arrives,
+/// then sorts them by path and entry so the report doesn't depend on thread scheduling.
+pub(super) fn run<W, F, R>(walker: W, jobs: usize, scan: F, mut on_result: R) -> Vec<FileResult>
+where
+    W: IntoIterator<Item = walkdir::Result<DirEntry>> + Send,
+    F: Fn(&Path) -> Vec<FileResult> + Sync,
+    R: FnMut(&FileResult),
+{
+    let jobs = jobs.max(1);
+    // bounded so the walker can't run ahead and buffer a whole filesystem worth of paths
+    let (path_tx, path_rx) = mpsc::sync_channel::<PathBuf>(jobs * 4);
+    let path_rx = Mutex::new(path_rx);
+    let (result_tx, result_rx) = mpsc::channel::<FileResult>();
+
+    let mut results = thread::scope(|s| {
+        let walker_tx = result_tx.clone();
+        s.spawn(move || {
+            for entry in walker {
+                match entry {
+                    Ok(entry) => {
+                        // symlinks are only resolved here when the walker follows them
+                        if entry.file_type().is_file() && path_tx.send(entry.into_path()).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        let path = e.path().map(Path::to_path_buf).unwrap_or_default();
+                        let _ = walker_tx.send(FileResult { path, entry: None, outcome: FileOutcome::Failed(ScanError::Walk(e)) });
+                    }
+                }
+            }
+        });
+
+        for _ in 0..jobs {
+            let result_tx = result_tx.clone();
+            let (path_rx, scan) = (&path_rx, &scan);
+            s.spawn(move || {
+                loop {
+                    // the lock is only held while waiting for the next path, not while scanning it
+                    let next = path_rx.lock().unwrap().recv();
+                    let Ok(path) = next else { break };
+                    for result in scan(&path) {
+                        let _ = result_tx.send(result);
+                    }
+                }
+            });
+        }
+
+        // the sink ends once the walker and every worker dropped their sender
+        drop(result_tx);
+        result_rx.iter()
+            .inspect(|result| on_result(result))
+            .collect::<Vec<FileResult>>()
+    });
+
+    results.sort_by(|a, b| (&a.path, &a.entry).cmp(&(&b.path, &b.entry)));
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use walkdir::WalkDir;
+    use super::*;
+    use crate::args_parser::file_scanner::{FileSignature, Verdict};
+
+    #[test]
+    fn results_are_ordered_regardless_of_jobs() {
+        let root = std::env::temp_dir().join(format!("sentinel_pipeline_{}", std::process::id()));
+        for i in 0..40 {
+            let dir = root.join(format!("dir{}", i % 5));
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join(format!("file{i}")), [i as u8]).unwrap();
+        }
+
+        let scan = |path: &Path| {
+            let byte = fs::read(path).unwrap()[0];
+            // skip a few files like non-executables get skipped
+            let outcome = (!byte.is_multiple_of(3)).then_some(FileOutcome::Scanned(Verdict {
+                signature: Some(FileSignature::Elf),
+                model: "test".to_string(),
+                score: Some(byte as f32),
+                threshold: 0.5,
+                heuristics: vec![],
+                known_bad: None,
+                rules: vec![],
+                body_signatures: vec![],
+                is_malware: byte.is_multiple_of(2),
+                sha256: None,
+            }));
+            outcome.map(|outcome| FileResult { path: path.to_path_buf(), entry: None, outcome }).into_iter().collect()
+        };
+        let paths = |results: Vec<FileResult>| results.into_iter().map(|r| r.path).collect::<Vec<PathBuf>>();
+
+        let mut seen = 0;
+        let serial = paths(run(WalkDir::new(&root), 1, scan, |_| seen += 1));
+        let parallel = paths(run(WalkDir::new(&root), 8, scan, |_| {}));
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(serial.len(), 26);
+        assert_eq!(seen, 26);
+        assert!(serial.is_sorted());
+        assert_eq!(serial, parallel);
+    }
+
+    #[test]
+    fn workers_never_hold_more_than_the_read_budget() {
+        use std::sync::atomic::{AtomicU64, Ordering};
+        let budget = ReadBudget::new(100);
+        let (held, peak) = (AtomicU64::new(0), AtomicU64::new(0));
+        thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    let _reservation = budget.reserve(40);
+                    peak.fetch_max(held.fetch_add(40, Ordering::SeqCst) + 40, Ordering::SeqCst);
+                    thread::sleep(std::time::Duration::from_millis(5));
+                    held.fetch_sub(40, Ordering::SeqCst);
+                });
+            }
+        });
+        assert!(peak.load(Ordering::SeqCst) <= 100);
+        // bigger than the whole budget, it gets all of it instead of waiting forever
+        drop(budget.reserve(1000));
+        assert_eq!(*budget.available.lock().unwrap(), 100);
+    }
+}
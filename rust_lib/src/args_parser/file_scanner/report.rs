@@ -0,0 +1,358 @@
+// What a scan found, for machines: SIEMs take JSON or NDJSON, spreadsheets CSV, code scanning dashboards SARIF.
+// The console output stays the way it is, the report is written on top of it.
+use std::{collections::HashMap, path::{Path, PathBuf}, time::Duration};
+use chrono::{DateTime, Local};
+use serde_json::{json, Value};
+
+use super::{FileOutcome, FileResult, FileSignature, Response, Verdict};
+
+const SARIF_SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+/// The one SARIF rule every detection is reported under, why a file was flagged is in the message
+const SARIF_RULE: &str = "malware";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// One document, the summary and every file
+    Json,
+    /// One file per line, then the summary, each tagged with a `type`
+    Ndjson,
+    /// One file per row, CSV has no room for the summary
+    Csv,
+    /// SARIF 2.1.0, a result per detection, skipped files and errors as notifications
+    Sarif,
+}
+
+impl std::str::FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(ReportFormat::Json),
+            "ndjson" | "jsonl" => Ok(ReportFormat::Ndjson),
+            "csv" => Ok(ReportFormat::Csv),
+            "sarif" => Ok(ReportFormat::Sarif),
+            _ => Err(
+                format!("Invalid report format: {s}.
+                    Use [json, ndjson, csv, sarif]"))
+        }
+    }
+}
+
+impl ReportFormat {
+    /// `report.sarif`, `scan.ndjson`... `None` for extensions that aren't a format
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        path.extension()?.to_str()?.parse().ok()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ScanSummary {
+    pub directory: PathBuf,
+    pub started: DateTime<Local>,
+    pub duration: Duration,
+    /// Executables, scripts and archives scanned, and anything else a rule, a signature or a hash list flagged
+    pub files: usize,
+    pub malware: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    /// Files answered from the scan cache
+    pub cache_hits: usize,
+}
+
+impl ScanSummary {
+    pub fn new(directory: &Path, started: DateTime<Local>, duration: Duration, results: &[FileResult], cache_hits: usize) -> Self {
+        let count = |matches: fn(&FileOutcome) -> bool| results.iter().filter(|result| matches(&result.outcome)).count();
+        Self {
+            directory: directory.to_path_buf(),
+            started,
+            duration,
+            files: results.len(),
+            malware: count(|outcome| matches!(outcome, FileOutcome::Scanned(verdict) if verdict.is_malware)),
+            skipped: count(|outcome| matches!(outcome, FileOutcome::Skipped(_))),
+            failed: count(|outcome| matches!(outcome, FileOutcome::Failed(_))),
+            cache_hits,
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "directory": self.directory,
+            "started": self.started.to_rfc3339(),
+            "duration_seconds": self.duration.as_secs_f64(),
+            "files": self.files,
+            "malware": self.malware,
+            "clean": self.files - self.malware - self.skipped - self.failed,
+            "skipped": self.skipped,
+            "errors": self.failed,
+            "cache_hits": self.cache_hits,
+        })
+    }
+}
+
+/// What was done about the detections is in `responses`, by path
+pub fn render(results: &[FileResult], summary: &ScanSummary, responses: &HashMap<PathBuf, Response>, format: ReportFormat) -> String {
+    let file_json = |result| file_json(result, responses);
+    match format {
+        ReportFormat::Json => {
+            let report = json!({
+                "summary": summary.to_json(),
+                "files": results.iter().map(file_json).collect::<Vec<Value>>(),
+            });
+            serde_json::to_string_pretty(&report).unwrap_or_default() + "\n"
+        }
+        ReportFormat::Ndjson => {
+            let mut ndjson = String::new();
+            for mut line in results.iter().map(file_json).chain(std::iter::once(summary.to_json())) {
+                let kind = if line.get("path").is_some() { "file" } else { "summary" };
+                line.as_object_mut().expect("Report lines are objects").insert("type".to_string(), json!(kind));
+                ndjson += &line.to_string();
+                ndjson += "\n";
+            }
+            ndjson
+        }
+        ReportFormat::Csv => render_csv(results, responses),
+        ReportFormat::Sarif => serde_json::to_string_pretty(&sarif(results, summary, responses)).unwrap_or_default() + "\n",
+    }
+}
+
+fn scanned(result: &FileResult) -> Option<&Verdict> {
+    match &result.outcome {
+        FileOutcome::Scanned(verdict) => Some(verdict),
+        _ => None,
+    }
+}
+
+fn verdict_name(outcome: &FileOutcome) -> &'static str {
+    match outcome {
+        FileOutcome::Scanned(verdict) if verdict.is_malware => "malware",
+        FileOutcome::Scanned(_) => "clean",
+        FileOutcome::Skipped(_) => "skipped",
+        FileOutcome::Failed(_) => "error",
+    }
+}
+
+/// Why it was flagged, or why it wasn't scanned
+fn reason(outcome: &FileOutcome) -> Option<String> {
+    match outcome {
+        FileOutcome::Scanned(verdict) => verdict.is_malware.then(|| verdict.reason()),
+        FileOutcome::Skipped(reason) => Some(reason.to_string()),
+        FileOutcome::Failed(_) => None,
+    }
+}
+
+/// Reported, quarantined... only for detections
+fn response(result: &FileResult, responses: &HashMap<PathBuf, Response>) -> Option<String> {
+    scanned(result).filter(|verdict| verdict.is_malware)?;
+    Some(responses.get(&result.path).unwrap_or(&Response::Reported).to_string())
+}
+
+fn error(outcome: &FileOutcome) -> Option<String> {
+    match outcome {
+        FileOutcome::Failed(e) => Some(e.to_string()),
+        _ => None,
+    }
+}
+
+/// The shortest decimal that reads back as the same f32, not the digits of its f64 widening
+fn number(value: f32) -> Value {
+    value.to_string().parse::<f64>().map_or(Value::Null, Value::from)
+}
+
+fn file_json(result: &FileResult, responses: &HashMap<PathBuf, Response>) -> Value {
+    let verdict = scanned(result);
+    json!({
+        "path": result.location(),
+        "sha256": verdict.and_then(|verdict| verdict.sha256.clone()),
+        "file_type": verdict.and_then(|verdict| verdict.signature).map(FileSignature::as_str),
+        "model": verdict.map(|verdict| verdict.model.clone()).filter(|model| !model.is_empty()),
+        "score": verdict.and_then(|verdict| verdict.score).map(number),
+        // without a score, the threshold wasn't applied to anything
+        "threshold": verdict.filter(|verdict| verdict.score.is_some()).map(|verdict| number(verdict.threshold)),
+        "verdict": verdict_name(&result.outcome),
+        "reason": reason(&result.outcome),
+        "known_bad": verdict.and_then(|verdict| verdict.known_bad.as_ref()).map(ToString::to_string),
+        "heuristics": verdict.map_or(vec![], |verdict| verdict.heuristics.iter().map(ToString::to_string).collect()),
+        "rules": verdict.map_or(&[][..], |verdict| &verdict.rules),
+        "signatures": verdict.map_or(&[][..], |verdict| &verdict.body_signatures),
+        "response": response(result, responses),
+        "error": error(&result.outcome),
+    })
+}
+
+fn render_csv(results: &[FileResult], responses: &HashMap<PathBuf, Response>) -> String {
+    let mut csv = csv::Writer::from_writer(vec![]);
+    let header = ["path", "sha256", "file_type", "model", "score", "threshold", "verdict", "reason", "rules", "signatures", "response", "error"];
+    let _ = csv.write_record(header);
+    for result in results {
+        let verdict = scanned(result);
+        let record = [
+            result.location(),
+            verdict.and_then(|verdict| verdict.sha256.clone()).unwrap_or_default(),
+            verdict.and_then(|verdict| verdict.signature).map_or("", FileSignature::as_str).to_string(),
+            verdict.map(|verdict| verdict.model.clone()).unwrap_or_default(),
+            verdict.and_then(|verdict| verdict.score).map(|score| score.to_string()).unwrap_or_default(),
+            verdict.filter(|verdict| verdict.score.is_some()).map(|verdict| verdict.threshold.to_string()).unwrap_or_default(),
+            verdict_name(&result.outcome).to_string(),
+            reason(&result.outcome).unwrap_or_default(),
+            verdict.map(|verdict| verdict.rules.join(";")).unwrap_or_default(),
+            verdict.map(|verdict| verdict.body_signatures.join(";")).unwrap_or_default(),
+            response(result, responses).unwrap_or_default(),
+            error(&result.outcome).unwrap_or_default(),
+        ];
+        let _ = csv.write_record(&record);
+    }
+    // writing to a Vec can't fail, and every field came from a String
+    String::from_utf8(csv.into_inner().unwrap_or_default()).unwrap_or_default()
+}
+
+fn sarif(results: &[FileResult], summary: &ScanSummary, responses: &HashMap<PathBuf, Response>) -> Value {
+    let detections = results.iter()
+        .filter_map(|result| Some((result, scanned(result).filter(|verdict| verdict.is_malware)?)))
+        .map(|(result, verdict)| json!({
+            "ruleId": SARIF_RULE,
+            "level": "error",
+            "message": { "text": format!("{} is a malware: {}", result.location(), verdict.reason()) },
+            "locations": [{ "physicalLocation": { "artifactLocation": { "uri": file_uri(&result.path) } } }],
+            "properties": {
+                "entry": result.entry,
+                "sha256": verdict.sha256,
+                "file_type": verdict.signature.map(FileSignature::as_str),
+                "score": verdict.score.map(number),
+                "threshold": verdict.score.map(|_| number(verdict.threshold)),
+                "rules": verdict.rules,
+                "signatures": verdict.body_signatures,
+                "response": response(result, responses),
+            },
+        }))
+        .collect::<Vec<Value>>();
+    let notifications = results.iter()
+        .filter(|result| !matches!(result.outcome, FileOutcome::Scanned(_)))
+        .map(|result| json!({
+            "level": if matches!(result.outcome, FileOutcome::Failed(_)) { "error" } else { "warning" },
+            "message": { "text": error(&result.outcome).or_else(|| reason(&result.outcome)).unwrap_or_default() },
+            "locations": [{ "physicalLocation": { "artifactLocation": { "uri": file_uri(&result.path) } } }],
+        }))
+        .collect::<Vec<Value>>();
+
+    json!({
+        "$schema": SARIF_SCHEMA,
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": [{
+                        "id": SARIF_RULE,
+                        "shortDescription": { "text": "File flagged as malware" },
+                        "fullDescription": { "text": "Flagged by a model, a heuristic, a known bad hash, a YARA rule or a ClamAV signature" },
+                    }],
+                },
+            },
+            "invocations": [{
+                // some files couldn't be scanned, the results don't cover everything
+                "executionSuccessful": summary.failed == 0,
+                "startTimeUtc": summary.started.to_utc().to_rfc3339(),
+                "toolExecutionNotifications": notifications,
+            }],
+            "results": detections,
+            "properties": { "summary": summary.to_json() },
+        }],
+    })
+}
+
+/// A `file://` URI for absolute paths, a relative reference otherwise, with everything but unreserved bytes escaped
+fn file_uri(path: &Path) -> String {
+    let path = path.to_string_lossy();
+    let mut uri = String::from(if path.starts_with('/') { "file://" } else { "" });
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => uri.push(byte as char),
+            _ => uri += &format!("%{byte:02X}"),
+        }
+    }
+    uri
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args_parser::file_scanner::{ScanError, SkipReason};
+    use std::io;
+
+    fn verdict(is_malware: bool) -> Verdict {
+        Verdict {
+            signature: Some(FileSignature::Elf),
+            model: "model/elf/model.ubj".to_string(),
+            score: Some(if is_malware { 0.9 } else { 0.1 }),
+            threshold: 0.49,
+            heuristics: vec![],
+            known_bad: None,
+            rules: if is_malware { vec!["upx".to_string()] } else { vec![] },
+            body_signatures: vec![],
+            is_malware,
+            sha256: Some("e3b0c442".to_string()),
+        }
+    }
+
+    #[test]
+    fn reports_every_file_and_the_summary() {
+        let result = |path: &str, entry: Option<&str>, outcome| FileResult { path: PathBuf::from(path), entry: entry.map(str::to_string), outcome };
+        let results = [
+            result("/bin/a,b", None, FileOutcome::Scanned(verdict(true))),
+            result("/bin/ok", None, FileOutcome::Scanned(verdict(false))),
+            result("/srv/x.zip", Some("evil"), FileOutcome::Scanned(verdict(true))),
+            result("/srv/big image", None, FileOutcome::Skipped(SkipReason::TooLarge(1 << 30))),
+            result("/root/secret", None, FileOutcome::Failed(ScanError::Io(PathBuf::from("/root/secret"), io::ErrorKind::PermissionDenied.into()))),
+        ];
+        let summary = ScanSummary::new(Path::new("/"), Local::now(), Duration::from_millis(1500), &results, 1);
+        let responses = HashMap::from([
+            (PathBuf::from("/bin/a,b"), Response::Quarantined { vault: PathBuf::from("/vault/a"), killed: vec![42] }),
+            (PathBuf::from("/srv/x.zip"), Response::Failed("not root".to_string())),
+        ]);
+        let render = |format| render(&results, &summary, &responses, format);
+
+        let json = serde_json::from_str::<Value>(&render(ReportFormat::Json)).unwrap();
+        assert_eq!(json["summary"]["malware"], 2);
+        assert_eq!(json["summary"]["clean"], 1);
+        assert_eq!(json["summary"]["skipped"], 1);
+        assert_eq!(json["summary"]["errors"], 1);
+        assert_eq!(json["files"][0]["score"], 0.9);
+        assert_eq!(json["files"][0]["threshold"], 0.49);
+        assert_eq!(json["files"][0]["response"], r#"quarantined into "/vault/a", killed [42]"#);
+        assert_eq!(json["files"][1]["response"], Value::Null);
+        assert_eq!(json["files"][2]["path"], "/srv/x.zip!evil");
+        assert_eq!(json["files"][2]["response"], "left in place: not root");
+        assert_eq!(json["files"][3]["reason"], "skipped: too large (1073741824 bytes)");
+
+        let ndjson = render(ReportFormat::Ndjson);
+        assert_eq!(ndjson.lines().count(), 6);
+        assert!(ndjson.lines().last().unwrap().contains(r#""type":"summary""#));
+
+        let csv = render(ReportFormat::Csv);
+        let mut reader = csv::Reader::from_reader(csv.as_bytes());
+        assert_eq!(reader.headers().unwrap(), vec!["path", "sha256", "file_type", "model", "score", "threshold", "verdict", "reason", "rules", "signatures", "response", "error"]);
+        let rows = reader.records().collect::<Result<Vec<csv::StringRecord>, _>>().unwrap();
+        assert_eq!(rows.len(), results.len());
+        assert!(rows.iter().all(|row| row.len() == 12));
+        assert_eq!(rows[0], vec!["/bin/a,b", "e3b0c442", "elf", "model/elf/model.ubj", "0.9", "0.49", "malware", "model/elf/model.ubj scored 0.900000 (threshold 0.49), matched rule upx", "upx", "", r#"quarantined into "/vault/a", killed [42]"#, ""]);
+        assert_eq!(rows[1], vec!["/bin/ok", "e3b0c442", "elf", "model/elf/model.ubj", "0.1", "0.49", "clean", "", "", "", "", ""]);
+        assert_eq!(&rows[2][0], "/srv/x.zip!evil");
+        assert_eq!(&rows[2][10], "left in place: not root");
+        assert_eq!(rows[3], vec!["/srv/big image", "", "", "", "", "", "skipped", "skipped: too large (1073741824 bytes)", "", "", "", ""]);
+        assert_eq!(&rows[4][6], "error");
+        assert!(rows[4][11].contains("/root/secret"));
+
+        let sarif = serde_json::from_str::<Value>(&render(ReportFormat::Sarif)).unwrap();
+        let run = &sarif["runs"][0];
+        assert_eq!(run["results"].as_array().unwrap().len(), 2);
+        assert_eq!(run["results"][0]["locations"][0]["physicalLocation"]["artifactLocation"]["uri"], "file:///bin/a%2Cb");
+        assert_eq!(run["results"][1]["properties"]["response"], "left in place: not root");
+        assert_eq!(run["invocations"][0]["toolExecutionNotifications"][0]["locations"][0]["physicalLocation"]["artifactLocation"]["uri"], "file:///srv/big%20image");
+        // /root/secret couldn't be scanned
+        assert_eq!(run["invocations"][0]["executionSuccessful"], false);
+        let clean = ScanSummary::new(Path::new("/"), Local::now(), Duration::ZERO, &results[..4], 0);
+        let sarif = serde_json::from_str::<Value>(&super::render(&results[..4], &clean, &responses, ReportFormat::Sarif)).unwrap();
+        assert_eq!(sarif["runs"][0]["invocations"][0]["executionSuccessful"], true);
+    }
+}
@@ -0,0 +1,315 @@
+use std::{collections::HashMap, fmt, fs, io::{self, BufRead, Write}, path::{Path, PathBuf}};
+use procfs::process::MMapPath;
+
+use crate::args_parser::quarantine::{QuarantineError, QuarantinedFile, Quarantinizer};
+use crate::palette;
+use super::Aggressiveness;
+
+/// What `scan-dir` does with a detection, from `--response-aggressiveness`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ResponseAction {
+    /// Only print the detections
+    Report,
+    /// Ask before quarantining each detection
+    Prompt,
+    /// Quarantine every detection
+    Quarantine,
+    /// Quarantine every detection, then kill the processes running or mapping it.
+    /// Never pid 1 or processes running as root, and never over library heuristics alone
+    QuarantineAndKill,
+}
+
+impl From<Aggressiveness> for ResponseAction {
+    fn from(aggressiveness: Aggressiveness) -> Self {
+        match aggressiveness {
+            Aggressiveness::Chill => Self::Report,
+            Aggressiveness::Cautious | Aggressiveness::Normal => Self::Prompt,
+            Aggressiveness::Aggressive => Self::Quarantine,
+            Aggressiveness::Hardcore => Self::QuarantineAndKill,
+        }
+    }
+}
+
+/// When detections are handed to the quarantine
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QuarantineMode {
+    /// All at once after the scan, once the report is printed
+    #[default]
+    Batch,
+    /// As soon as the file is detected, while the rest of the tree is still being scanned
+    Immediate,
+}
+
+impl std::str::FromStr for QuarantineMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "batch" => Ok(Self::Batch),
+            "immediate" => Ok(Self::Immediate),
+            _ => Err(
+                format!("Invalid quarantine mode: {s}.
+                    Use [batch, immediate]"))
+        }
+    }
+}
+
+/// What became of a detection, for the report
+#[derive(Debug, Clone, PartialEq)]
+pub enum Response {
+    /// Left in place: reporting only, or declined at the prompt
+    Reported,
+    /// `--dry-run`, with the processes that would have been killed
+    WouldQuarantine(Vec<i32>),
+    /// Moved into the vault, then the processes running or mapping it were killed
+    Quarantined { vault: PathBuf, killed: Vec<i32> },
+    /// Left in place, quarantining it failed
+    Failed(String),
+}
+
+impl fmt::Display for Response {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Response::Reported => write!(f, "reported"),
+            Response::WouldQuarantine(pids) if pids.is_empty() => write!(f, "would be quarantined"),
+            Response::WouldQuarantine(pids) => write!(f, "would be quarantined, killing {pids:?}"),
+            Response::Quarantined { vault, killed } if killed.is_empty() => write!(f, "quarantined into {:?}", vault),
+            Response::Quarantined { vault, killed } => write!(f, "quarantined into {:?}, killed {killed:?}", vault),
+            Response::Failed(e) => write!(f, "left in place: {e}"),
+        }
+    }
+}
+
+/// Carries out the response action for every detection of a scan
+pub struct Responder {
+    pub action: ResponseAction,
+    pub mode: QuarantineMode,
+    /// Print what would be killed and quarantined without touching anything
+    pub dry_run: bool,
+    /// The standard output carries a report, what's done is told on the standard error
+    notices_on_stderr: bool,
+    quarantinizer: Option<Quarantinizer>,
+    pending: Vec<QuarantinedFile>,
+    /// Killed once they're quarantined, by path
+    to_kill: HashMap<PathBuf, Vec<i32>>,
+    /// An archive with several detections in it is only handled once
+    responses: HashMap<PathBuf, Response>,
+}
+
+impl Responder {
+    pub fn new(action: ResponseAction, mode: QuarantineMode, dry_run: bool, notices_on_stderr: bool) -> Self {
+        Self {
+            action,
+            mode,
+            dry_run,
+            notices_on_stderr,
+            quarantinizer: None,
+            pending: vec![],
+            to_kill: HashMap::new(),
+            responses: HashMap::new(),
+        }
+    }
+
+    /// What was done about every detection handled so far, by path
+    pub fn responses(&self) -> &HashMap<PathBuf, Response> {
+        &self.responses
+    }
+
+    fn notice(&self, line: &str) {
+        notice(self.notices_on_stderr, line);
+    }
+
+    pub fn set_quarantinizer(&mut self, quarantinizer: Quarantinizer) {
+        self.quarantinizer = Some(quarantinizer);
+    }
+
+    /// Prompts right away, the quarantine itself and the kills wait for `finish` in batch mode.
+    /// Only `killable` detections get their processes killed.
+    pub fn respond(&mut self, path: &Path, reason: &str, score: Option<f32>, killable: bool) {
+        let palette = palette::current();
+        if self.responses.contains_key(path) {
+            return;
+        }
+        // until it's quarantined
+        self.responses.insert(path.to_path_buf(), Response::Reported);
+        match self.action {
+            ResponseAction::Report => return,
+            ResponseAction::Prompt => {
+                if !confirm(&format!("Quarantine {:?} ({reason})?", path)) {
+                    return;
+                }
+            }
+            ResponseAction::Quarantine => {}
+            ResponseAction::QuarantineAndKill if !killable => {
+                self.notice(&palette.warning(&format!("Only library heuristics flagged {:?}, nothing running it is killed", path)));
+            }
+            // looked up before the file is gone, /proc only shows the unlinked name after
+            ResponseAction::QuarantineAndKill => match owning_processes(path) {
+                Ok(pids) => {
+                    let (spared, pids): (Vec<i32>, Vec<i32>) = pids.into_iter().partition(|pid| is_protected(*pid));
+                    if !spared.is_empty() {
+                        self.notice(&palette.warning(&format!("{spared:?} running {:?} run as root or are init, they aren't killed", path)));
+                    }
+                    self.to_kill.insert(path.to_path_buf(), pids);
+                }
+                Err(e) => eprintln!("{}", palette.danger(&format!("Couldn't look for processes running {:?}: {e}", path))),
+            },
+        }
+
+        self.pending.push(QuarantinedFile::new(path, reason).with_score(score));
+
+        if self.mode == QuarantineMode::Immediate {
+            self.finish();
+        }
+    }
+
+    /// Quarantines everything `respond` accepted so far, one failure doesn't stop the others
+    pub fn finish(&mut self) {
+        let palette = palette::current();
+        let pending = std::mem::take(&mut self.pending);
+        if pending.is_empty() {
+            return;
+        }
+
+        if self.dry_run {
+            for qf in &pending {
+                self.notice(&palette.warning(&format!("[dry-run] Would quarantine {:?}: {}", qf.original_path, qf.reason)));
+                let path = PathBuf::from(&qf.original_path);
+                let pids = self.to_kill.remove(&path).unwrap_or_default();
+                if !pids.is_empty() {
+                    self.notice(&palette.warning(&format!("[dry-run] Would kill {pids:?} running {:?}", path)));
+                }
+                self.responses.insert(path, Response::WouldQuarantine(pids));
+            }
+            return;
+        }
+
+        let Some(quarantinizer) = self.quarantinizer.as_mut() else {
+            let paths = pending.iter().map(|qf| PathBuf::from(&qf.original_path)).collect::<Vec<PathBuf>>();
+            eprintln!("{}", palette.danger(&format!("No quarantine configured, {paths:?} were left in place")));
+            for path in paths {
+                self.to_kill.remove(&path);
+                self.responses.insert(path, Response::Failed("no quarantine configured".to_string()));
+            }
+            return;
+        };
+        let mut not_root = None;
+        for qf in pending {
+            let path = PathBuf::from(&qf.original_path);
+            let pids = self.to_kill.remove(&path).unwrap_or_default();
+            let stored = match &not_root {
+                Some(vault) => Err(QuarantineError::NotRoot(PathBuf::clone(vault))),
+                None => quarantinizer.push_quarantined(qf),
+            };
+            let response = match stored {
+                Ok(stored) => {
+                    let Some(sealed) = stored.first() else {
+                        // already in the vault
+                        continue;
+                    };
+                    let vault = PathBuf::from(&sealed.quarantine_path);
+                    notice(self.notices_on_stderr, &format!("Quarantined {:?} into {:?}", path, vault));
+                    // only once it's sealed, nothing is killed over a file left in place
+                    let killed = kill(&pids);
+                    if !killed.is_empty() {
+                        notice(self.notices_on_stderr, &palette.warning(&format!("Killed {killed:?} running {:?}", path)));
+                    }
+                    Response::Quarantined { vault, killed }
+                }
+                Err(e) => {
+                    // the same for every other file, say it once
+                    if not_root.is_none() {
+                        eprintln!("{}", palette.danger(&e.to_string()));
+                    }
+                    if let QuarantineError::NotRoot(vault) = &e {
+                        not_root = Some(vault.clone());
+                    }
+                    Response::Failed(e.to_string())
+                }
+            };
+            self.responses.insert(path, response);
+        }
+    }
+}
+
+fn notice(on_stderr: bool, line: &str) {
+    match on_stderr {
+        true => eprintln!("{line}"),
+        false => println!("{line}"),
+    }
+}
+
+pub fn confirm(question: &str) -> bool {
+    // the standard output may carry a report
+    eprint!("{question} [y/N] ");
+    let _ = io::stderr().flush();
+
+    let mut answer = String::new();
+    match io::stdin().lock().read_line(&mut answer) {
+        Ok(_) => matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"),
+        Err(_) => false,
+    }
+}
+
+/// Pids of every process executing or mapping `path`
+pub fn owning_processes(path: &Path) -> io::Result<Vec<i32>> {
+    // /proc/<pid>/exe and maps always hold the resolved path
+    let path = fs::canonicalize(path)?;
+    let own_pid = std::process::id() as i32;
+    let mut pids = vec![];
+
+    for process in procfs::process::all_processes().map_err(io::Error::other)?.flatten() {
+        if process.pid == own_pid {
+            continue;
+        }
+
+        let runs_it = process.exe().is_ok_and(|exe| exe == path);
+        let maps_it = || process.maps().is_ok_and(|maps| {
+            maps.into_iter().any(|map| matches!(&map.pathname, MMapPath::Path(mapped) if *mapped == path))
+        });
+        if runs_it || maps_it() {
+            pids.push(process.pid);
+        }
+    }
+
+    Ok(pids)
+}
+
+/// Init and everything running as root, system services among them, is never killed
+fn is_protected(pid: i32) -> bool {
+    pid == 1 || procfs::process::Process::new(pid).and_then(|process| process.uid()).is_ok_and(|uid| uid == 0)
+}
+
+/// SIGKILLs `pids` and returns the ones that were killed
+pub fn kill(pids: &[i32]) -> Vec<i32> {
+    pids.iter()
+        .copied()
+        .filter(|pid| {
+            let killed = unsafe { libc::kill(*pid, libc::SIGKILL) } == 0;
+            if !killed {
+                eprintln!("Couldn't kill process {pid}: {}", io::Error::last_os_error());
+            }
+            killed
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::CommandExt;
+
+    #[test]
+    fn never_kills_init_or_root() {
+        assert!(is_protected(1));
+        assert_eq!(is_protected(std::process::id() as i32), unsafe { libc::geteuid() } == 0);
+
+        // only root can start something as nobody
+        if unsafe { libc::geteuid() } == 0 {
+            let mut sleeper = std::process::Command::new("sleep").arg("30").uid(65534).gid(65534).spawn().unwrap();
+            assert!(!is_protected(sleeper.id() as i32));
+            assert_eq!(kill(&[sleeper.id() as i32]), vec![sleeper.id() as i32]);
+            sleeper.wait().unwrap();
+        }
+    }
+}
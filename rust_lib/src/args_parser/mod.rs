@@ -2,12 +2,17 @@ pub mod file_scanner;
 pub mod unauthorized_changes_scanner;
 pub mod process_behaviors_analyzer;
 pub mod quarantine;
+pub mod exclusions;
+pub mod reputation;
+pub mod signatures;
+pub mod state;
 
-use std::path::PathBuf;
+use std::{num::NonZeroUsize, path::PathBuf};
 
+use chrono::{DateTime, Local, TimeDelta};
 use clap::{Parser, Subcommand};
 
-use crate::args_parser::{file_scanner::FileCommands, quarantine::ViewMode};
+use crate::args_parser::{exclusions::ExclusionCommands, reputation::ReputationCommands, signatures::SignatureCommands, file_scanner::{Colorblindness, FileCommands, QuarantineMode, ReportFormat}, quarantine::{ListFormat, QuarantineCommands, RetentionAction, RetentionPolicy, ViewMode, SYSTEM_VAULT, parse_age, parse_since, parse_size, parse_until}};
 
 // #[derive(Debug, Clone, Copy)]
 // pub enum Platform {
@@ -34,6 +39,48 @@ use crate::args_parser::{file_scanner::FileCommands, quarantine::ViewMode};
 pub struct Args {
     #[command(subcommand)]
     pub command: Option<Commands>,
+
+    /// Pick colors that stay apart with protanopia, deuteranopia or tritanopia, for every command
+    #[arg(short, long, global = true)]
+    pub colorblindness: Option<Colorblindness>,
+
+    /// Quarantine vault directory, root-only
+    #[arg(long, global = true, default_value = SYSTEM_VAULT)]
+    pub vault: PathBuf,
+
+    /// Retire the oldest quarantined files once the vault is bigger than e.g. `500M` or `2G`.
+    /// Like the other retention flags, overrides the policy stored with `quarantine retention`
+    #[arg(long, global = true, value_parser = parse_size)]
+    pub vault_max_size: Option<u64>,
+
+    /// Retire quarantined files older than e.g. `90d`
+    #[arg(long, global = true, value_parser = parse_age)]
+    pub vault_max_age: Option<TimeDelta>,
+
+    /// What retiring does to a quarantined file: purge (securely delete) or archive. Defaults to purge
+    #[arg(long, global = true)]
+    pub vault_retention: Option<RetentionAction>,
+
+    /// Where archived quarantined files go, root-only. Defaults to /var/lib/sentinel/archive
+    #[arg(long, global = true)]
+    pub vault_archive: Option<PathBuf>,
+}
+
+impl Args {
+    /// `stored` with the retention flags given on the command line on top
+    pub fn retention_policy(&self, stored: RetentionPolicy) -> RetentionPolicy {
+        RetentionPolicy {
+            max_size: self.vault_max_size.or(stored.max_size),
+            max_age: self.vault_max_age.or(stored.max_age),
+            action: self.vault_retention.unwrap_or(stored.action),
+            archive_dir: self.vault_archive.clone().unwrap_or(stored.archive_dir),
+        }
+    }
+
+    /// Whether any retention flag was given
+    pub fn sets_retention(&self) -> bool {
+        self.vault_max_size.is_some() || self.vault_max_age.is_some() || self.vault_retention.is_some() || self.vault_archive.is_some()
+    }
 }
 
 #[derive(Subcommand, Clone)]
@@ -45,6 +92,79 @@ pub enum Commands {
         #[arg(long)]
         show_pred: bool,
 
+        /// Number of worker threads, defaults to the number of CPUs
+        #[arg(short, long)]
+        jobs: Option<NonZeroUsize>,
+
+        /// How many directories deep to scan below --dir. Unlimited by default, older versions stopped
+        /// at 3 (--max-depth 3). /proc, /sys and /dev are never scanned below --dir, however they're reached
+        #[arg(long)]
+        max_depth: Option<usize>,
+
+        /// Follow symlinks instead of skipping them, symlink loops are reported and not followed
+        #[arg(long)]
+        follow_symlinks: bool,
+
+        /// Don't cross into other mounted filesystems
+        #[arg(long)]
+        one_file_system: bool,
+
+        /// Only scan files matching this glob, can be repeated
+        #[arg(long)]
+        include: Vec<String>,
+
+        /// Skip files and directories matching this glob, can be repeated.
+        /// Stored exclusions (see `sentinel exclusions`) always apply
+        #[arg(long)]
+        exclude: Vec<String>,
+
+        /// Quarantine every detection, `batch` after the scan or `immediate` as they're found
+        #[arg(long, num_args = 0..=1, default_missing_value = "batch")]
+        quarantine: Option<QuarantineMode>,
+
+        /// Show what would be quarantined or killed without doing it
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Analyze every file again instead of skipping the ones unchanged since the last scan
+        #[arg(long)]
+        no_cache: bool,
+
+        /// YARA rules to match every file against, a rule file or a directory of .yar/.yara files, can be repeated
+        #[arg(long)]
+        rules: Vec<PathBuf>,
+
+        /// How many archives deep to look into .zip, .tar, .deb, .rpm, gzip, xz and zstd files, 0 doesn't open them
+        #[arg(long, default_value_t = 3)]
+        archive_depth: usize,
+
+        /// Stop unpacking a file past this many decompressed bytes (512M, 2G...)
+        #[arg(long, value_parser = parse_size, default_value = "512M")]
+        archive_max_size: u64,
+
+        /// Stop unpacking a file when something in it decompresses to more than this many times its size
+        #[arg(long, default_value_t = 100)]
+        archive_max_ratio: u64,
+
+        /// Load the elf and exe models, and the so, dll and macho ones if they're there, from this directory
+        /// instead of the models built into sentinel
+        #[arg(long)]
+        model_dir: Option<PathBuf>,
+
+        /// Report files bigger than this (256M, 2G...) as skipped instead of reading them, files that are neither
+        /// executables, scripts nor archives are only read at all for --rules, signatures and hash lists
+        #[arg(long, value_parser = parse_size, default_value = "256M")]
+        max_file_size: u64,
+
+        /// Write a json, ndjson, csv or sarif report of the scan, to --output or to the standard output.
+        /// The console output goes to the standard error then
+        #[arg(long)]
+        report_format: Option<ReportFormat>,
+
+        /// Where the report goes, its format is guessed from the extension without --report-format, json otherwise
+        #[arg(long)]
+        output: Option<PathBuf>,
+
         #[command(subcommand)]
         scan: Option<FileCommands>,
     },
@@ -53,6 +173,7 @@ pub enum Commands {
         path: Option<PathBuf>,
     },
     AnalyzeProcessBehaviors,
+    #[command(subcommand_negates_reqs = true)]
     Quarantine {
         #[arg(required_unless_present="view")]
         file: Option<PathBuf>,
@@ -60,7 +181,41 @@ pub enum Commands {
         #[arg(long)]
         view: bool,
 
-        #[arg(long, required_unless_present="file")]
-        view_mode: Option<ViewMode>
+        /// Database, local (the vault itself) or all, reconciling both. Defaults to all
+        #[arg(long)]
+        view_mode: Option<ViewMode>,
+
+        /// How to print the listing: table, json or csv
+        #[arg(long, default_value = "table")]
+        format: ListFormat,
+
+        /// Only list files quarantined from this date, YYYY-MM-DD or RFC 3339
+        #[arg(long, value_parser = parse_since)]
+        since: Option<DateTime<Local>>,
+
+        /// Only list files quarantined up to this date, included
+        #[arg(long, value_parser = parse_until)]
+        until: Option<DateTime<Local>>,
+
+        /// Only list files whose reason contains this
+        #[arg(long)]
+        reason: Option<String>,
+
+        #[command(subcommand)]
+        action: Option<QuarantineCommands>,
+    },
+    Exclusions {
+        #[command(subcommand)]
+        action: ExclusionCommands,
+    },
+    /// Known bad and known good hashes, checked before the models on every scan
+    Reputation {
+        #[command(subcommand)]
+        action: ReputationCommands,
+    },
+    /// ClamAV signature databases, matched on every scan
+    Signatures {
+        #[command(subcommand)]
+        action: SignatureCommands,
     },
 }
@@ -0,0 +1,316 @@
+use std::{collections::HashMap, fs::{self, File, OpenOptions, Permissions}, io::{self, Read, Write}, os::unix::fs::{OpenOptionsExt, PermissionsExt}, path::{Path, PathBuf}};
+use chacha20poly1305::aead::{OsRng, rand_core::RngCore};
+use chrono::Local;
+use md5::Md5;
+use serde_json::{json, Value};
+use sha1::Sha1;
+use sha2::Digest;
+
+use super::{container, is_root, store_quarantined, user_name, vault, ContainerHeader, QuarantineError, QuarantinedFile, Quarantinizer, VaultKey, KEY_FILE};
+
+/// Bumped whenever the manifest or the sample layout changes
+const BUNDLE_VERSION: u64 = 1;
+const MANIFEST: &str = "manifest.json";
+const SALT_LEN: usize = 16;
+/// A manifest lists a few hundred bytes per sample
+const MAX_MANIFEST_LEN: u64 = 16 << 20;
+
+/// Read when there's no `--password-file`, so scripts don't need a terminal
+pub const PASSWORD_ENV: &str = "SENTINEL_BUNDLE_PASSWORD";
+
+/// A bundle is a plain tar with `manifest.json` and one vault file per sample under `samples/`,
+/// sealed with a key derived from the bundle password. The manifest stays readable so analysts can
+/// see what's in a bundle without the password, the samples don't.
+impl Quarantinizer {
+    /// Writes the samples `ids` into the bundle `out`, re-sealed with `password`. Root only.
+    pub fn export(&self, ids: &[i64], out: &Path, password: &str) -> Result<Vec<QuarantinedFile>, QuarantineError> {
+        if !is_root() {
+            return Err(QuarantineError::NotRoot(self.quarantine_dir.clone()));
+        }
+        let exported = ids.iter()
+            .map(|id| self.find(*id).cloned())
+            .collect::<Result<Vec<QuarantinedFile>, QuarantineError>>()?;
+
+        let mut salt = [0; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let bundle_key = VaultKey::from_password(password.as_bytes(), &salt).map_err(|e| QuarantineError::Io(out.to_path_buf(), e))?;
+
+        let mut samples = vec![];
+        let mut entries = vec![];
+        for (i, qf) in exported.iter().enumerate() {
+            let vault_path = PathBuf::from(&qf.quarantine_path);
+            // the plaintext sample only ever lives in memory
+            let mut sample = vec![];
+            let header = if container::is_container(&vault_path) {
+                let key = Self::key_for(&vault_path)?;
+                let mut sealed = File::open(&vault_path).map_err(|e| QuarantineError::Io(vault_path.clone(), e))?;
+                container::open(&key, &mut sealed, &mut sample).map_err(|e| QuarantineError::Io(vault_path.clone(), e))?
+            } else {
+                legacy_header(qf, &vault_path, &mut sample)?
+            };
+
+            let file = format!("samples/{}.sntlq", i + 1);
+            let mut sealed = vec![];
+            container::seal(&bundle_key, &header, &mut &sample[..], &mut sealed).map_err(|e| QuarantineError::Io(out.to_path_buf(), e))?;
+            samples.push(json!({
+                "file": file,
+                "id": qf.id,
+                "original_path": header.original_path,
+                "reason": header.reason,
+                "score": header.score,
+                "quarantined_date": header.quarantined_date.map(|date| date.to_rfc3339()),
+                "quarantined_by": header.quarantined_by.map(user_name),
+                "size": sample.len(),
+                "sha256": header.sha256,
+                "sha1": hex::encode(Sha1::digest(&sample)),
+                "md5": hex::encode(Md5::digest(&sample)),
+            }));
+            entries.push((file, sealed));
+        }
+
+        let manifest = json!({
+            "version": BUNDLE_VERSION,
+            "created": Local::now().to_rfc3339(),
+            "host": fs::read_to_string("/proc/sys/kernel/hostname").map(|host| host.trim().to_string()).ok(),
+            "kdf": { "algorithm": "argon2id", "salt": hex::encode(salt) },
+            "samples": samples,
+        });
+        entries.insert(0, (MANIFEST.to_string(), serde_json::to_vec_pretty(&manifest).unwrap_or_default()));
+
+        write_bundle(out, &entries).map_err(|e| QuarantineError::Io(out.to_path_buf(), e))?;
+        Ok(exported)
+    }
+
+    /// Quarantines every sample of the bundle into this vault, skipping the ones it already has.
+    /// Imported files count as quarantined by whoever imports them. Root only.
+    pub fn import(&mut self, bundle: &Path, password: &str) -> Result<Vec<QuarantinedFile>, QuarantineError> {
+        if !is_root() {
+            return Err(QuarantineError::NotRoot(self.quarantine_dir.clone()));
+        }
+        let invalid = |message: String| QuarantineError::InvalidBundle(bundle.to_path_buf(), message);
+        let manifest = read_manifest(bundle)?;
+        let manifest = serde_json::from_slice::<Value>(&manifest).map_err(|e| invalid(format!("Invalid {MANIFEST}: {e}")))?;
+        if manifest["version"].as_u64() != Some(BUNDLE_VERSION) {
+            return Err(invalid(format!("Unsupported bundle version {}", manifest["version"])));
+        }
+        let salt = manifest["kdf"]["salt"].as_str()
+            .and_then(|salt| hex::decode(salt).ok())
+            .ok_or_else(|| invalid("No key salt".to_string()))?;
+        let bundle_key = VaultKey::from_password(password.as_bytes(), &salt).map_err(|e| QuarantineError::Io(bundle.to_path_buf(), e))?;
+
+        // only what the manifest lists is read from the bundle, and no more of it than its size
+        let mut listed = HashMap::new();
+        for sample in manifest["samples"].as_array().into_iter().flatten() {
+            let file = sample["file"].as_str().ok_or_else(|| invalid("Sample without a file".to_string()))?;
+            let size = sample["size"].as_u64().ok_or_else(|| invalid(format!("{file} has no size")))?;
+            if listed.insert(file.to_string(), container::max_sealed_len(size)).is_some() {
+                return Err(invalid(format!("{file} is listed twice")));
+            }
+        }
+
+        let mut imported = vec![];
+        let mut archive = tar::Archive::new(File::open(bundle).map_err(|e| QuarantineError::Io(bundle.to_path_buf(), e))?);
+        let entries = archive.entries().map_err(|e| QuarantineError::Io(bundle.to_path_buf(), e))?;
+        for entry in entries {
+            let mut entry = entry.map_err(|e| QuarantineError::Io(bundle.to_path_buf(), e))?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let file = entry.path().map_err(|e| QuarantineError::Io(bundle.to_path_buf(), e))?.to_string_lossy().to_string();
+            let Some(max_len) = listed.remove(&file) else {
+                continue;
+            };
+            if entry.size() > max_len {
+                return Err(invalid(format!("{file} is bigger than the manifest says")));
+            }
+
+            // decrypted straight from the bundle, the sealed sample is never held whole
+            let mut plaintext = vec![];
+            let header = container::open(&bundle_key, &mut (&mut entry).take(max_len), &mut plaintext)
+                .map_err(|e| invalid(format!("{file}: {e}. Is the password right?")))?;
+            if let Some(qf) = self.import_sample(bundle, header, &plaintext)? {
+                imported.push(qf);
+            }
+        }
+        if let Some(file) = listed.keys().next() {
+            return Err(invalid(format!("{file} is missing")));
+        }
+        if !imported.is_empty() {
+            self.make_room();
+        }
+        Ok(imported)
+    }
+
+    /// Seals one sample into the vault and records it, unless the same sample from the same path is already there.
+    /// It's flagged as imported, so `restore` never puts it where the bundle says it came from.
+    fn import_sample(&mut self, bundle: &Path, mut header: ContainerHeader, sample: &[u8]) -> Result<Option<QuarantinedFile>, QuarantineError> {
+        let known = self.quarantined_files.iter()
+            .any(|qf| qf.sha256.as_deref() == Some(header.sha256.as_str()) && qf.original_path == header.original_path);
+        if known {
+            println!("{:?} is already quarantined", header.original_path);
+            return Ok(None);
+        }
+
+        let db = self.db.as_ref().ok_or(QuarantineError::NoDatabase)?;
+        self.open_vault()?;
+        let key = VaultKey::load_or_create(&self.quarantine_dir.join(KEY_FILE))
+            .map_err(|e| QuarantineError::Io(self.quarantine_dir.join(KEY_FILE), e))?;
+
+        header.quarantined_by = Some(self.requester);
+        header.imported = true;
+        let vault_path = self.vault_path(Path::new(&header.original_path))
+            .ok_or_else(|| QuarantineError::InvalidBundle(bundle.to_path_buf(), format!("{:?} isn't a file path", header.original_path)))?;
+        let partial = vault::partial_path(&vault_path);
+        let sealed = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&partial)
+            .and_then(|mut sealed| {
+                container::seal(&key, &header, &mut &sample[..], &mut sealed)?;
+                sealed.sync_all()
+            })
+            .and_then(|()| fs::rename(&partial, &vault_path));
+        if let Err(e) = sealed {
+            let _ = fs::remove_file(&partial);
+            return Err(QuarantineError::Io(vault_path, e));
+        }
+
+        let mut qf = QuarantinedFile {
+            id: None,
+            original_path: header.original_path,
+            quarantine_path: vault_path.to_string_lossy().to_string(),
+            reason: header.reason,
+            quarantined_date: header.quarantined_date,
+            metadata: Some(header.metadata),
+            sha256: Some(header.sha256),
+            quarantined_by: header.quarantined_by,
+            score: header.score,
+        };
+        match store_quarantined(db, &qf) {
+            Ok(id) => qf.id = Some(id),
+            Err(e) => {
+                let _ = fs::remove_file(&vault_path);
+                return Err(e.into());
+            }
+        }
+        if let Err(e) = fs::set_permissions(&vault_path, Permissions::from_mode(0o000)) {
+            eprintln!("Couldn't lock {:?}: {e}", vault_path);
+        }
+        self.quarantined_files.push(qf.clone());
+        Ok(Some(qf))
+    }
+}
+
+/// The bundle password from `password_file`, `$SENTINEL_BUNDLE_PASSWORD` or the terminal.
+/// `confirm` asks twice on the terminal, for new bundles.
+pub fn read_password(password_file: Option<&Path>, confirm: bool) -> Result<String, QuarantineError> {
+    let password = match password_file {
+        Some(path) => fs::read_to_string(path)
+            .map(|password| password.trim_end_matches(['\n', '\r']).to_string())
+            .map_err(|e| QuarantineError::Io(path.to_path_buf(), e))?,
+        None => match std::env::var(PASSWORD_ENV) {
+            Ok(password) => password,
+            Err(_) => {
+                let prompt = |prompt: &str| rpassword::prompt_password(prompt)
+                    .map_err(|e| QuarantineError::Password(format!("Couldn't read the bundle password, set ${PASSWORD_ENV} or use --password-file: {e}")));
+                let password = prompt("Bundle password: ")?;
+                if confirm && prompt("Again: ")? != password {
+                    return Err(QuarantineError::Password("Passwords don't match".to_string()));
+                }
+                password
+            }
+        },
+    };
+    if password.is_empty() {
+        return Err(QuarantineError::Password("The bundle password is empty".to_string()));
+    }
+    Ok(password)
+}
+
+/// The header a vault file from before encryption would have had, from its record
+fn legacy_header(qf: &QuarantinedFile, vault_path: &Path, sample: &mut Vec<u8>) -> Result<ContainerHeader, QuarantineError> {
+    File::open(vault_path)
+        .and_then(|mut file| file.read_to_end(sample))
+        .map_err(|e| QuarantineError::Io(vault_path.to_path_buf(), e))?;
+    Ok(ContainerHeader {
+        sha256: container::sha256(&mut &sample[..]).map_err(|e| QuarantineError::Io(vault_path.to_path_buf(), e))?,
+        original_path: qf.original_path.clone(),
+        reason: qf.reason.clone(),
+        quarantined_date: qf.quarantined_date,
+        metadata: qf.metadata.clone().unwrap_or_default(),
+        quarantined_by: qf.quarantined_by,
+        score: qf.score,
+        imported: false,
+    })
+}
+
+fn write_bundle(out: &Path, entries: &[(String, Vec<u8>)]) -> io::Result<()> {
+    let partial = vault::partial_path(out);
+    let written = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&partial)
+        .and_then(|file| {
+            let mut builder = tar::Builder::new(file);
+            for (name, data) in entries {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(data.len() as u64);
+                header.set_mode(0o600);
+                header.set_mtime(Local::now().timestamp() as u64);
+                builder.append_data(&mut header, name, &data[..])?;
+            }
+            let mut file = builder.into_inner()?;
+            file.flush()?;
+            file.sync_all()
+        });
+    match written {
+        Ok(()) => fs::rename(&partial, out),
+        Err(e) => {
+            let _ = fs::remove_file(&partial);
+            Err(e)
+        }
+    }
+}
+
+/// The manifest, whichever entry of the bundle it is
+fn read_manifest(bundle: &Path) -> Result<Vec<u8>, QuarantineError> {
+    let io_error = |e| QuarantineError::Io(bundle.to_path_buf(), e);
+    let mut archive = tar::Archive::new(File::open(bundle).map_err(io_error)?);
+    for entry in archive.entries().map_err(io_error)? {
+        let mut entry = entry.map_err(io_error)?;
+        if !entry.header().entry_type().is_file() || entry.path().map_err(io_error)? != Path::new(MANIFEST) {
+            continue;
+        }
+        if entry.size() > MAX_MANIFEST_LEN {
+            return Err(QuarantineError::InvalidBundle(bundle.to_path_buf(), format!("{MANIFEST} is over {MAX_MANIFEST_LEN} bytes")));
+        }
+        let mut manifest = vec![];
+        entry.read_to_end(&mut manifest).map_err(io_error)?;
+        return Ok(manifest);
+    }
+    Err(QuarantineError::InvalidBundle(bundle.to_path_buf(), format!("No {MANIFEST}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_manifest_wherever_it_is() {
+        let out = std::env::temp_dir().join(format!("sentinel_bundle_{}.tar", std::process::id()));
+        let entries = vec![("samples/1.sntlq".to_string(), vec![0, 1, 2]), (MANIFEST.to_string(), b"{}".to_vec())];
+        write_bundle(&out, &entries).unwrap();
+        assert_eq!(read_manifest(&out).unwrap(), b"{}");
+        fs::remove_file(&out).unwrap();
+
+        write_bundle(&out, &entries[..1]).unwrap();
+        assert!(matches!(read_manifest(&out), Err(QuarantineError::InvalidBundle(..))));
+        fs::remove_file(&out).unwrap();
+
+        write_bundle(&out, &[(MANIFEST.to_string(), vec![b' '; MAX_MANIFEST_LEN as usize + 1])]).unwrap();
+        assert!(matches!(read_manifest(&out), Err(QuarantineError::InvalidBundle(..))));
+        fs::remove_file(&out).unwrap();
+    }
+}
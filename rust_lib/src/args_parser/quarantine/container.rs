@@ -0,0 +1,374 @@
+use std::{fs::{self, OpenOptions}, io::{self, Read, Write}, os::unix::fs::OpenOptionsExt, path::Path};
+use chacha20poly1305::{aead::{Aead, KeyInit, OsRng, Payload, rand_core::RngCore}, ChaCha20Poly1305, Nonce};
+use chrono::{DateTime, Local};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use super::FileMetadata;
+
+/// Vault files start with this, anything else in the vault is a plain file from older versions
+pub const MAGIC: &[u8; 8] = b"SNTLQ001";
+
+const CHUNK_LEN: usize = 64 * 1024;
+const TAG_LEN: usize = 16;
+const NONCE_PREFIX_LEN: usize = 7;
+/// The header is a few hundred bytes of JSON, plus the extended attributes. Longer ones aren't allocated
+const MAX_HEADER_LEN: usize = 1 << 20;
+
+/// The per-install key every vault file is encrypted with
+pub struct VaultKey(ChaCha20Poly1305);
+
+impl VaultKey {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        ChaCha20Poly1305::new_from_slice(&fs::read(path)?)
+            .map(Self)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("{:?} isn't a vault key", path)))
+    }
+
+    /// Derives a key from a password with Argon2id, for bundles that leave the machine
+    pub fn from_password(password: &[u8], salt: &[u8]) -> io::Result<Self> {
+        let mut key = [0; 32];
+        argon2::Argon2::default()
+            .hash_password_into(password, salt, &mut key)
+            .map_err(|e| invalid(&format!("Couldn't derive a key: {e}")))?;
+        Ok(Self(ChaCha20Poly1305::new(&key.into())))
+    }
+
+    /// Reads the key at `path`, or generates it (mode 600) on first use
+    pub fn load_or_create(path: &Path) -> io::Result<Self> {
+        match Self::load(path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let key = ChaCha20Poly1305::generate_key(&mut OsRng);
+                let mut file = OpenOptions::new().write(true).create_new(true).mode(0o600).open(path)?;
+                file.write_all(&key)?;
+                file.sync_all()?;
+                Ok(Self(ChaCha20Poly1305::new(&key)))
+            }
+            key => key,
+        }
+    }
+}
+
+/// What a vault file says about its sample, readable without the key
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContainerHeader {
+    pub sha256: String,
+    pub original_path: String,
+    pub reason: String,
+    pub quarantined_date: Option<DateTime<Local>>,
+    pub metadata: FileMetadata,
+    pub quarantined_by: Option<u32>,
+    /// What the model scored it, None when no model did
+    pub score: Option<f32>,
+    /// Came from an export bundle, so its path and metadata are another machine's
+    pub imported: bool,
+}
+
+impl ContainerHeader {
+    fn to_json(&self) -> Vec<u8> {
+        let metadata = &self.metadata;
+        json!({
+            "sha256": self.sha256,
+            "original_path": self.original_path,
+            "reason": self.reason,
+            "quarantined_date": self.quarantined_date.map(|date| date.to_rfc3339()),
+            "mode": metadata.mode,
+            "uid": metadata.uid,
+            "gid": metadata.gid,
+            "size": metadata.size,
+            "accessed": metadata.accessed,
+            "modified": metadata.modified,
+            "xattrs": serde_json::from_str::<Value>(&metadata.xattrs_to_json()).unwrap_or_default(),
+            "quarantined_by": self.quarantined_by,
+            "score": self.score,
+            "imported": self.imported,
+        })
+        .to_string()
+        .into_bytes()
+    }
+
+    fn from_json(json: &[u8]) -> io::Result<Self> {
+        let header = serde_json::from_slice::<Value>(json).map_err(|e| invalid(&format!("Invalid header: {e}")))?;
+        let string = |key: &str| header[key].as_str().map(str::to_string).ok_or_else(|| invalid(&format!("Header has no {key}")));
+        let number = |key: &str| header[key].as_i64().ok_or_else(|| invalid(&format!("Header has no {key}")));
+
+        Ok(Self {
+            sha256: string("sha256")?,
+            original_path: string("original_path")?,
+            reason: string("reason")?,
+            quarantined_date: header["quarantined_date"].as_str()
+                .and_then(|date| DateTime::parse_from_rfc3339(date).ok())
+                .map(|date| date.with_timezone(&Local)),
+            metadata: FileMetadata {
+                mode: number("mode")? as u32,
+                uid: number("uid")? as u32,
+                gid: number("gid")? as u32,
+                size: number("size")? as u64,
+                accessed: number("accessed")?,
+                modified: number("modified")?,
+                xattrs: FileMetadata::xattrs_from_json(&header["xattrs"].to_string()),
+            },
+            quarantined_by: header["quarantined_by"].as_u64().map(|uid| uid as u32),
+            score: header["score"].as_f64().map(|score| score as f32),
+            imported: header["imported"].as_bool().unwrap_or(false),
+        })
+    }
+
+    fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let json = self.to_json();
+        // read_raw_header wouldn't take it back
+        if json.len() > MAX_HEADER_LEN {
+            return Err(invalid(&format!("Vault header of {} bytes is over the {MAX_HEADER_LEN} bytes limit", json.len())));
+        }
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend((json.len() as u32).to_be_bytes());
+        bytes.extend(json);
+        Ok(bytes)
+    }
+}
+
+/// SHA-256 of everything `reader` has left, as hex
+pub fn sha256<R: Read>(reader: &mut R) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; CHUNK_LEN];
+    loop {
+        match reader.read(&mut buf)? {
+            0 => break,
+            n => hasher.update(&buf[..n]),
+        }
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Writes the header, then `reader` encrypted in 64 KiB chunks.
+/// Every chunk is authenticated along with the header, the last one is flagged so a
+/// truncated vault file doesn't decrypt.
+pub fn seal<R: Read, W: Write>(key: &VaultKey, header: &ContainerHeader, reader: &mut R, writer: &mut W) -> io::Result<()> {
+    let header = header.to_bytes()?;
+    writer.write_all(&header)?;
+
+    let mut prefix = [0; NONCE_PREFIX_LEN];
+    OsRng.fill_bytes(&mut prefix);
+    writer.write_all(&prefix)?;
+
+    let mut chunk = vec![0; CHUNK_LEN];
+    let mut next = vec![0; CHUNK_LEN];
+    let mut len = read_full(reader, &mut chunk)?;
+    for counter in 0.. {
+        let next_len = read_full(reader, &mut next)?;
+        let last = next_len == 0;
+
+        let sealed = key.0
+            .encrypt(&nonce(&prefix, counter, last), Payload { msg: &chunk[..len], aad: &header })
+            .map_err(|_| invalid("Couldn't encrypt"))?;
+        writer.write_all(&sealed)?;
+
+        if last {
+            break;
+        }
+        std::mem::swap(&mut chunk, &mut next);
+        len = next_len;
+    }
+    Ok(())
+}
+
+/// The most a vault file of a `len` bytes sample can take, header included
+pub fn max_sealed_len(len: u64) -> u64 {
+    (MAGIC.len() + 4 + MAX_HEADER_LEN + NONCE_PREFIX_LEN) as u64 + len + (len / CHUNK_LEN as u64 + 1) * TAG_LEN as u64
+}
+
+/// Reads only the header, no key needed
+pub fn read_header<R: Read>(reader: &mut R) -> io::Result<ContainerHeader> {
+    read_raw_header(reader).and_then(|raw| ContainerHeader::from_json(&raw[MAGIC.len() + 4..]))
+}
+
+/// Decrypts the sample into `writer` and checks it against the SHA-256 in the header.
+/// On error, whatever was already written to `writer` must be thrown away.
+pub fn open<R: Read, W: Write>(key: &VaultKey, reader: &mut R, writer: &mut W) -> io::Result<ContainerHeader> {
+    let raw_header = read_raw_header(reader)?;
+    open_chunks(key, &raw_header, reader, |opened, _, _| writer.write_all(opened))
+}
+
+/// Re-encrypts a vault file from `from` to `to` one chunk at a time, the sample is never written out in the clear.
+/// Checked like `open`, on error whatever was already written to `writer` must be thrown away.
+pub fn reseal<R: Read, W: Write>(from: &VaultKey, to: &VaultKey, reader: &mut R, writer: &mut W) -> io::Result<ContainerHeader> {
+    let raw_header = read_raw_header(reader)?;
+    writer.write_all(&raw_header)?;
+
+    let mut prefix = [0; NONCE_PREFIX_LEN];
+    OsRng.fill_bytes(&mut prefix);
+    writer.write_all(&prefix)?;
+
+    open_chunks(from, &raw_header, reader, |opened, counter, last| {
+        let sealed = to.0
+            .encrypt(&nonce(&prefix, counter, last), Payload { msg: opened, aad: &raw_header })
+            .map_err(|_| invalid("Couldn't encrypt"))?;
+        writer.write_all(&sealed)
+    })
+}
+
+/// Decrypts the chunks after `raw_header`, hands each one to `f` along with its counter and whether it's
+/// the last one, then checks the sample against the SHA-256 in the header
+fn open_chunks<R, F>(key: &VaultKey, raw_header: &[u8], reader: &mut R, mut f: F) -> io::Result<ContainerHeader>
+where
+    R: Read,
+    F: FnMut(&[u8], u32, bool) -> io::Result<()>,
+{
+    let header = ContainerHeader::from_json(&raw_header[MAGIC.len() + 4..])?;
+
+    let mut prefix = [0; NONCE_PREFIX_LEN];
+    reader.read_exact(&mut prefix)?;
+
+    let mut hasher = Sha256::new();
+    let mut chunk = vec![0; CHUNK_LEN + TAG_LEN];
+    let mut next = vec![0; CHUNK_LEN + TAG_LEN];
+    let mut len = read_full(reader, &mut chunk)?;
+    for counter in 0.. {
+        let next_len = read_full(reader, &mut next)?;
+        let last = next_len == 0;
+
+        let opened = key.0
+            .decrypt(&nonce(&prefix, counter, last), Payload { msg: &chunk[..len], aad: raw_header })
+            .map_err(|_| invalid("Vault file was tampered with, truncated or sealed with another key"))?;
+        hasher.update(&opened);
+        f(&opened, counter, last)?;
+
+        if last {
+            break;
+        }
+        std::mem::swap(&mut chunk, &mut next);
+        len = next_len;
+    }
+
+    if hex::encode(hasher.finalize()) != header.sha256 {
+        return Err(invalid("Decrypted sample doesn't match its SHA-256"));
+    }
+    Ok(header)
+}
+
+/// Whether `path` is a vault file rather than a plain one
+pub fn is_container(path: &Path) -> bool {
+    let mut magic = [0; MAGIC.len()];
+    fs::File::open(path).and_then(|mut file| file.read_exact(&mut magic)).is_ok() && &magic == MAGIC
+}
+
+fn read_raw_header<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut raw = vec![0; MAGIC.len() + 4];
+    reader.read_exact(&mut raw)?;
+    if &raw[..MAGIC.len()] != MAGIC {
+        return Err(invalid("Not a vault file"));
+    }
+
+    let json_len = u32::from_be_bytes(raw[MAGIC.len()..].try_into().expect("4 bytes")) as usize;
+    if json_len > MAX_HEADER_LEN {
+        return Err(invalid(&format!("Vault header of {json_len} bytes is over the {MAX_HEADER_LEN} bytes limit")));
+    }
+    raw.resize(MAGIC.len() + 4 + json_len, 0);
+    reader.read_exact(&mut raw[MAGIC.len() + 4..])?;
+    Ok(raw)
+}
+
+fn nonce(prefix: &[u8; NONCE_PREFIX_LEN], counter: u32, last: bool) -> Nonce {
+    let mut nonce = [0; 12];
+    nonce[..NONCE_PREFIX_LEN].copy_from_slice(prefix);
+    nonce[NONCE_PREFIX_LEN..11].copy_from_slice(&counter.to_be_bytes());
+    nonce[11] = last as u8;
+    nonce.into()
+}
+
+/// Fills as much of `buf` as the reader has, only short at the end
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match reader.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(len)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key() -> VaultKey {
+        VaultKey(ChaCha20Poly1305::new(&ChaCha20Poly1305::generate_key(&mut OsRng)))
+    }
+
+    fn header(sample: &[u8]) -> ContainerHeader {
+        ContainerHeader {
+            sha256: sha256(&mut &sample[..]).unwrap(),
+            original_path: "/tmp/payload".to_string(),
+            reason: "model/elf/model.ubj scored 0.912345 (threshold 0.49)".to_string(),
+            quarantined_date: None,
+            metadata: FileMetadata { mode: 0o100755, size: sample.len() as u64, ..Default::default() },
+            quarantined_by: Some(1000),
+            score: Some(0.912345),
+            imported: false,
+        }
+    }
+
+    #[test]
+    fn round_trips_across_chunks() {
+        let key = key();
+        for sample in [vec![], b"\x7fELF".to_vec(), (0..CHUNK_LEN * 2).map(|i| i as u8).collect()] {
+            let mut sealed = vec![];
+            seal(&key, &header(&sample), &mut &sample[..], &mut sealed).unwrap();
+            assert!(!sealed.windows(4).any(|w| w == b"\x7fELF"));
+            assert!(sealed.len() as u64 <= max_sealed_len(sample.len() as u64));
+
+            let mut opened = vec![];
+            assert_eq!(open(&key, &mut &sealed[..], &mut opened).unwrap(), header(&sample));
+            assert_eq!(opened, sample);
+            assert_eq!(read_header(&mut &sealed[..]).unwrap(), header(&sample));
+        }
+    }
+
+    #[test]
+    fn rejects_tampering_truncation_and_other_keys() {
+        let key = key();
+        let sample = (0..CHUNK_LEN + 10).map(|i| i as u8).collect::<Vec<u8>>();
+        let mut sealed = vec![];
+        seal(&key, &header(&sample), &mut &sample[..], &mut sealed).unwrap();
+
+        let mut flipped = sealed.clone();
+        *flipped.last_mut().unwrap() ^= 1;
+        assert!(open(&key, &mut &flipped[..], &mut vec![]).is_err());
+        assert!(open(&key, &mut &sealed[..sealed.len() - 10 - TAG_LEN], &mut vec![]).is_err());
+        assert!(open(&self::key(), &mut &sealed[..], &mut vec![]).is_err());
+    }
+
+    #[test]
+    fn refuses_oversized_headers() {
+        let mut raw = MAGIC.to_vec();
+        raw.extend(u32::MAX.to_be_bytes());
+        assert_eq!(read_raw_header(&mut &raw[..]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let mut header = header(b"");
+        header.reason = "x".repeat(MAX_HEADER_LEN);
+        assert_eq!(seal(&key(), &header, &mut &b""[..], &mut vec![]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn reseals_to_another_key() {
+        let (from, to) = (key(), key());
+        let sample = (0..CHUNK_LEN * 2 + 10).map(|i| i as u8).collect::<Vec<u8>>();
+        let mut sealed = vec![];
+        seal(&from, &header(&sample), &mut &sample[..], &mut sealed).unwrap();
+
+        let mut resealed = vec![];
+        assert_eq!(reseal(&from, &to, &mut &sealed[..], &mut resealed).unwrap(), header(&sample));
+        assert!(open(&from, &mut &resealed[..], &mut vec![]).is_err());
+        let mut opened = vec![];
+        open(&to, &mut &resealed[..], &mut opened).unwrap();
+        assert_eq!(opened, sample);
+        assert!(reseal(&to, &from, &mut &sealed[..], &mut vec![]).is_err());
+    }
+}
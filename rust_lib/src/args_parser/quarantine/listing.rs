@@ -0,0 +1,315 @@
+use std::{collections::{HashMap, HashSet}, ffi::CStr, fmt};
+use chrono::{DateTime, Local, NaiveDate, TimeDelta};
+use serde_json::json;
+
+use super::{is_root, QuarantinedFile, Quarantinizer, ViewMode};
+
+/// How `quarantine --view` prints the files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ListFormat {
+    #[default]
+    Table,
+    Json,
+    Csv,
+}
+
+impl std::str::FromStr for ListFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "table" => Ok(ListFormat::Table),
+            "json" => Ok(ListFormat::Json),
+            "csv" => Ok(ListFormat::Csv),
+            _ => Err(
+                format!("Invalid format: {s}.
+                    Use [table, json, csv]"))
+        }
+    }
+}
+
+/// Whether the database and the vault agree about a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VaultStatus {
+    /// Recorded and in the vault
+    Ok,
+    /// Recorded, but its vault file is gone
+    Missing,
+    /// In the vault, but nothing in the database knows about it
+    Unrecorded,
+    /// Only root can look into the vault
+    Unchecked,
+}
+
+impl fmt::Display for VaultStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            VaultStatus::Ok => "ok",
+            VaultStatus::Missing => "missing",
+            VaultStatus::Unrecorded => "unrecorded",
+            VaultStatus::Unchecked => "unchecked",
+        };
+        f.write_str(status)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ListedFile {
+    pub file: QuarantinedFile,
+    pub status: VaultStatus,
+}
+
+/// `--since`, `--until` and `--reason`
+#[derive(Debug, Clone, Default)]
+pub struct ListFilter {
+    pub since: Option<DateTime<Local>>,
+    /// Exclusive
+    pub until: Option<DateTime<Local>>,
+    /// Case insensitive substring of the reason
+    pub reason: Option<String>,
+}
+
+impl ListFilter {
+    /// Files without a date only pass without a date range
+    pub fn matches(&self, file: &QuarantinedFile) -> bool {
+        let in_range = match file.quarantined_date {
+            Some(date) => self.since.is_none_or(|since| date >= since) && self.until.is_none_or(|until| date < until),
+            None => self.since.is_none() && self.until.is_none(),
+        };
+        let reason_matches = self.reason.as_ref()
+            .is_none_or(|reason| file.reason.to_lowercase().contains(&reason.to_lowercase()));
+        in_range && reason_matches
+    }
+}
+
+/// `YYYY-MM-DD` for the start of that day, or an RFC 3339 date
+pub fn parse_since(s: &str) -> Result<DateTime<Local>, String> {
+    parse_date_arg(s, TimeDelta::zero())
+}
+
+/// `YYYY-MM-DD` includes that whole day, or an RFC 3339 date
+pub fn parse_until(s: &str) -> Result<DateTime<Local>, String> {
+    parse_date_arg(s, TimeDelta::days(1))
+}
+
+fn parse_date_arg(s: &str, day_offset: TimeDelta) -> Result<DateTime<Local>, String> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(s) {
+        return Ok(date.with_timezone(&Local));
+    }
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .and_then(|date| date.and_local_timezone(Local).earliest())
+        .map(|date| date + day_offset)
+        .ok_or_else(|| format!("Invalid date: {s:?}. Use YYYY-MM-DD or RFC 3339"))
+}
+
+impl Quarantinizer {
+    /// The database, the vault, or both reconciled against each other.
+    /// Unprivileged users can't look into the vault and only get their own records.
+    pub fn list(&self, view_mode: ViewMode) -> Result<Vec<ListedFile>, String> {
+        let vault_files = match (&view_mode, is_root()) {
+            (ViewMode::Database, _) | (ViewMode::All, false) => None,
+            _ => {
+                let mut vault_files = self.get_local_files(&self.quarantine_dir)?;
+                vault_files.sort_by(|a, b| a.quarantine_path.cmp(&b.quarantine_path));
+                Some(vault_files)
+            }
+        };
+        let recorded = match view_mode {
+            ViewMode::Local => None,
+            _ => Some(self.get_quarantined_files()?),
+        };
+
+        let listed = match (recorded, vault_files) {
+            (Some(recorded), None) => recorded.into_iter()
+                .map(|file| ListedFile { file, status: VaultStatus::Unchecked })
+                .collect(),
+            (None, Some(vault_files)) => {
+                let recorded = self.get_quarantined_files()?.into_iter()
+                    .map(|file| (file.quarantine_path, file.id))
+                    .collect::<HashMap<String, Option<i64>>>();
+                vault_files.into_iter()
+                    .map(|mut file| match recorded.get(&file.quarantine_path) {
+                        Some(id) => {
+                            file.id = *id;
+                            ListedFile { file, status: VaultStatus::Ok }
+                        }
+                        None => ListedFile { file, status: VaultStatus::Unrecorded },
+                    })
+                    .collect()
+            }
+            (Some(recorded), Some(vault_files)) => {
+                let in_vault = vault_files.iter()
+                    .map(|file| file.quarantine_path.clone())
+                    .collect::<HashSet<String>>();
+                let recorded_paths = recorded.iter()
+                    .map(|file| file.quarantine_path.clone())
+                    .collect::<HashSet<String>>();
+
+                let mut listed = recorded.into_iter()
+                    .map(|file| {
+                        // files of older vaults aren't in this one, look for them where they were put
+                        let found = in_vault.contains(&file.quarantine_path) || std::path::Path::new(&file.quarantine_path).exists();
+                        let status = if found { VaultStatus::Ok } else { VaultStatus::Missing };
+                        ListedFile { file, status }
+                    })
+                    .collect::<Vec<ListedFile>>();
+                listed.extend(vault_files.into_iter()
+                    .filter(|file| !recorded_paths.contains(&file.quarantine_path))
+                    .map(|file| ListedFile { file, status: VaultStatus::Unrecorded }));
+                listed
+            }
+            (None, None) => vec![],
+        };
+        Ok(listed)
+    }
+}
+
+pub fn render(files: &[ListedFile], format: ListFormat) -> String {
+    match format {
+        ListFormat::Table => render_table(files),
+        ListFormat::Json => {
+            let files = files.iter().map(|listed| {
+                let file = &listed.file;
+                json!({
+                    "id": file.id,
+                    "status": listed.status.to_string(),
+                    "original_path": file.original_path,
+                    "quarantine_path": file.quarantine_path,
+                    "reason": file.reason,
+                    "quarantined_date": file.quarantined_date.map(|date| date.to_rfc3339()),
+                    "size": file.metadata.as_ref().map(|metadata| metadata.size),
+                    "sha256": file.sha256,
+                    "quarantined_by": file.quarantined_by.map(user_name),
+                })
+            })
+            .collect::<Vec<serde_json::Value>>();
+            serde_json::to_string_pretty(&files).unwrap_or_default() + "\n"
+        }
+        ListFormat::Csv => render_csv(files),
+    }
+}
+
+fn render_csv(files: &[ListedFile]) -> String {
+    let mut csv = csv::Writer::from_writer(vec![]);
+    let header = ["id", "status", "original_path", "quarantine_path", "reason", "quarantined_date", "size", "sha256", "quarantined_by"];
+    let _ = csv.write_record(header);
+    for listed in files {
+        let file = &listed.file;
+        let record = [
+            file.id.map(|id| id.to_string()).unwrap_or_default(),
+            listed.status.to_string(),
+            file.original_path.clone(),
+            file.quarantine_path.clone(),
+            file.reason.clone(),
+            file.quarantined_date.map(|date| date.to_rfc3339()).unwrap_or_default(),
+            file.metadata.as_ref().map(|metadata| metadata.size.to_string()).unwrap_or_default(),
+            file.sha256.clone().unwrap_or_default(),
+            file.quarantined_by.map(user_name).unwrap_or_default(),
+        ];
+        let _ = csv.write_record(&record);
+    }
+    // writing to a Vec can't fail, and every field came from a String
+    String::from_utf8(csv.into_inner().unwrap_or_default()).unwrap_or_default()
+}
+
+fn render_table(files: &[ListedFile]) -> String {
+    let rows = files.iter().map(|listed| {
+        let file = &listed.file;
+        [
+            file.id.map(|id| id.to_string()).unwrap_or_else(|| "-".to_string()),
+            listed.status.to_string(),
+            file.quarantined_date.map(|date| date.format("%Y-%m-%d %H:%M").to_string()).unwrap_or_else(|| "-".to_string()),
+            file.metadata.as_ref().map(|metadata| human_size(metadata.size)).unwrap_or_else(|| "-".to_string()),
+            file.sha256.as_ref().map(|sha256| sha256[..12.min(sha256.len())].to_string()).unwrap_or_else(|| "-".to_string()),
+            file.quarantined_by.map(user_name).unwrap_or_else(|| "-".to_string()),
+            file.original_path.clone(),
+            file.reason.clone(),
+        ]
+    })
+    .collect::<Vec<[String; 8]>>();
+
+    let header = ["ID", "STATUS", "DATE", "SIZE", "SHA256", "BY", "ORIGINAL PATH", "REASON"].map(str::to_string);
+    let mut widths = header.clone().map(|column| column.chars().count());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let mut table = String::new();
+    for row in std::iter::once(&header).chain(&rows) {
+        let line = row.iter().zip(widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect::<Vec<String>>()
+            .join("  ");
+        table += line.trim_end();
+        table += "\n";
+    }
+    table
+}
+
+pub(super) fn human_size(size: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = size as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{size} {}", UNITS[unit]),
+        _ => format!("{size:.1} {}", UNITS[unit]),
+    }
+}
+
+/// The user's login name, or the uid if it has none
+pub fn user_name(uid: u32) -> String {
+    // getpwuid's buffer is shared, copy the name out right away
+    let passwd = unsafe { libc::getpwuid(uid) };
+    if passwd.is_null() {
+        return uid.to_string();
+    }
+    unsafe { CStr::from_ptr((*passwd).pw_name) }.to_string_lossy().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn file(reason: &str, date: &str) -> QuarantinedFile {
+        QuarantinedFile {
+            quarantined_date: Some(parse_since(date).unwrap()),
+            ..QuarantinedFile::new(Path::new("/tmp/a,b"), reason)
+        }
+    }
+
+    #[test]
+    fn filters_by_date_range_and_reason() {
+        let filter = ListFilter {
+            since: Some(parse_since("2026-01-01").unwrap()),
+            until: Some(parse_until("2026-01-31").unwrap()),
+            reason: Some("ENTROPY".to_string()),
+        };
+        assert!(filter.matches(&file("high entropy (packed or encrypted)", "2026-01-15T12:00:00+00:00")));
+        assert!(!filter.matches(&file("high entropy (packed or encrypted)", "2026-02-10T12:00:00+00:00")));
+        assert!(!filter.matches(&file("model/elf/model.ubj scored 0.9", "2026-01-10")));
+        assert!(ListFilter::default().matches(&QuarantinedFile { quarantined_date: None, ..file("", "2026-01-10") }));
+    }
+
+    #[test]
+    fn csv_quotes_fields() {
+        let listed = ListedFile { file: file("say \"hi\"\nthen leave", "2026-01-10"), status: VaultStatus::Unchecked };
+        let csv = render(&[listed], ListFormat::Csv);
+        assert!(csv.starts_with("id,status,original_path,quarantine_path,reason,"));
+
+        let mut reader = csv::Reader::from_reader(csv.as_bytes());
+        let records = reader.records().collect::<Result<Vec<csv::StringRecord>, csv::Error>>().unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(&records[0][1], "unchecked");
+        assert_eq!(&records[0][2], "/tmp/a,b");
+        assert_eq!(&records[0][4], "say \"hi\"\nthen leave");
+    }
+}
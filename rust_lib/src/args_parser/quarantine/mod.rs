@@ -1,6 +1,26 @@
-use std::{env::home_dir, fs::{self, File, OpenOptions}, io::ErrorKind, os::unix::fs::PermissionsExt, path::{Path, PathBuf}};
-use chrono::{DateTime, Local};
-use rusqlite::{Connection, ffi::Error};
+use std::{fmt, fs::{self, Permissions}, io, os::unix::fs::{MetadataExt, PermissionsExt}, path::{Path, PathBuf}};
+use chrono::{DateTime, Local, TimeDelta};
+use clap::Subcommand;
+use rusqlite::Connection;
+
+mod bundle;
+mod container;
+mod listing;
+mod retention;
+mod vault;
+mod verify;
+pub use bundle::{read_password, PASSWORD_ENV};
+pub use container::{ContainerHeader, VaultKey};
+pub use listing::{ListFilter, ListFormat, ListedFile, VaultStatus, parse_since, parse_until, render, user_name};
+pub use retention::{parse_size, Retired, RetentionAction, RetentionPolicy, SYSTEM_ARCHIVE};
+pub use vault::FileMetadata;
+pub use verify::{Finding, Verification};
+
+/// Where quarantined files go unless `--vault` says otherwise. Only root can get in.
+pub const SYSTEM_VAULT: &str = "/var/lib/sentinel/quarantine";
+
+/// Per-install key of the vault, inside the vault directory
+const KEY_FILE: &str = ".vault.key";
 
 #[derive(Clone)]
 pub enum ViewMode {
@@ -18,222 +38,671 @@ impl std::str::FromStr for ViewMode {
             "local" => Ok(ViewMode::Local),
             "all" => Ok(ViewMode::All),
             _ => Err(
-                format!("Invalid aggressiveness: {s}.
+                format!("Invalid view mode: {s}.
                     Use [Database, Local, All]"))
         }
     }
 }
 
+#[derive(Subcommand, Clone)]
+pub enum QuarantineCommands {
+    /// Put a quarantined file back where it was, with its original permissions and ownership
+    Restore {
+        id: i64,
+
+        /// What to do if something now exists at the original path: fail, rename or overwrite
+        #[arg(long, default_value = "fail")]
+        on_conflict: ConflictPolicy,
+
+        /// Restore here instead of the original path. Imported samples only restore this way, as root's
+        #[arg(long)]
+        to: Option<PathBuf>,
+    },
+    /// Securely delete a quarantined file
+    Delete {
+        id: i64,
+    },
+    /// Securely delete every quarantined file older than e.g. `30d`, `12h` or `2w`, recording each purge
+    Purge {
+        #[arg(long, value_parser = parse_age)]
+        older_than: TimeDelta,
+    },
+    /// Write quarantined samples into a password-protected bundle for offline analysis
+    Export {
+        #[arg(required = true)]
+        ids: Vec<i64>,
+
+        #[arg(long)]
+        out: PathBuf,
+
+        /// Read the bundle password from this file instead of $SENTINEL_BUNDLE_PASSWORD or the terminal
+        #[arg(long)]
+        password_file: Option<PathBuf>,
+    },
+    /// Quarantine the samples of an exported bundle into this vault
+    Import {
+        bundle: PathBuf,
+
+        /// Read the bundle password from this file instead of $SENTINEL_BUNDLE_PASSWORD or the terminal
+        #[arg(long)]
+        password_file: Option<PathBuf>,
+    },
+    /// Store the `--vault-max-size`, `--vault-max-age`, `--vault-retention` and `--vault-archive` given with it
+    /// as the retention policy every quarantine and `verify --every` apply, and print it
+    Retention {
+        /// Forget the stored policy instead
+        #[arg(long)]
+        clear: bool,
+    },
+    /// Check every quarantined sample against its SHA-256 and look for missing or extra vault files
+    Verify {
+        /// Keep running and verify again every e.g. `6h`, for the daemon
+        #[arg(long, value_parser = parse_age)]
+        every: Option<TimeDelta>,
+    },
+}
+
+/// What `restore` does when the original path is taken again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    Fail,
+    /// Restore next to it, as `<name>.restored-<id>`
+    Rename,
+    Overwrite,
+}
+
+impl std::str::FromStr for ConflictPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "fail" => Ok(ConflictPolicy::Fail),
+            "rename" => Ok(ConflictPolicy::Rename),
+            "overwrite" => Ok(ConflictPolicy::Overwrite),
+            _ => Err(
+                format!("Invalid conflict policy: {s}.
+                    Use [fail, rename, overwrite]"))
+        }
+    }
+}
+
+/// Ages like `90m`, `12h`, `30d` or `2w`
+pub fn parse_age(s: &str) -> Result<TimeDelta, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (amount, unit) = s.split_at(split);
+    let amount = amount.parse::<i64>().map_err(|_| format!("Invalid age: {s:?}. Use e.g. 30d, 12h or 2w"))?;
+
+    let age = match unit {
+        "s" => TimeDelta::try_seconds(amount),
+        "m" => TimeDelta::try_minutes(amount),
+        "h" => TimeDelta::try_hours(amount),
+        "d" | "" => TimeDelta::try_days(amount),
+        "w" => TimeDelta::try_weeks(amount),
+        _ => return Err(format!("Invalid age unit: {unit:?}. Use [s, m, h, d, w]")),
+    };
+    age.ok_or_else(|| format!("Age {s:?} is too large"))
+}
 
 #[derive(Debug, Clone)]
 pub struct QuarantinedFile {
+    /// None until the file is in the vault and recorded
+    pub id: Option<i64>,
     pub original_path: String,
     pub quarantine_path: String,
     pub reason: String,
     pub quarantined_date: Option<DateTime<Local>>,
+    pub metadata: Option<FileMetadata>,
+    /// SHA-256 of the original bytes, as hex
+    pub sha256: Option<String>,
+    /// uid of the user who asked for the quarantine, see `requester()`
+    pub quarantined_by: Option<u32>,
+    /// What the model scored it, None when no model did
+    pub score: Option<f32>,
 }
 
+impl QuarantinedFile {
+    /// A file waiting to be quarantined
+    pub fn new(original_path: &Path, reason: &str) -> Self {
+        Self {
+            id: None,
+            original_path: original_path.to_string_lossy().to_string(),
+            quarantine_path: String::new(),
+            reason: reason.to_string(),
+            quarantined_date: Some(Local::now()),
+            metadata: None,
+            sha256: None,
+            quarantined_by: None,
+            score: None,
+        }
+    }
+
+    pub fn with_score(mut self, score: Option<f32>) -> Self {
+        self.score = score;
+        self
+    }
+
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        let metadata = match row.get::<_, Option<u32>>(5)? {
+            Some(mode) => Some(FileMetadata {
+                mode,
+                uid: row.get(6)?,
+                gid: row.get(7)?,
+                size: row.get(8)?,
+                accessed: row.get(9)?,
+                modified: row.get(10)?,
+                xattrs: row.get::<_, Option<String>>(11)?
+                    .map(|json| FileMetadata::xattrs_from_json(&json))
+                    .unwrap_or_default(),
+            }),
+            None => None,
+        };
+
+        Ok(Self {
+            id: row.get(0)?,
+            original_path: row.get(1)?,
+            quarantine_path: row.get(2)?,
+            reason: row.get(3)?,
+            quarantined_date: parse_date(&row.get::<_, String>(4)?),
+            metadata,
+            sha256: row.get(12)?,
+            quarantined_by: row.get(13)?,
+            score: row.get::<_, Option<f64>>(14)?.map(|score| score as f32),
+        })
+    }
+}
+
+const COLUMNS: &str = "id, original_path, quarantine_path, reason, quarantined_date, mode, uid, gid, size, accessed, modified, xattrs, sha256, quarantined_by, score";
+
+/// Dates are stored as RFC 3339, older rows used chrono's Display format
+fn parse_date(date: &str) -> Option<DateTime<Local>> {
+    DateTime::parse_from_rfc3339(date)
+        .or_else(|_| DateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S%.f %:z"))
+        .ok()
+        .map(|date| date.with_timezone(&Local))
+}
+
+#[derive(Debug)]
+pub enum QuarantineError {
+    Io(PathBuf, io::Error),
+    /// Only regular files can be quarantined
+    NotAFile(PathBuf),
+    /// The original couldn't be unlinked, nothing was moved and it's still in place
+    OriginalNotRemoved(PathBuf, io::Error),
+    UnknownId(i64),
+    /// Something exists at the path a file is restored to
+    Conflict(PathBuf),
+    /// The directory a file is restored to doesn't exist anymore
+    MissingDirectory(PathBuf),
+    Db(rusqlite::Error),
+    NoDatabase,
+    /// The vault is root-only, unprivileged users go through sudo
+    NotRoot(PathBuf),
+    /// The vault isn't owned by root, someone else could have planted or swapped files in it
+    InsecureVault(PathBuf),
+    /// A record points at something that isn't a sample in the vault
+    OutsideVault(PathBuf),
+    /// A record says the sample came from elsewhere than its vault file was sealed with
+    RecordMismatch(PathBuf),
+    /// A plain vault file from before the header, there's no sealed path to restore it to
+    NoHeader(PathBuf),
+    /// An imported sample, its original path is another machine's
+    Imported(i64),
+    /// An export bundle that can't be imported
+    InvalidBundle(PathBuf, String),
+    /// No usable bundle password
+    Password(String),
+}
+
+impl fmt::Display for QuarantineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QuarantineError::Io(path, e) => write!(f, "Couldn't quarantine {:?}: {e}", path),
+            QuarantineError::NotAFile(path) => write!(f, "{:?} isn't a regular file", path),
+            QuarantineError::OriginalNotRemoved(path, e) => {
+                write!(f, "Couldn't remove {:?}, nothing was moved and it's still in place: {e}", path)
+            }
+            QuarantineError::UnknownId(id) => write!(f, "There is no quarantined file with id {id}"),
+            QuarantineError::Conflict(path) => {
+                write!(f, "{:?} already exists, restore with --on-conflict rename or overwrite", path)
+            }
+            QuarantineError::MissingDirectory(dir) => {
+                write!(f, "{:?} doesn't exist anymore, create it or restore with --to", dir)
+            }
+            QuarantineError::Db(e) => write!(f, "Couldn't record the quarantined file: {e}"),
+            QuarantineError::NoDatabase => write!(f, "Couldn't load database! Is this database created or initialized?"),
+            QuarantineError::NotRoot(vault) => {
+                write!(f, "The quarantine vault {:?} is root-only, nothing was changed. Rerun with sudo", vault)
+            }
+            QuarantineError::InsecureVault(vault) => {
+                write!(f, "{:?} isn't owned by root, refusing to use it as the quarantine vault", vault)
+            }
+            QuarantineError::OutsideVault(path) => write!(f, "{:?} isn't a sample in the quarantine vault, refusing to touch it", path),
+            QuarantineError::RecordMismatch(path) => {
+                write!(f, "The record of {:?} doesn't match the path it was sealed with, refusing to restore it", path)
+            }
+            QuarantineError::NoHeader(path) => {
+                write!(f, "{:?} has no header to check its original path against, restore it with --to", path)
+            }
+            QuarantineError::Imported(id) => {
+                write!(f, "Quarantined file {id} was imported, its original path is another machine's. Restore it with --to")
+            }
+            QuarantineError::InvalidBundle(bundle, message) => write!(f, "Couldn't import {:?}: {message}", bundle),
+            QuarantineError::Password(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl std::error::Error for QuarantineError {}
+
+impl From<rusqlite::Error> for QuarantineError {
+    fn from(e: rusqlite::Error) -> Self {
+        QuarantineError::Db(e)
+    }
+}
+
+/// Quarantines into a single root-owned vault, `SYSTEM_VAULT` by default.
+///
+/// Only root touches the vault: quarantining, restoring and deleting fail with `NotRoot` otherwise,
+/// so unprivileged users go through `sudo sentinel quarantine ...`. Every file records who asked for it
+/// (the user behind sudo), and unprivileged users only get to list their own files.
 pub struct Quarantinizer {
     pub quarantine_dir: PathBuf,
     pub quarantined_files: Vec<QuarantinedFile>,
 
     db: Option<Connection>,
+    requester: u32,
+    retention: RetentionPolicy,
 }
 
 #[allow(clippy::new_without_default)]
 impl Quarantinizer {
     pub fn new() -> Self {
-        let quarantine_dir = home_dir()
-            .map(|h| h.join(".sentinel_quarantine"))
-            .ok_or("Couldn't determine home directory")
-            .unwrap();
-        fs::create_dir_all(&quarantine_dir)
-            .unwrap_or_else(|e| println!("Couldn't create {}\nError {e}", quarantine_dir.to_string_lossy()));
-
         Self {
-            quarantine_dir,
+            quarantine_dir: PathBuf::from(SYSTEM_VAULT),
             quarantined_files: vec![],
             db: None,
+            requester: requester(),
+            retention: RetentionPolicy::default(),
         }
     }
 
+    /// Loads the recorded files and the stored retention policy
     pub fn from_db(conn: Connection) -> rusqlite::Result<Self> {
-        let quarantine_dir = home_dir()
-            .map(|h| h.join(".sentinel_quarantine"))
-            .ok_or("Couldn't determine home directory")
-            .unwrap();
-        fs::create_dir_all(&quarantine_dir)
-            .unwrap_or_else(|e| println!("Couldn't create {}\nError {e}", quarantine_dir.to_string_lossy()));
-
-        let quarantined_files = {
-            let mut stmt = conn.prepare("SELECT id, original_path, quarantine_path, reason, quarantined_date FROM quarantined_files")?;
-            stmt.query_map([], |row| {
-                Ok(QuarantinedFile {
-                    original_path: row.get(1)?,
-                    quarantine_path: row.get(2)?,
-                    reason: row.get(3)?,
-                    quarantined_date: None,
-                })
-            })?
-            .filter_map(|result| result.ok())
-            .collect::<Vec<QuarantinedFile>>()
-        };
+        let quarantined_files = load_quarantined(&conn)?;
+        let retention = RetentionPolicy::load(&conn)?;
 
         Ok(Self {
-            quarantine_dir,
+            quarantine_dir: PathBuf::from(SYSTEM_VAULT),
             quarantined_files,
             db: Some(conn),
+            requester: requester(),
+            retention,
         })
     }
 
-    pub fn quarantine(&mut self) -> Result<(), String> {
-        let db_quarantined_files = self.get_quarantined_files().unwrap();
-        let mut is_quarantined: bool = false;
+    /// Uses another vault directory, it's created on the first quarantine
+    pub fn with_vault(mut self, quarantine_dir: PathBuf) -> Self {
+        self.quarantine_dir = quarantine_dir;
+        self
+    }
+
+    /// Creates the vault if needed and makes sure only root can get in
+    fn open_vault(&self) -> Result<(), QuarantineError> {
+        if !is_root() {
+            return Err(QuarantineError::NotRoot(self.quarantine_dir.clone()));
+        }
+        let io_error = |e| QuarantineError::Io(self.quarantine_dir.clone(), e);
+
+        fs::create_dir_all(&self.quarantine_dir).map_err(io_error)?;
+        let metadata = fs::symlink_metadata(&self.quarantine_dir).map_err(io_error)?;
+        if !metadata.is_dir() || metadata.uid() != 0 {
+            return Err(QuarantineError::InsecureVault(self.quarantine_dir.clone()));
+        }
+        if metadata.mode() & 0o077 != 0 {
+            fs::set_permissions(&self.quarantine_dir, Permissions::from_mode(0o700)).map_err(io_error)?;
+        }
+        Ok(())
+    }
+
+    /// Moves every file that isn't quarantined yet into the vault and records it, then enforces the retention policy.
+    /// Stops at the first failure, the files before it stay quarantined and the rest are dropped.
+    /// Returns the files it just quarantined.
+    pub fn quarantine(&mut self) -> Result<Vec<QuarantinedFile>, QuarantineError> {
+        let (stored, mut pending): (Vec<QuarantinedFile>, Vec<QuarantinedFile>) = std::mem::take(&mut self.quarantined_files)
+            .into_iter()
+            .partition(|qf| qf.id.is_some());
+        self.quarantined_files = stored;
 
         // dedup initialization
-        self.quarantined_files.sort_by(|a, b| a.original_path.cmp(&b.original_path));
-        self.quarantined_files.dedup_by(|a, b| a.original_path == b.original_path);
-
-        for qf in self.quarantined_files.iter_mut() {
-            let original_file_name = Path::new(&qf.original_path)
-                .file_name()
-                .expect("Invalid file path")
-                .to_string_lossy();
-            let quarantine_file_path = self.quarantine_dir.join(&*original_file_name);
-
-            let quarantined_file_name = format!("{}_{}", original_file_name, Local::now().format("%Y%m%d%H%M%S"));
-            // println!("{quarantined_file_name}");
-            let full_quarantine_file_path = self.quarantine_dir.join(&quarantined_file_name);
-            qf.quarantine_path = quarantine_file_path.to_string_lossy().to_string();
-
-            is_quarantined = db_quarantined_files
-                .iter()
-                .any(|db_qf| db_qf.quarantine_path.starts_with(&*quarantine_file_path.to_string_lossy()));
-            // println!("is_quarantined: {is_quarantined}");
-            if !is_quarantined {
-                println!("Is not quarantined. Quarantine in process");
-                // put file in /home/user/.sentinel_quarantine/ for quarantine
-                // println!("{full_quarantine_file_path:?}");
-                //
-                // println!("About to create: {}", full_quarantine_file_path.display());
-                // println!("Parent exists: {}", full_quarantine_file_path.parent().unwrap().exists());
-                // println!("Dir perms: {:?}", fs::metadata(full_quarantine_file_path.parent().unwrap()));
-
-                let file = match File::create(&full_quarantine_file_path) {
-                    Ok(captured_file) => captured_file,
-                    Err(e) => {
-                        println!("CREATE FAILED: {e}");
-                        return Err(format!("Creation failed for {:?}: {e}", full_quarantine_file_path));
-                    }
-                };
-
-                let mut perm = file.metadata()
-                    .unwrap_or_else(|e| panic!("Couldn't get metadata of {:?}\nError: {e}", file))
-                    .permissions();
-
-                println!("Locking {:?}", original_file_name);
-                perm.set_mode(0o000); // lock it. even for the user, except root can change it soo yeah
-                fs::set_permissions(&full_quarantine_file_path, perm)
-                    .unwrap_or_else(|e| panic!("Couldn't set permissions to {:?}\nError {e}", &full_quarantine_file_path));
-
-                println!("Quarantined {:?}", full_quarantine_file_path);
-            } else {
-                eprintln!("Already quarantined");
-            }
+        pending.sort_by(|a, b| a.original_path.cmp(&b.original_path));
+        pending.dedup_by(|a, b| a.original_path == b.original_path);
+
+        if pending.is_empty() {
+            return Ok(vec![]);
         }
+        let mut quarantined = vec![];
+        for mut qf in pending {
+            self.quarantine_file(&mut qf)?;
+            quarantined.push(qf.clone());
+            self.quarantined_files.push(qf);
+        }
+        self.make_room();
+        Ok(quarantined)
+    }
 
-        // after all that quaranting, store it to the db
-        for quarantined_file in &self.quarantined_files {
-            if !is_quarantined {
-                self.store_quarantined(quarantined_file).unwrap();
+    /// Enforces the retention policy once new files are in. A full vault shouldn't undo quarantining them.
+    fn make_room(&mut self) {
+        match self.enforce_retention() {
+            Ok(retired) => for retired in retired {
+                eprintln!("Retired {:?} from the vault ({}, {})", retired.file.original_path, retired.action.as_str(), retired.cause);
+            },
+            Err(e) => eprintln!("Couldn't enforce the vault retention policy: {e}"),
+        }
+    }
+
+    fn quarantine_file(&self, qf: &mut QuarantinedFile) -> Result<(), QuarantineError> {
+        let db = self.db.as_ref().ok_or(QuarantineError::NoDatabase)?;
+
+        // a restore has to know where it was, whatever the working directory is then
+        let original = std::path::absolute(&qf.original_path)
+            .map_err(|e| QuarantineError::Io(PathBuf::from(&qf.original_path), e))?;
+        self.open_vault()?;
+        let key = VaultKey::load_or_create(&self.quarantine_dir.join(KEY_FILE))
+            .map_err(|e| QuarantineError::Io(self.quarantine_dir.join(KEY_FILE), e))?;
+
+        let vault_path = self.vault_path(&original).ok_or_else(|| QuarantineError::NotAFile(original.clone()))?;
+        let mut header = ContainerHeader {
+            sha256: String::new(),
+            original_path: original.to_string_lossy().to_string(),
+            reason: qf.reason.clone(),
+            quarantined_date: qf.quarantined_date,
+            // read from the open original
+            metadata: FileMetadata::default(),
+            quarantined_by: Some(self.requester),
+            score: qf.score,
+            imported: false,
+        };
+        vault::seal_into(&original, &vault_path, &key, &mut header)?;
+
+        qf.original_path = header.original_path;
+        qf.quarantine_path = vault_path.to_string_lossy().to_string();
+        qf.metadata = Some(header.metadata);
+        qf.sha256 = Some(header.sha256);
+        qf.quarantined_by = Some(self.requester);
+        match store_quarantined(db, qf) {
+            Ok(id) => qf.id = Some(id),
+            Err(e) => {
+                // don't keep a file nobody knows about in the vault
+                if let Err(put_back) = vault::unseal_to(&vault_path, &original, &key) {
+                    eprintln!("Couldn't put {:?} back to {:?}: {put_back}", vault_path, original);
+                }
+                return Err(e.into());
             }
         }
 
+        // lock it. even for the user, except root can change it soo yeah
+        if let Err(e) = fs::set_permissions(&vault_path, Permissions::from_mode(0o000)) {
+            eprintln!("Couldn't lock {:?}: {e}", vault_path);
+        }
         Ok(())
     }
 
-    /// Pushing a quarantined file will immediately trigger the `quarantine()` function again
-    pub fn push_quarantined(&mut self, quarantined: QuarantinedFile) -> Result<(), String> {
-        // old files, just in case everything fails
-        let old_files = self.quarantined_files.clone();
-        self.quarantined_files.push(quarantined.clone());
+    /// Where the sample of `qf` is, refused unless it's in this vault
+    fn vault_file(&self, qf: &QuarantinedFile) -> Result<PathBuf, QuarantineError> {
+        vault::resolve(&self.quarantine_dir, Path::new(&qf.quarantine_path))
+            .ok_or_else(|| QuarantineError::OutsideVault(PathBuf::from(&qf.quarantine_path)))
+    }
 
-        // quarantine again
-        if let Err(e) = self.quarantine() {
-            self.quarantined_files = old_files;
-            return Err(format!("Couldn't quarantine the files\nError: {e}"));
+    /// The key of the vault `vault_path` is in, which may be an older vault than the current one
+    fn key_for(vault_path: &Path) -> Result<VaultKey, QuarantineError> {
+        let path = vault_path.with_file_name(KEY_FILE);
+        VaultKey::load(&path).map_err(|e| QuarantineError::Io(path, e))
+    }
+
+    /// `<name>_<timestamp>` in the vault, with a counter if that's taken. None for paths without a
+    /// file name, like `/` or ones ending in `..`
+    fn vault_path(&self, original: &Path) -> Option<PathBuf> {
+        let original_file_name = original.file_name()?.to_string_lossy();
+        let quarantined_file_name = format!("{}_{}", original_file_name, Local::now().format("%Y%m%d%H%M%S"));
+
+        let mut vault_path = self.quarantine_dir.join(&quarantined_file_name);
+        let mut i = 1;
+        while fs::symlink_metadata(&vault_path).is_ok() {
+            vault_path = self.quarantine_dir.join(format!("{quarantined_file_name}_{i}"));
+            i += 1;
         }
-        Ok(())
+        Some(vault_path)
     }
 
-    fn store_quarantined(&self, quarantined: &QuarantinedFile) -> rusqlite::Result<()> {
-        for qf in &self.quarantined_files {
-            let original_file_name = Path::new(&qf.original_path)
-                .file_name()
-                .expect("Invalid file path")
-                .to_string_lossy();
-
-            let quarantined_file_name = format!("{}_{}", original_file_name, Local::now().format("%Y%m%d%H%M%S"));
-            let full_quarantine_file_path = self.quarantine_dir.join(&quarantined_file_name);
-
-            // (i, self.quarantine_dir.join(&quarantined_file_name))
-            let quarantined_clone  = quarantined.clone();
-            match &self.db {
-                Some(db) => {
-                    db.execute(
-                    "INSERT INTO quarantined_files (original_path, quarantine_path, reason, quarantined_date)
-                            VALUES ($1, $2, $3, $4)",
-                    [
-                        quarantined_clone.original_path,
-                        full_quarantine_file_path.to_string_lossy().to_string(),
-                        quarantined_clone.reason,
-                        quarantined_clone.quarantined_date.unwrap_or_default().to_string()
-                    ])?;
-                }
-                None => panic!("Couldn't load database! Is this database created or initialized?")
+    /// Pushing a quarantined file will immediately trigger the `quarantine()` function again
+    pub fn push_quarantined(&mut self, quarantined: QuarantinedFile) -> Result<Vec<QuarantinedFile>, QuarantineError> {
+        self.quarantined_files.push(quarantined);
+        self.quarantine()
+    }
+
+    pub fn find(&self, id: i64) -> Result<&QuarantinedFile, QuarantineError> {
+        self.quarantined_files.iter()
+            .find(|qf| qf.id == Some(id))
+            .ok_or(QuarantineError::UnknownId(id))
+    }
+
+    /// Moves a quarantined file back to the path and metadata its vault file was sealed with, or to `to`,
+    /// and forgets it. Returns where it was restored to.
+    /// Imported samples and plain vault files from older versions need `to`, and stay root's.
+    pub fn restore(&mut self, id: i64, on_conflict: ConflictPolicy, to: Option<&Path>) -> Result<PathBuf, QuarantineError> {
+        let db = self.db.as_ref().ok_or(QuarantineError::NoDatabase)?;
+        let qf = self.find(id)?;
+        if !is_root() {
+            return Err(QuarantineError::NotRoot(self.quarantine_dir.clone()));
+        }
+        let vault_path = self.vault_file(qf)?;
+
+        // the database row is only a copy, the header is what the sample was sealed with
+        let header = match fs::symlink_metadata(&vault_path).is_ok() && !container::is_container(&vault_path) {
+            true => None,
+            false => Some(fs::File::open(&vault_path)
+                .and_then(|mut file| container::read_header(&mut file))
+                .map_err(|e| QuarantineError::Io(vault_path.clone(), e))?),
+        };
+        let original = match (to, &header) {
+            (Some(to), _) => to.to_path_buf(),
+            (None, None) => return Err(QuarantineError::NoHeader(vault_path)),
+            (None, Some(header)) if header.imported => return Err(QuarantineError::Imported(id)),
+            (None, Some(header)) if header.original_path != qf.original_path => return Err(QuarantineError::RecordMismatch(vault_path)),
+            (None, Some(header)) => PathBuf::from(&header.original_path),
+        };
+
+        let target = match (fs::symlink_metadata(&original).is_ok(), on_conflict) {
+            (false, _) | (true, ConflictPolicy::Overwrite) => original.clone(),
+            (true, ConflictPolicy::Fail) => return Err(QuarantineError::Conflict(original)),
+            (true, ConflictPolicy::Rename) => {
+                let mut name = original.file_name().unwrap_or_default().to_os_string();
+                name.push(format!(".restored-{id}"));
+                original.with_file_name(name)
             }
+        };
+        // root creating directories in a user's tree would hand them root-owned ones
+        if let Some(parent) = target.parent()
+            && !parent.is_dir()
+        {
+            return Err(QuarantineError::MissingDirectory(parent.to_path_buf()));
         }
+
+        // unlock it, it has to be read
+        fs::set_permissions(&vault_path, Permissions::from_mode(0o600))
+            .map_err(|e| QuarantineError::Io(vault_path.clone(), e))?;
+        match header {
+            Some(_) => {
+                vault::unseal_to(&vault_path, &target, &Self::key_for(&vault_path)?)?;
+            }
+            None => vault::move_into(&vault_path, &target)?,
+        }
+
+        db.execute("DELETE FROM quarantined_files WHERE id = $1", [id])?;
+        self.quarantined_files.retain(|qf| qf.id != Some(id));
+        Ok(target)
+    }
+
+    /// Wipes a quarantined file and forgets it
+    pub fn delete(&mut self, id: i64) -> Result<(), QuarantineError> {
+        self.db.as_ref().ok_or(QuarantineError::NoDatabase)?;
+        let qf = self.find(id)?;
+        if !is_root() {
+            return Err(QuarantineError::NotRoot(self.quarantine_dir.clone()));
+        }
+        let vault_path = self.vault_file(qf)?;
+
+        match vault::secure_delete(&self.quarantine_dir, &vault_path) {
+            Ok(()) => {}
+            // already gone, only the record is left
+            Err(e) if e.kind() == io::ErrorKind::NotFound => eprintln!("{:?} was already gone", vault_path),
+            Err(e) => return Err(QuarantineError::Io(vault_path, e)),
+        }
+
+        self.forget(id)
+    }
+
+    /// Drops the record of a file whose vault file is taken care of
+    fn forget(&mut self, id: i64) -> Result<(), QuarantineError> {
+        let db = self.db.as_ref().ok_or(QuarantineError::NoDatabase)?;
+        db.execute("DELETE FROM quarantined_files WHERE id = $1", [id])?;
+        self.quarantined_files.retain(|qf| qf.id != Some(id));
         Ok(())
     }
 
+    /// Deletes every quarantined file older than `age`, records each one in `quarantine_purges` and returns them.
+    /// Files without a readable date are kept.
+    pub fn purge(&mut self, age: TimeDelta) -> Result<Vec<QuarantinedFile>, QuarantineError> {
+        let cutoff = Local::now() - age;
+        let expired = self.quarantined_files.iter()
+            .filter(|qf| qf.quarantined_date.is_some_and(|date| date < cutoff))
+            .cloned()
+            .collect::<Vec<QuarantinedFile>>();
+
+        let cause = format!("purge --older-than {}", retention::format_age(age));
+        expired.into_iter()
+            .map(|qf| self.retire(qf, RetentionAction::Purge, &cause).map(|retired| retired.file))
+            .collect()
+    }
+
+    /// Every recorded file for root, only their own files for other users
     pub fn get_quarantined_files(&self) -> Result<Vec<QuarantinedFile>, String> {
-        let quarantined_files = if let Some(db) = &self.db {
-            let mut stmt = db.prepare("SELECT id, original_path, quarantine_path, reason, quarantined_date FROM quarantined_files").unwrap();
-            stmt.query_map([], |row| {
-                Ok(QuarantinedFile {
-                    original_path: row.get(1)?,
-                    quarantine_path: row.get(2)?,
-                    reason: row.get(3)?,
-                    quarantined_date: None,
-                })
-            }).unwrap()
-            .filter_map(|result| result.ok())
-            .collect::<Vec<QuarantinedFile>>()
-        } else {
-            vec![]
+        let quarantined_files = match &self.db {
+            Some(db) => load_quarantined(db).map_err(|e| format!("Couldn't load the quarantined files: {e}"))?,
+            None => vec![],
         };
 
-        Ok(quarantined_files)
+        Ok(quarantined_files.into_iter()
+            .filter(|qf| is_root() || qf.quarantined_by == Some(self.requester))
+            .collect())
     }
 
+    /// What's actually in the vault, described by the vault files' own headers. Root only.
     pub fn get_local_files(&self, quarantine_path: &PathBuf) -> Result<Vec<QuarantinedFile>, String> {
+        if !is_root() {
+            return Err(QuarantineError::NotRoot(quarantine_path.clone()).to_string());
+        }
+        let entries = match fs::read_dir(quarantine_path) {
+            Ok(entries) => entries,
+            // nothing was quarantined yet
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(format!("Couldn't read the vault {:?}: {e}", quarantine_path)),
+        };
         let mut local_files = vec![];
-        for entry in fs::read_dir(quarantine_path).unwrap().flatten() {
+        for entry in entries.flatten() {
             let original_path = entry.path();
 
             // pretty confidenct next_back wont return a none
             let original_file_name = original_path.iter().next_back().unwrap();
+            // the key and unfinished writes
+            if original_file_name.to_string_lossy().starts_with('.') || original_path.extension().is_some_and(|ext| ext == "part") {
+                continue;
+            }
             let quarantine_path_file = quarantine_path.join(original_file_name);
 
+            // reading the header needs read access, vault files are locked
+            let header = fs::File::open(&quarantine_path_file)
+                .and_then(|mut file| container::read_header(&mut file))
+                .ok();
             local_files.push(
                 QuarantinedFile {
-                    original_path: original_path.to_string_lossy().to_string(),
+                    id: None,
+                    original_path: header.as_ref()
+                        .map(|header| header.original_path.clone())
+                        .unwrap_or_else(|| original_path.to_string_lossy().to_string()),
                     quarantine_path: quarantine_path_file.to_string_lossy().to_string(),
-                    reason: "Local Files shiii".to_string(),
-                    quarantined_date: None,
+                    reason: header.as_ref()
+                        .map(|header| header.reason.clone())
+                        .unwrap_or_else(|| "Local Files shiii".to_string()),
+                    quarantined_date: header.as_ref().and_then(|header| header.quarantined_date),
+                    metadata: header.as_ref().map(|header| header.metadata.clone()),
+                    sha256: header.as_ref().map(|header| header.sha256.clone()),
+                    quarantined_by: header.as_ref().and_then(|header| header.quarantined_by),
+                    score: header.and_then(|header| header.score),
                 }
             )
         }
         Ok(local_files)
     }
 }
+
+fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// Who asked for it: the user behind sudo, otherwise whoever runs sentinel
+pub fn requester() -> u32 {
+    let uid = unsafe { libc::getuid() };
+    let sudo_uid = std::env::var("SUDO_UID").ok().and_then(|uid| uid.parse().ok());
+    match (uid, sudo_uid) {
+        (0, Some(sudo_uid)) => sudo_uid,
+        _ => uid,
+    }
+}
+
+fn load_quarantined(db: &Connection) -> rusqlite::Result<Vec<QuarantinedFile>> {
+    let mut stmt = db.prepare(&format!("SELECT {COLUMNS} FROM quarantined_files ORDER BY id"))?;
+    stmt.query_map([], QuarantinedFile::from_row)?
+        .collect()
+}
+
+fn store_quarantined(db: &Connection, quarantined: &QuarantinedFile) -> rusqlite::Result<i64> {
+    let metadata = quarantined.metadata.clone().unwrap_or_default();
+    db.execute(
+        "INSERT INTO quarantined_files (original_path, quarantine_path, reason, quarantined_date, mode, uid, gid, size, accessed, modified, xattrs, sha256, quarantined_by, score)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)",
+        rusqlite::params![
+            quarantined.original_path,
+            quarantined.quarantine_path,
+            quarantined.reason,
+            quarantined.quarantined_date.unwrap_or_else(Local::now).to_rfc3339(),
+            metadata.mode,
+            metadata.uid,
+            metadata.gid,
+            metadata.size,
+            metadata.accessed,
+            metadata.modified,
+            metadata.xattrs_to_json(),
+            quarantined.sha256,
+            quarantined.quarantined_by,
+            quarantined.score,
+        ],
+    )?;
+    Ok(db.last_insert_rowid())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_ages() {
+        assert_eq!(parse_age("30d"), Ok(TimeDelta::days(30)));
+        assert_eq!(parse_age("12h"), Ok(TimeDelta::hours(12)));
+        assert_eq!(parse_age("2w"), Ok(TimeDelta::weeks(2)));
+        assert_eq!(parse_age("7"), Ok(TimeDelta::days(7)));
+        assert!(parse_age("3y").is_err());
+        assert!(parse_age("d").is_err());
+    }
+}
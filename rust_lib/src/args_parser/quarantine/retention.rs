@@ -0,0 +1,356 @@
+use std::{fmt, fs, io, os::unix::fs::PermissionsExt, path::PathBuf};
+use chrono::{Local, TimeDelta};
+use rusqlite::{Connection, OptionalExtension};
+
+use super::{container, is_root, listing::human_size, load_quarantined, vault, QuarantineError, QuarantinedFile, Quarantinizer, VaultKey, KEY_FILE};
+use crate::palette;
+
+/// Where `archive` moves retired vault files unless `--vault-archive` says otherwise
+pub const SYSTEM_ARCHIVE: &str = "/var/lib/sentinel/archive";
+
+/// Share of `max_size` past which every quarantine warns
+const NEAR_FULL: f64 = 0.9;
+
+/// What happens to the vault files the retention policy retires
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RetentionAction {
+    /// Securely delete them
+    #[default]
+    Purge,
+    /// Move them, still encrypted, into the archive directory
+    Archive,
+}
+
+impl std::str::FromStr for RetentionAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "purge" => Ok(RetentionAction::Purge),
+            "archive" => Ok(RetentionAction::Archive),
+            _ => Err(
+                format!("Invalid retention action: {s}.
+                    Use [purge, archive]"))
+        }
+    }
+}
+
+impl RetentionAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RetentionAction::Purge => "purge",
+            RetentionAction::Archive => "archive",
+        }
+    }
+}
+
+/// How big and how old the vault may get. Nothing is retired without a limit.
+#[derive(Debug, Clone)]
+pub struct RetentionPolicy {
+    /// Bytes of vault files
+    pub max_size: Option<u64>,
+    pub max_age: Option<TimeDelta>,
+    pub action: RetentionAction,
+    pub archive_dir: PathBuf,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            max_size: None,
+            max_age: None,
+            action: RetentionAction::default(),
+            archive_dir: PathBuf::from(SYSTEM_ARCHIVE),
+        }
+    }
+}
+
+impl RetentionPolicy {
+    /// The policy stored with `quarantine retention`, no limits without one
+    pub fn load(db: &Connection) -> rusqlite::Result<Self> {
+        db.query_row(
+            "SELECT max_size, max_age, action, archive_dir FROM quarantine_retention WHERE id = 1",
+            [],
+            |row| Ok(Self {
+                max_size: row.get::<_, Option<i64>>(0)?.map(|size| size as u64),
+                max_age: row.get::<_, Option<i64>>(1)?.map(TimeDelta::seconds),
+                action: row.get::<_, String>(2)?.parse().unwrap_or_default(),
+                archive_dir: PathBuf::from(row.get::<_, String>(3)?),
+            }),
+        )
+        .optional()
+        .map(Option::unwrap_or_default)
+    }
+}
+
+impl fmt::Display for RetentionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut limits = vec![];
+        if let Some(max_size) = self.max_size {
+            limits.push(format!("vault up to {}", human_size(max_size)));
+        }
+        if let Some(max_age) = self.max_age {
+            limits.push(format!("files up to {} old", format_age(max_age)));
+        }
+        if limits.is_empty() {
+            return write!(f, "no limits, nothing is retired");
+        }
+        match self.action {
+            RetentionAction::Purge => write!(f, "{}, then purge", limits.join(", ")),
+            RetentionAction::Archive => write!(f, "{}, then archive into {:?}", limits.join(", "), self.archive_dir),
+        }
+    }
+}
+
+/// Sizes like `512K`, `500M` or `2G`, in powers of 1024. A bare number is bytes.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (amount, unit) = s.split_at(split);
+    let amount = amount.parse::<u64>().map_err(|_| format!("Invalid size: {s:?}. Use e.g. 500M or 2G"))?;
+
+    let shift = match unit.to_uppercase().trim_end_matches("IB").trim_end_matches('B') {
+        "" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        "T" => 40,
+        _ => return Err(format!("Invalid size unit: {unit:?}. Use [B, K, M, G, T]")),
+    };
+    amount.checked_mul(1 << shift).ok_or_else(|| format!("Size {s:?} is too large"))
+}
+
+/// A vault file the retention policy (or `purge`) got rid of
+#[derive(Debug, Clone)]
+pub struct Retired {
+    pub file: QuarantinedFile,
+    pub action: RetentionAction,
+    /// Where it went when archived
+    pub archive_path: Option<PathBuf>,
+    /// Why, e.g. `older than 30d`
+    pub cause: String,
+}
+
+impl Quarantinizer {
+    pub fn with_retention(mut self, retention: RetentionPolicy) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// The stored policy, with whatever `with_retention` put on top
+    pub fn retention(&self) -> &RetentionPolicy {
+        &self.retention
+    }
+
+    /// Stores the current policy, every later quarantine and `verify --every` apply it. Root only.
+    pub fn store_retention(&self) -> Result<(), QuarantineError> {
+        if !is_root() {
+            return Err(QuarantineError::NotRoot(self.quarantine_dir.clone()));
+        }
+        let db = self.db.as_ref().ok_or(QuarantineError::NoDatabase)?;
+        db.execute(
+            "INSERT OR REPLACE INTO quarantine_retention (id, max_size, max_age, action, archive_dir, updated_date)
+                    VALUES (1, $1, $2, $3, $4, $5)",
+            rusqlite::params![
+                self.retention.max_size.map(|size| size as i64),
+                self.retention.max_age.map(|age| age.num_seconds()),
+                self.retention.action.as_str(),
+                self.retention.archive_dir.to_string_lossy(),
+                Local::now().to_rfc3339(),
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Forgets the stored policy, nothing is retired anymore unless asked for on the command line. Root only.
+    pub fn clear_retention(&mut self) -> Result<(), QuarantineError> {
+        if !is_root() {
+            return Err(QuarantineError::NotRoot(self.quarantine_dir.clone()));
+        }
+        let db = self.db.as_ref().ok_or(QuarantineError::NoDatabase)?;
+        db.execute("DELETE FROM quarantine_retention", [])?;
+        self.retention = RetentionPolicy::default();
+        Ok(())
+    }
+
+    /// Retires everything older than `max_age`, then the oldest files until the vault fits in `max_size`,
+    /// and warns when what's left is close to `max_size`. Root only.
+    /// Files without a readable date never expire, but count as the oldest when the vault is too big.
+    pub fn enforce_retention(&mut self) -> Result<Vec<Retired>, QuarantineError> {
+        if self.retention.max_size.is_none() && self.retention.max_age.is_none() {
+            return Ok(vec![]);
+        }
+        if !is_root() {
+            return Err(QuarantineError::NotRoot(self.quarantine_dir.clone()));
+        }
+
+        // the daemon keeps running while files get quarantined, start from the database
+        let db = self.db.as_ref().ok_or(QuarantineError::NoDatabase)?;
+        let pending = std::mem::take(&mut self.quarantined_files).into_iter().filter(|qf| qf.id.is_none());
+        self.quarantined_files = load_quarantined(db)?;
+        self.quarantined_files.extend(pending);
+
+        let mut stored = self.quarantined_files.iter()
+            .filter(|qf| qf.id.is_some())
+            .cloned()
+            .collect::<Vec<QuarantinedFile>>();
+        stored.sort_by_key(|qf| qf.quarantined_date);
+
+        let mut retired = vec![];
+        if let Some(max_age) = self.retention.max_age {
+            let cutoff = Local::now() - max_age;
+            let (expired, kept) = stored.into_iter().partition(|qf| qf.quarantined_date.is_some_and(|date| date < cutoff));
+            stored = kept;
+            for qf in expired {
+                retired.push(self.retire(qf, self.retention.action, &format!("older than {}", format_age(max_age)))?);
+            }
+        }
+
+        if let Some(max_size) = self.retention.max_size {
+            let mut sizes = stored.iter().map(|qf| vault_size(&qf.quarantine_path)).collect::<Vec<u64>>();
+            let mut total = sizes.iter().sum::<u64>();
+            let mut oldest = stored.into_iter();
+            while total > max_size && let Some(qf) = oldest.next() {
+                total -= sizes.remove(0);
+                retired.push(self.retire(qf, self.retention.action, &format!("vault over {}", human_size(max_size)))?);
+            }
+
+            if total as f64 >= max_size as f64 * NEAR_FULL {
+                let warning = format!("Quarantine vault is {} of its {} quota", human_size(total), human_size(max_size));
+                eprintln!("{}", palette::current().warning(&warning));
+            }
+        }
+        Ok(retired)
+    }
+
+    /// Gets rid of one stored file and records it in `quarantine_purges`
+    pub(super) fn retire(&mut self, qf: QuarantinedFile, action: RetentionAction, cause: &str) -> Result<Retired, QuarantineError> {
+        let id = qf.id.expect("Stored files have an id");
+        let archive_path = match action {
+            RetentionAction::Purge => {
+                self.delete(id)?;
+                None
+            }
+            RetentionAction::Archive => {
+                let archive_path = self.archive(&qf)?;
+                self.forget(id)?;
+                archive_path
+            }
+        };
+
+        let db = self.db.as_ref().ok_or(QuarantineError::NoDatabase)?;
+        db.execute(
+            "INSERT INTO quarantine_purges (purged_date, quarantined_file_id, original_path, quarantine_path, sha256, size, action, archive_path, cause)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+            rusqlite::params![
+                Local::now().to_rfc3339(),
+                id,
+                qf.original_path,
+                qf.quarantine_path,
+                qf.sha256,
+                qf.metadata.as_ref().map(|metadata| metadata.size as i64),
+                action.as_str(),
+                archive_path.as_ref().map(|path| path.to_string_lossy().to_string()),
+                cause,
+            ],
+        )?;
+        Ok(Retired { file: qf, action, archive_path, cause: cause.to_string() })
+    }
+
+    /// Re-encrypts the vault file into the root-only archive with the archive's own key,
+    /// so archived files outlive the vault key they were sealed with. None if the vault file was already gone.
+    fn archive(&self, qf: &QuarantinedFile) -> Result<Option<PathBuf>, QuarantineError> {
+        let vault_path = &self.vault_file(qf)?;
+        let archive_dir = &self.retention.archive_dir;
+        let io_error = |e| QuarantineError::Io(archive_dir.clone(), e);
+        fs::create_dir_all(archive_dir).map_err(io_error)?;
+        fs::set_permissions(archive_dir, fs::Permissions::from_mode(0o700)).map_err(io_error)?;
+
+        let file_name = vault_path.file_name().expect("Vault files have a name");
+        let mut archive_path = archive_dir.join(file_name);
+        if archive_path.exists() {
+            archive_path = archive_dir.join(format!("{}.{}", file_name.to_string_lossy(), qf.id.unwrap_or_default()));
+        }
+        let archived = match container::is_container(vault_path) {
+            true => {
+                let archive_key = archive_dir.join(KEY_FILE);
+                let archive_key = VaultKey::load_or_create(&archive_key).map_err(|e| QuarantineError::Io(archive_key, e))?;
+                vault::reseal_into(vault_path, &archive_path, &Self::key_for(vault_path)?, &archive_key)
+            }
+            // a plain file from before the vault was encrypted, or already gone
+            false => vault::move_into(vault_path, &archive_path),
+        };
+        match archived {
+            Ok(()) => {
+                // locked like in the vault
+                if let Err(e) = fs::set_permissions(&archive_path, fs::Permissions::from_mode(0o000)) {
+                    eprintln!("Couldn't lock {:?}: {e}", archive_path);
+                }
+                Ok(Some(archive_path))
+            }
+            Err(QuarantineError::Io(_, e)) if e.kind() == io::ErrorKind::NotFound => {
+                eprintln!("{:?} was already gone", vault_path);
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// On-disk size of a vault file, a missing one takes no room
+fn vault_size(quarantine_path: &str) -> u64 {
+    fs::symlink_metadata(quarantine_path).map(|metadata| metadata.len()).unwrap_or(0)
+}
+
+/// `30d`, `12h` or `90m`, the way `parse_age` reads them
+pub(super) fn format_age(age: TimeDelta) -> String {
+    match age {
+        age if age.num_days() > 0 && age == TimeDelta::days(age.num_days()) => format!("{}d", age.num_days()),
+        age if age.num_hours() > 0 && age == TimeDelta::hours(age.num_hours()) => format!("{}h", age.num_hours()),
+        age if age.num_minutes() > 0 && age == TimeDelta::minutes(age.num_minutes()) => format!("{}m", age.num_minutes()),
+        age => format!("{}s", age.num_seconds()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("512K"), Ok(512 * 1024));
+        assert_eq!(parse_size("500MiB"), Ok(500 * 1024 * 1024));
+        assert_eq!(parse_size("2g"), Ok(2 << 30));
+        assert!(parse_size("2P").is_err());
+        assert!(parse_size("M").is_err());
+        assert_eq!(format_age(TimeDelta::weeks(2)), "14d");
+        assert_eq!(format_age(TimeDelta::minutes(90)), "90m");
+    }
+
+    #[test]
+    fn loads_the_stored_policy() {
+        let db = Connection::open_in_memory().unwrap();
+        db.execute_batch(
+            "CREATE TABLE quarantine_retention (
+                    id INTEGER PRIMARY KEY CHECK (id = 1),
+                    max_size INTEGER,
+                    max_age INTEGER,
+                    action TEXT NOT NULL,
+                    archive_dir TEXT NOT NULL,
+                    updated_date TEXT NOT NULL
+                )"
+        ).unwrap();
+        assert_eq!(RetentionPolicy::load(&db).unwrap().to_string(), "no limits, nothing is retired");
+
+        db.execute(
+            "INSERT INTO quarantine_retention VALUES (1, NULL, 2592000, 'archive', '/srv/archive', '2026-01-01T00:00:00+00:00')",
+            [],
+        ).unwrap();
+        let policy = RetentionPolicy::load(&db).unwrap();
+        assert_eq!(policy.max_age, Some(TimeDelta::days(30)));
+        assert_eq!(policy.max_size, None);
+        assert_eq!(policy.action, RetentionAction::Archive);
+        assert_eq!(policy.to_string(), "files up to 30d old, then archive into \"/srv/archive\"");
+    }
+}
@@ -0,0 +1,411 @@
+use std::{collections::BTreeMap, fs::{self, File, OpenOptions, Permissions}, io::{self, Read, Seek, SeekFrom}, os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt}, path::{Path, PathBuf}};
+use filetime::FileTime;
+use xattr::FileExt;
+
+use super::container::{self, ContainerHeader, VaultKey};
+use super::QuarantineError;
+
+/// What the original file looked like, kept in the database so it can be put back as it was
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FileMetadata {
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    /// Nanoseconds since the epoch
+    pub accessed: i64,
+    /// Nanoseconds since the epoch
+    pub modified: i64,
+    pub xattrs: Vec<(String, Vec<u8>)>,
+}
+
+impl FileMetadata {
+    /// Read from the open file, not from a path someone could point elsewhere in the meantime
+    pub fn of(file: &File) -> io::Result<Self> {
+        let metadata = file.metadata()?;
+        let xattrs = match file.list_xattr() {
+            Ok(names) => names
+                .filter_map(|name| {
+                    let value = file.get_xattr(&name).ok()??;
+                    Some((name.to_string_lossy().to_string(), value))
+                })
+                .collect(),
+            Err(e) if e.raw_os_error() == Some(libc::ENOTSUP) => vec![],
+            Err(e) => return Err(e),
+        };
+
+        Ok(Self {
+            mode: metadata.mode(),
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            size: metadata.size(),
+            accessed: metadata.atime() * 1_000_000_000 + metadata.atime_nsec(),
+            modified: metadata.mtime() * 1_000_000_000 + metadata.mtime_nsec(),
+            xattrs,
+        })
+    }
+
+    /// Puts the recorded owner, mode, xattrs and timestamps back on the open file, before it's renamed into place:
+    /// by path, root would follow a symlink the owner of the directory swapped in.
+    /// Only root can give a file away, a failed chown is a warning.
+    pub fn apply(&self, file: &File) -> io::Result<()> {
+        for (name, value) in &self.xattrs {
+            file.set_xattr(name, value)?;
+        }
+        // chown clears the setuid bits, so it goes before the chmod
+        if let Err(e) = std::os::unix::fs::fchown(file, Some(self.uid), Some(self.gid)) {
+            eprintln!("Couldn't give the file back to {}:{}: {e}", self.uid, self.gid);
+        }
+        file.set_permissions(Permissions::from_mode(self.mode & 0o7777))?;
+
+        let nanos = |time: i64| FileTime::from_unix_time(time.div_euclid(1_000_000_000), time.rem_euclid(1_000_000_000) as u32);
+        filetime::set_file_handle_times(file, Some(nanos(self.accessed)), Some(nanos(self.modified)))
+    }
+
+    /// The xattrs as a JSON object of hex encoded values, for the `xattrs` column
+    pub fn xattrs_to_json(&self) -> String {
+        let xattrs = self.xattrs.iter()
+            .map(|(name, value)| (name.clone(), hex::encode(value)))
+            .collect::<BTreeMap<String, String>>();
+        serde_json::to_string(&xattrs).unwrap_or_default()
+    }
+
+    pub fn xattrs_from_json(json: &str) -> Vec<(String, Vec<u8>)> {
+        serde_json::from_str::<BTreeMap<String, String>>(json)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|(name, value)| Some((name, hex::decode(value).ok()?)))
+            .collect()
+    }
+}
+
+/// Encrypts `original` into `vault_path`, filling in the header's metadata and SHA-256, then unlinks and wipes the original.
+/// If the original can't be removed, the vault file is dropped and the original is left untouched.
+pub fn seal_into(original: &Path, vault_path: &Path, key: &VaultKey, header: &mut ContainerHeader) -> Result<(), QuarantineError> {
+    write_then_unlink(original, vault_path, |source, sealed| {
+        header.metadata = FileMetadata::of(source)?;
+        header.sha256 = container::sha256(source)?;
+        source.seek(SeekFrom::Start(0))?;
+        container::seal(key, header, source, sealed)
+    })
+}
+
+/// Decrypts the vault file to `target`, replacing whatever is there, and removes the vault file.
+/// Nothing is written to `target` unless the sample matches its SHA-256.
+/// It gets its recorded metadata back, unless it was imported: an imported sample's owner and mode are another machine's.
+pub fn unseal_to(vault_path: &Path, target: &Path, key: &VaultKey) -> Result<ContainerHeader, QuarantineError> {
+    let mut sealed = File::open(vault_path).map_err(|e| QuarantineError::Io(vault_path.to_path_buf(), e))?;
+    let partial = partial_path(target);
+    let opened = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&partial)
+        .and_then(|mut opened| {
+            let header = container::open(key, &mut sealed, &mut opened)?;
+            opened.sync_all()?;
+            if !header.imported && let Err(e) = header.metadata.apply(&opened) {
+                eprintln!("Couldn't restore the metadata of {:?}: {e}", target);
+            }
+            Ok(header)
+        });
+    let header = match opened {
+        Ok(header) => header,
+        Err(e) => {
+            let _ = fs::remove_file(&partial);
+            return Err(QuarantineError::Io(vault_path.to_path_buf(), e));
+        }
+    };
+
+    fs::rename(&partial, target).map_err(|e| QuarantineError::Io(target.to_path_buf(), e))?;
+    if let Err(e) = fs::remove_file(vault_path) {
+        eprintln!("Restored {:?} but couldn't remove {:?}: {e}", target, vault_path);
+    }
+    Ok(header)
+}
+
+/// Re-encrypts a vault file from `from` to `to` into `destination`, and removes the vault file
+pub fn reseal_into(vault_path: &Path, destination: &Path, from: &VaultKey, to: &VaultKey) -> Result<(), QuarantineError> {
+    let mut sealed = File::open(vault_path).map_err(|e| QuarantineError::Io(vault_path.to_path_buf(), e))?;
+    let partial = partial_path(destination);
+    let written = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&partial)
+        .and_then(|mut written| {
+            container::reseal(from, to, &mut sealed, &mut written)?;
+            written.sync_all()
+        });
+    if let Err(e) = written {
+        let _ = fs::remove_file(&partial);
+        return Err(QuarantineError::Io(vault_path.to_path_buf(), e));
+    }
+
+    fs::rename(&partial, destination).map_err(|e| QuarantineError::Io(destination.to_path_buf(), e))?;
+    if let Err(e) = fs::remove_file(vault_path) {
+        eprintln!("Moved {:?} to {:?} but couldn't remove it: {e}", vault_path, destination);
+    }
+    Ok(())
+}
+
+/// Moves a plain file, with a rename when both are on the same filesystem.
+/// Only vault files from before they were encrypted still go through here.
+pub fn move_into(original: &Path, destination: &Path) -> Result<(), QuarantineError> {
+    match fs::rename(original, destination) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            write_then_unlink(original, destination, |source, copy| io::copy(source, copy).map(drop))
+        }
+        Err(e) if is_removal_error(&e) => Err(QuarantineError::OriginalNotRemoved(original.to_path_buf(), e)),
+        Err(e) => Err(QuarantineError::Io(original.to_path_buf(), e)),
+    }
+}
+
+/// Opens `original` once, without following a symlink, and only if it's a regular file.
+/// Everything after goes through that descriptor: the owner of the file could swap the name for a link to /etc/shadow.
+fn open_original(original: &Path) -> Result<(File, bool), QuarantineError> {
+    // a FIFO would block the open
+    let open = |write| OpenOptions::new().read(true).write(write).custom_flags(libc::O_NOFOLLOW | libc::O_NONBLOCK).open(original);
+    let (file, writable) = match open(true) {
+        Ok(file) => (file, true),
+        Err(e) if e.raw_os_error() == Some(libc::ELOOP) => return Err(QuarantineError::NotAFile(original.to_path_buf())),
+        Err(_) => match open(false) {
+            Ok(file) => (file, false),
+            Err(e) if e.raw_os_error() == Some(libc::ELOOP) => return Err(QuarantineError::NotAFile(original.to_path_buf())),
+            Err(e) => return Err(QuarantineError::Io(original.to_path_buf(), e)),
+        },
+    };
+    match file.metadata() {
+        Ok(metadata) if metadata.is_file() => Ok((file, writable)),
+        Ok(_) => Err(QuarantineError::NotAFile(original.to_path_buf())),
+        Err(e) => Err(QuarantineError::Io(original.to_path_buf(), e)),
+    }
+}
+
+/// Whether `path` still names the file open as `file`
+fn still_names(path: &Path, file: &File) -> io::Result<bool> {
+    let (named, open) = (fs::symlink_metadata(path)?, file.metadata()?);
+    Ok(named.dev() == open.dev() && named.ino() == open.ino())
+}
+
+/// Writes `destination` from `original` with `write`, then unlinks and wipes the original.
+/// If the original can't be removed, `destination` is never created.
+fn write_then_unlink<F>(original: &Path, destination: &Path, write: F) -> Result<(), QuarantineError>
+where
+    F: FnOnce(&mut File, &mut File) -> io::Result<()>,
+{
+    // keep the original open, its bytes can still be wiped once the name is gone
+    let (mut source, writable) = open_original(original)?;
+
+    let partial = partial_path(destination);
+    let written = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&partial)
+        .and_then(|mut written| {
+            write(&mut source, &mut written)?;
+            written.sync_all()
+        });
+    if let Err(e) = written {
+        let _ = fs::remove_file(&partial);
+        return Err(QuarantineError::Io(original.to_path_buf(), e));
+    }
+
+    // the name was swapped after the open, what it names now isn't what was sealed
+    match still_names(original, &source) {
+        Ok(true) => {}
+        Ok(false) => {
+            let _ = fs::remove_file(&partial);
+            return Err(QuarantineError::NotAFile(original.to_path_buf()));
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&partial);
+            return Err(QuarantineError::Io(original.to_path_buf(), e));
+        }
+    }
+    if let Err(e) = fs::remove_file(original) {
+        let _ = fs::remove_file(&partial);
+        return Err(QuarantineError::OriginalNotRemoved(original.to_path_buf(), e));
+    }
+
+    if !writable {
+        eprintln!("{:?} wasn't writable, its unlinked bytes weren't wiped", original);
+    } else if let Err(e) = wipe(&mut source) {
+        eprintln!("Couldn't wipe the unlinked {:?}, its bytes may still be on disk: {e}", original);
+    }
+
+    fs::rename(&partial, destination).map_err(|e| QuarantineError::Io(destination.to_path_buf(), e))
+}
+
+/// `<path>.part`, where a file is written before being renamed into place
+pub fn partial_path(path: &Path) -> PathBuf {
+    let mut partial = path.as_os_str().to_os_string();
+    partial.push(".part");
+    PathBuf::from(partial)
+}
+
+/// Overwrites the whole file with zeros
+fn wipe(file: &mut File) -> io::Result<()> {
+    let len = file.metadata()?.len();
+    file.seek(SeekFrom::Start(0))?;
+    io::copy(&mut io::repeat(0).take(len), file)?;
+    file.sync_all()
+}
+
+/// `path` with its directory resolved, if that's `vault_dir` itself and it names a sample there:
+/// not the key, an unfinished write, or anything but a regular file. A missing sample still resolves.
+/// Records only say where a sample is, they don't get to send root anywhere else.
+pub fn resolve(vault_dir: &Path, path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?;
+    if name.to_string_lossy().starts_with('.') || Path::new(name).extension().is_some_and(|ext| ext == "part") {
+        return None;
+    }
+    let dir = fs::canonicalize(path.parent()?).ok()?;
+    if dir != fs::canonicalize(vault_dir).ok()? {
+        return None;
+    }
+
+    let resolved = dir.join(name);
+    match fs::symlink_metadata(&resolved) {
+        Ok(metadata) if !metadata.is_file() => None,
+        _ => Some(resolved),
+    }
+}
+
+/// Wipes and unlinks a sample of `vault_dir` for good, refusing anything `resolve` doesn't
+pub fn secure_delete(vault_dir: &Path, path: &Path) -> io::Result<()> {
+    let path = resolve(vault_dir, path)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("{:?} isn't a sample in {:?}", path, vault_dir)))?;
+    let mut file = OpenOptions::new().write(true).custom_flags(libc::O_NOFOLLOW).open(&path)?;
+    // vault files are locked with mode 000, the open file is enough to unlock it
+    file.set_permissions(Permissions::from_mode(0o600))?;
+    wipe(&mut file)?;
+    fs::remove_file(path)
+}
+
+fn is_removal_error(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::PermissionDenied | io::ErrorKind::ReadOnlyFilesystem | io::ErrorKind::ResourceBusy
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seals_and_unseals_the_original() {
+        let dir = std::env::temp_dir().join(format!("sentinel_vault_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (original, vault_path) = (dir.join("payload"), dir.join("payload_vault"));
+        fs::write(&original, b"\x7fELF payload").unwrap();
+        fs::set_permissions(&original, Permissions::from_mode(0o751)).unwrap();
+        filetime::set_file_mtime(&original, FileTime::from_unix_time(1_600_000_000, 0)).unwrap();
+        let key = VaultKey::load_or_create(&dir.join(".vault.key")).unwrap();
+
+        let metadata = FileMetadata::of(&File::open(&original).unwrap()).unwrap();
+        let mut header = ContainerHeader {
+            sha256: String::new(),
+            original_path: original.to_string_lossy().to_string(),
+            reason: "test".to_string(),
+            quarantined_date: None,
+            metadata: FileMetadata::default(),
+            quarantined_by: None,
+            score: None,
+            imported: false,
+        };
+        seal_into(&original, &vault_path, &key, &mut header).unwrap();
+
+        assert!(!original.exists());
+        assert_eq!(header.metadata, metadata);
+        assert!(container::is_container(&vault_path));
+        assert_eq!(header.sha256, container::sha256(&mut &b"\x7fELF payload"[..]).unwrap());
+
+        assert_eq!(unseal_to(&vault_path, &original, &key).unwrap().metadata, metadata);
+        assert_eq!(fs::read(&original).unwrap(), b"\x7fELF payload");
+        // put back on the file before it got its name
+        let restored = FileMetadata::of(&File::open(&original).unwrap()).unwrap();
+        assert_eq!((restored.mode, restored.modified), (metadata.mode, metadata.modified));
+        assert!(!vault_path.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn never_seals_what_a_symlink_points_to() {
+        let dir = std::env::temp_dir().join(format!("sentinel_vault_link_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("shadow"), b"root:$6$secret").unwrap();
+        std::os::unix::fs::symlink(dir.join("shadow"), dir.join("payload")).unwrap();
+        let key = VaultKey::load_or_create(&dir.join(".vault.key")).unwrap();
+        let mut header = ContainerHeader {
+            sha256: String::new(),
+            original_path: dir.join("payload").to_string_lossy().to_string(),
+            reason: "test".to_string(),
+            quarantined_date: None,
+            metadata: FileMetadata::default(),
+            quarantined_by: None,
+            score: None,
+            imported: false,
+        };
+
+        let sealed = seal_into(&dir.join("payload"), &dir.join("payload_vault"), &key, &mut header);
+        assert!(matches!(sealed, Err(QuarantineError::NotAFile(_))));
+        assert_eq!(fs::read(dir.join("shadow")).unwrap(), b"root:$6$secret");
+        assert!(!dir.join("payload_vault").exists() && !dir.join("payload_vault.part").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn resolves_only_samples_inside_the_vault() {
+        let dir = std::env::temp_dir().join(format!("sentinel_resolve_{}", std::process::id()));
+        let vault_dir = dir.join("vault");
+        fs::create_dir_all(&vault_dir).unwrap();
+        fs::write(vault_dir.join("payload_20240101000000"), b"sealed").unwrap();
+        fs::write(dir.join("passwd"), b"root:x:0:0").unwrap();
+        std::os::unix::fs::symlink(dir.join("passwd"), vault_dir.join("link_20240101000000")).unwrap();
+
+        let sample = vault_dir.join("payload_20240101000000");
+        assert_eq!(resolve(&vault_dir, &sample), Some(sample.clone()));
+        assert_eq!(resolve(&vault_dir, &vault_dir.join("gone_20240101000000")), Some(vault_dir.join("gone_20240101000000")));
+        assert_eq!(resolve(&vault_dir, &vault_dir.join("../passwd")), None);
+        assert_eq!(resolve(&vault_dir, &dir.join("passwd")), None);
+        assert_eq!(resolve(&vault_dir, &vault_dir.join("link_20240101000000")), None);
+        assert_eq!(resolve(&vault_dir, &vault_dir.join(".vault.key")), None);
+
+        assert_eq!(secure_delete(&vault_dir, &vault_dir.join("../passwd")).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(fs::read(dir.join("passwd")).unwrap(), b"root:x:0:0");
+        secure_delete(&vault_dir, &sample).unwrap();
+        assert!(!sample.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn applies_the_recorded_metadata() {
+        let path = std::env::temp_dir().join(format!("sentinel_restore_{}", std::process::id()));
+        fs::write(&path, b"restored").unwrap();
+        let metadata = FileMetadata {
+            mode: 0o100640,
+            modified: 1_600_000_000_123_456_789,
+            accessed: 1_600_000_000_000_000_000,
+            ..FileMetadata::of(&File::open(&path).unwrap()).unwrap()
+        };
+
+        metadata.apply(&File::open(&path).unwrap()).unwrap();
+        let restored = FileMetadata::of(&File::open(&path).unwrap()).unwrap();
+        assert_eq!(restored.mode, 0o100640);
+        assert_eq!(restored.modified, metadata.modified);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn xattrs_round_trip_through_json() {
+        let metadata = FileMetadata {
+            xattrs: vec![("user.origin".to_string(), b"\x00\xffmail".to_vec())],
+            ..Default::default()
+        };
+        assert_eq!(FileMetadata::xattrs_from_json(&metadata.xattrs_to_json()), metadata.xattrs);
+    }
+}
@@ -0,0 +1,202 @@
+use std::{collections::HashSet, fmt, fs::File, io, path::Path};
+use chrono::Local;
+use rusqlite::Connection;
+
+use super::{container, is_root, load_quarantined, QuarantineError, Quarantinizer};
+
+/// What `quarantine verify` found about one vault file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Finding {
+    Ok,
+    /// Recorded, but the vault file is gone
+    Missing,
+    /// The vault file doesn't decrypt, or isn't the sample that was recorded
+    Tampered(String),
+    /// In the vault without being recorded
+    Extra,
+    /// Couldn't be read at all, e.g. its vault key is gone
+    Unreadable(String),
+    /// Quarantined before hashes were recorded, nothing to check against
+    Unhashed,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Finding::Ok => write!(f, "ok"),
+            Finding::Missing => write!(f, "missing"),
+            Finding::Tampered(detail) => write!(f, "tampered: {detail}"),
+            Finding::Extra => write!(f, "extra"),
+            Finding::Unreadable(detail) => write!(f, "unreadable: {detail}"),
+            Finding::Unhashed => write!(f, "no stored hash"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Verification {
+    /// None for extra files
+    pub id: Option<i64>,
+    pub quarantine_path: String,
+    pub finding: Finding,
+}
+
+impl Quarantinizer {
+    /// Checks every recorded file against its vault file and stored SHA-256,
+    /// and looks for vault files nothing knows about. Root only.
+    pub fn verify(&self) -> Result<Vec<Verification>, QuarantineError> {
+        if !is_root() {
+            return Err(QuarantineError::NotRoot(self.quarantine_dir.clone()));
+        }
+        // straight from the database, the daemon keeps running while files get quarantined
+        let recorded = load_quarantined(self.db.as_ref().ok_or(QuarantineError::NoDatabase)?)?;
+
+        let mut verifications = recorded.iter()
+            .map(|qf| Verification {
+                id: qf.id,
+                quarantine_path: qf.quarantine_path.clone(),
+                finding: match self.vault_file(qf) {
+                    Ok(vault_path) => verify_file(&vault_path, qf.sha256.as_deref(), &qf.original_path),
+                    Err(_) => Finding::Tampered("Recorded outside the vault".to_string()),
+                },
+            })
+            .collect::<Vec<Verification>>();
+
+        let recorded_paths = recorded.iter().map(|qf| qf.quarantine_path.as_str()).collect::<HashSet<&str>>();
+        let vault_files = self.get_local_files(&self.quarantine_dir).map_err(|e| QuarantineError::Io(self.quarantine_dir.clone(), io::Error::other(e)))?;
+        verifications.extend(vault_files.into_iter()
+            .filter(|file| !recorded_paths.contains(file.quarantine_path.as_str()))
+            .map(|file| Verification { id: None, quarantine_path: file.quarantine_path, finding: Finding::Extra }));
+        Ok(verifications)
+    }
+
+    /// Keeps every problem in `quarantine_verifications`. A problem already recorded for the same vault file
+    /// only gets its `last_checked_date` moved, so the daemon's passes don't pile up rows.
+    pub fn record_verifications(&self, verifications: &[Verification]) -> Result<(), QuarantineError> {
+        record_findings(self.db.as_ref().ok_or(QuarantineError::NoDatabase)?, verifications)?;
+        Ok(())
+    }
+}
+
+fn record_findings(db: &Connection, verifications: &[Verification]) -> rusqlite::Result<()> {
+    let checked_date = Local::now().to_rfc3339();
+    for verification in verifications.iter().filter(|verification| verification.finding != Finding::Ok) {
+        db.execute(
+            "INSERT INTO quarantine_verifications (checked_date, last_checked_date, quarantined_file_id, quarantine_path, finding)
+                    VALUES ($1, $1, $2, $3, $4)
+                    ON CONFLICT (quarantine_path, finding) DO UPDATE SET
+                        last_checked_date = excluded.last_checked_date,
+                        quarantined_file_id = excluded.quarantined_file_id",
+            rusqlite::params![
+                checked_date,
+                verification.id,
+                verification.quarantine_path,
+                verification.finding.to_string(),
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+fn verify_file(vault_path: &Path, stored_sha256: Option<&str>, original_path: &str) -> Finding {
+    let mut file = match File::open(vault_path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Finding::Missing,
+        Err(e) => return Finding::Unreadable(e.to_string()),
+    };
+
+    if !container::is_container(vault_path) {
+        // a plain file from before the vault was encrypted, only the hash can be checked
+        return match (stored_sha256, container::sha256(&mut file)) {
+            (None, _) => Finding::Unhashed,
+            (Some(stored), Ok(sha256)) if stored == sha256 => Finding::Ok,
+            (Some(_), Ok(sha256)) => Finding::Tampered(format!("SHA-256 is now {sha256}")),
+            (Some(_), Err(e)) => Finding::Unreadable(e.to_string()),
+        };
+    }
+
+    let key = match Quarantinizer::key_for(vault_path) {
+        Ok(key) => key,
+        Err(e) => return Finding::Unreadable(e.to_string()),
+    };
+    // decrypting checks every chunk and the SHA-256 in the header
+    let header = match container::open(&key, &mut file, &mut io::sink()) {
+        Ok(header) => header,
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => return Finding::Tampered(e.to_string()),
+        Err(e) => return Finding::Unreadable(e.to_string()),
+    };
+
+    match stored_sha256 {
+        None => Finding::Unhashed,
+        Some(stored) if stored != header.sha256 => Finding::Tampered("Sample isn't the one that was quarantined".to_string()),
+        Some(_) if header.original_path != original_path => Finding::Tampered("Sample was quarantined from another path".to_string()),
+        Some(_) => Finding::Ok,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use super::*;
+    use crate::args_parser::quarantine::{vault, ContainerHeader, FileMetadata, VaultKey, KEY_FILE};
+
+    #[test]
+    fn finds_tampered_and_missing_samples() {
+        let dir = std::env::temp_dir().join(format!("sentinel_verify_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (original, vault_path) = (dir.join("payload"), dir.join("payload_vault"));
+        fs::write(&original, b"\x7fELF payload").unwrap();
+        let key = VaultKey::load_or_create(&dir.join(KEY_FILE)).unwrap();
+
+        let original_path = original.to_string_lossy().to_string();
+        let mut header = ContainerHeader {
+            sha256: String::new(),
+            original_path: original_path.clone(),
+            reason: "test".to_string(),
+            quarantined_date: None,
+            metadata: FileMetadata::default(),
+            quarantined_by: None,
+            score: None,
+            imported: false,
+        };
+        vault::seal_into(&original, &vault_path, &key, &mut header).unwrap();
+
+        assert_eq!(verify_file(&vault_path, Some(&header.sha256), &original_path), Finding::Ok);
+        assert!(matches!(verify_file(&vault_path, Some("0000"), &original_path), Finding::Tampered(_)));
+        assert_eq!(verify_file(&vault_path, None, &original_path), Finding::Unhashed);
+
+        let mut sealed = fs::read(&vault_path).unwrap();
+        *sealed.last_mut().unwrap() ^= 1;
+        fs::write(&vault_path, sealed).unwrap();
+        assert!(matches!(verify_file(&vault_path, Some(&header.sha256), &original_path), Finding::Tampered(_)));
+
+        fs::remove_file(&vault_path).unwrap();
+        assert_eq!(verify_file(&vault_path, Some(&header.sha256), &original_path), Finding::Missing);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn records_each_problem_once() {
+        let db = Connection::open_in_memory().unwrap();
+        db.execute_batch(
+            "CREATE TABLE quarantine_verifications (
+                    id INTEGER PRIMARY KEY,
+                    checked_date TEXT NOT NULL,
+                    last_checked_date TEXT,
+                    quarantined_file_id INTEGER,
+                    quarantine_path TEXT NOT NULL,
+                    finding TEXT NOT NULL
+                );
+            CREATE UNIQUE INDEX quarantine_verifications_finding ON quarantine_verifications (quarantine_path, finding);"
+        ).unwrap();
+        let verification = |path: &str, finding| Verification { id: Some(1), quarantine_path: path.to_string(), finding };
+
+        let pass = [verification("/vault/a", Finding::Missing), verification("/vault/b", Finding::Ok)];
+        record_findings(&db, &pass).unwrap();
+        record_findings(&db, &pass).unwrap();
+        record_findings(&db, &[verification("/vault/a", Finding::Unhashed)]).unwrap();
+
+        let rows = db.query_row("SELECT COUNT(*) FROM quarantine_verifications", [], |row| row.get::<_, i64>(0)).unwrap();
+        assert_eq!(rows, 2);
+    }
+}
@@ -0,0 +1,456 @@
+use std::{fmt, fs::File, io::{BufRead, BufReader}, path::{Path, PathBuf}, sync::{Arc, Mutex}};
+use chrono::Local;
+use clap::Subcommand;
+use md5::Md5;
+use rusqlite::{Connection, Result};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+#[derive(Subcommand, Clone)]
+pub enum ReputationCommands {
+    /// Mark a SHA-256, SHA-1 or MD5 as known bad or known good
+    Add {
+        hash: String,
+
+        /// bad (always flagged) or good (never scanned)
+        #[arg(long)]
+        verdict: HashVerdict,
+
+        /// e.g. the malware family
+        #[arg(long)]
+        name: Option<String>,
+    },
+    Remove {
+        hash: String,
+    },
+    /// Import a hash list: one hash per line (optionally followed by a name), or a CSV feed with sha256/sha1/md5 columns
+    Import {
+        file: PathBuf,
+
+        #[arg(long)]
+        verdict: HashVerdict,
+
+        /// text or csv, guessed from the extension by default
+        #[arg(long)]
+        format: Option<HashListFormat>,
+
+        /// Where the list came from, defaults to its file name
+        #[arg(long)]
+        source: Option<String>,
+    },
+    /// Show what the store knows about a hash
+    Lookup {
+        hash: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashVerdict {
+    Bad,
+    Good,
+}
+
+impl std::str::FromStr for HashVerdict {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "bad" | "malicious" | "block" => Ok(HashVerdict::Bad),
+            "good" | "trusted" | "allow" => Ok(HashVerdict::Good),
+            _ => Err(
+                format!("Invalid hash verdict: {s}.
+                    Use [bad, good]"))
+        }
+    }
+}
+
+impl HashVerdict {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HashVerdict::Bad => "bad",
+            HashVerdict::Good => "good",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashListFormat {
+    Text,
+    Csv,
+}
+
+impl std::str::FromStr for HashListFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" | "txt" => Ok(HashListFormat::Text),
+            "csv" => Ok(HashListFormat::Csv),
+            _ => Err(
+                format!("Invalid hash list format: {s}.
+                    Use [text, csv]"))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashKind {
+    Sha256,
+    Sha1,
+    Md5,
+}
+
+impl HashKind {
+    /// Told apart by length, hex only
+    pub fn of(hash: &str) -> Option<Self> {
+        if !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        match hash.len() {
+            64 => Some(HashKind::Sha256),
+            40 => Some(HashKind::Sha1),
+            32 => Some(HashKind::Md5),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            HashKind::Sha256 => "sha256",
+            HashKind::Sha1 => "sha1",
+            HashKind::Md5 => "md5",
+        }
+    }
+}
+
+impl fmt::Display for HashKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HashKind::Sha256 => write!(f, "SHA-256"),
+            HashKind::Sha1 => write!(f, "SHA-1"),
+            HashKind::Md5 => write!(f, "MD5"),
+        }
+    }
+}
+
+/// A hash the store has a verdict for
+#[derive(Debug, Clone, PartialEq)]
+pub struct KnownHash {
+    pub hash: String,
+    pub kind: HashKind,
+    pub verdict: HashVerdict,
+    pub name: Option<String>,
+    pub source: String,
+}
+
+impl fmt::Display for KnownHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let verdict = match self.verdict {
+            HashVerdict::Bad => "known bad",
+            HashVerdict::Good => "known good",
+        };
+        match &self.name {
+            Some(name) => write!(f, "{} {verdict} ({name}, from {})", self.kind, self.source),
+            None => write!(f, "{} {verdict} (from {})", self.kind, self.source),
+        }
+    }
+}
+
+/// The hashes of a file the store has lists for. SHA-256 is always there, the scan cache needs it.
+#[derive(Debug, Clone)]
+pub struct FileHashes {
+    pub sha256: String,
+    pub sha1: Option<String>,
+    pub md5: Option<String>,
+}
+
+impl FileHashes {
+    /// Only the SHA-256, for scans without hash lists
+    pub fn sha256(data: &[u8]) -> Self {
+        Self { sha256: hex::encode(Sha256::digest(data)), sha1: None, md5: None }
+    }
+}
+
+/// The `hash_reputation` table of `scanner.db`, looked up by `FileScanner` before the models.
+/// Known bad files are flagged without asking the models, known good ones aren't scanned at all.
+pub struct HashReputation {
+    db: Arc<Mutex<Connection>>,
+    /// Which kinds have at least one hash, the others aren't worth computing
+    has_sha1: bool,
+    has_md5: bool,
+    empty: bool,
+}
+
+impl HashReputation {
+    pub fn from_db(conn: Connection) -> Result<Self> {
+        Self::from_shared(Arc::new(Mutex::new(conn)))
+    }
+
+    /// On the connection a scan shares with its cache
+    pub fn from_shared(db: Arc<Mutex<Connection>>) -> Result<Self> {
+        let conn = db.lock().unwrap();
+        let has_kind = |kind: HashKind| conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM hash_reputation WHERE kind = $1)", [kind.as_str()], |row| row.get::<_, bool>(0),
+        );
+        let empty = !conn.query_row("SELECT EXISTS (SELECT 1 FROM hash_reputation)", [], |row| row.get::<_, bool>(0))?;
+        let (has_sha1, has_md5) = (has_kind(HashKind::Sha1)?, has_kind(HashKind::Md5)?);
+        drop(conn);
+        Ok(Self { db, has_sha1, has_md5, empty })
+    }
+
+    /// When it was opened
+    pub fn is_empty(&self) -> bool {
+        self.empty
+    }
+
+    /// Changes whenever a hash is added, removed or imported, so results cached before can be told apart
+    pub fn version(&self) -> Result<String> {
+        self.db.lock().unwrap().query_row(
+            "SELECT count(*), coalesce(max(rowid), 0), coalesce(max(added_date), '') FROM hash_reputation",
+            [],
+            |row| Ok(format!("{}-{}-{}", row.get::<_, i64>(0)?, row.get::<_, i64>(1)?, row.get::<_, String>(2)?)),
+        )
+    }
+
+    pub fn hashes(&self, data: &[u8]) -> FileHashes {
+        FileHashes {
+            sha1: self.has_sha1.then(|| hex::encode(Sha1::digest(data))),
+            md5: self.has_md5.then(|| hex::encode(Md5::digest(data))),
+            ..FileHashes::sha256(data)
+        }
+    }
+
+    /// Known bad wins when a file's hashes, or the lists a hash is on, disagree
+    pub fn lookup(&self, hashes: &FileHashes) -> Result<Option<KnownHash>> {
+        let db = self.db.lock().unwrap();
+        let mut stmt = db.prepare_cached("SELECT hash, verdict, name, source FROM hash_reputation WHERE hash = $1")?;
+        let mut known = vec![];
+        for hash in [Some(&hashes.sha256), hashes.sha1.as_ref(), hashes.md5.as_ref()].into_iter().flatten() {
+            for found in stmt.query_map([hash], from_row)? {
+                known.push(found?);
+            }
+        }
+        known.sort_by_key(|known| known.verdict != HashVerdict::Bad);
+        Ok(known.into_iter().next())
+    }
+
+    /// Replaces what `source` said about the hash before. Returns false if the hash isn't a SHA-256, SHA-1 or MD5
+    pub fn add(&self, hash: &str, verdict: HashVerdict, name: Option<&str>, source: &str) -> Result<bool> {
+        let hash = hash.trim().to_lowercase();
+        let Some(kind) = HashKind::of(&hash) else { return Ok(false) };
+        insert(&self.db.lock().unwrap(), &hash, kind, verdict, name, source, &Local::now().to_rfc3339())?;
+        Ok(true)
+    }
+
+    /// Drops the hash from every list. Returns false if there was no such hash
+    pub fn remove(&self, hash: &str) -> Result<bool> {
+        let removed = self.db.lock().unwrap().execute("DELETE FROM hash_reputation WHERE hash = $1", [hash.trim().to_lowercase()])?;
+        Ok(removed > 0)
+    }
+
+    /// What every list says about the hash, known bad first
+    pub fn get(&self, hash: &str) -> Result<Vec<KnownHash>> {
+        let db = self.db.lock().unwrap();
+        let mut stmt = db.prepare("SELECT hash, verdict, name, source FROM hash_reputation WHERE hash = $1 ORDER BY verdict = 'good', source")?;
+        stmt.query_map([hash.trim().to_lowercase()], from_row)?.collect()
+    }
+
+    /// Imports every hash of the list in one transaction. Returns how many were imported and how many lines were skipped.
+    pub fn import(&self, path: &Path, verdict: HashVerdict, format: Option<HashListFormat>, source: Option<&str>) -> std::result::Result<(usize, usize), String> {
+        let format = format.unwrap_or_else(|| match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("csv") => HashListFormat::Csv,
+            _ => HashListFormat::Text,
+        });
+        let source = source.map(str::to_string)
+            .unwrap_or_else(|| path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default());
+        let file = File::open(path).map_err(|e| format!("Couldn't open {:?}: {e}", path))?;
+        let entries = match format {
+            HashListFormat::Text => parse_text(BufReader::new(file)),
+            HashListFormat::Csv => parse_csv(file),
+        }.map_err(|e| format!("Couldn't read {:?}: {e}", path))?;
+
+        let mut db = self.db.lock().unwrap();
+        let tx = db.transaction().map_err(|e| format!("Couldn't import {:?}: {e}", path))?;
+        let added_date = Local::now().to_rfc3339();
+        let (mut imported, mut skipped) = (0, 0);
+        for (hash, name) in entries {
+            match HashKind::of(&hash) {
+                Some(kind) => {
+                    insert(&tx, &hash, kind, verdict, name.as_deref(), &source, &added_date)
+                        .map_err(|e| format!("Couldn't import {:?}: {e}", path))?;
+                    imported += 1;
+                }
+                None => skipped += 1,
+            }
+        }
+        tx.commit().map_err(|e| format!("Couldn't import {:?}: {e}", path))?;
+        Ok((imported, skipped))
+    }
+}
+
+pub(crate) fn insert(db: &Connection, hash: &str, kind: HashKind, verdict: HashVerdict, name: Option<&str>, source: &str, added_date: &str) -> Result<usize> {
+    db.prepare_cached(
+        "INSERT OR REPLACE INTO hash_reputation (hash, kind, verdict, name, source, added_date) VALUES ($1, $2, $3, $4, $5, $6)",
+    )?
+    .execute(rusqlite::params![hash, kind.as_str(), verdict.as_str(), name, source, added_date])
+}
+
+fn from_row(row: &rusqlite::Row) -> Result<KnownHash> {
+    let hash = row.get::<_, String>(0)?;
+    Ok(KnownHash {
+        kind: HashKind::of(&hash).unwrap_or(HashKind::Sha256),
+        hash,
+        verdict: if row.get::<_, String>(1)? == HashVerdict::Good.as_str() { HashVerdict::Good } else { HashVerdict::Bad },
+        name: row.get(2)?,
+        source: row.get(3)?,
+    })
+}
+
+/// `hash_reputation` as `scanner.db` has it
+#[cfg(test)]
+pub(crate) fn create_table(conn: &Connection) -> Result<usize> {
+    conn.execute(
+        "CREATE TABLE hash_reputation (
+                hash TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                verdict TEXT NOT NULL,
+                name TEXT,
+                source TEXT NOT NULL,
+                added_date TEXT NOT NULL,
+                PRIMARY KEY (hash, source)
+            )",
+        [],
+    )
+}
+
+#[cfg(test)]
+impl HashReputation {
+    /// A store in memory holding `known`
+    pub(crate) fn in_memory(known: &[(&str, HashVerdict, &str)]) -> Self {
+        let conn = Connection::open_in_memory().unwrap();
+        create_table(&conn).unwrap();
+        for (hash, verdict, source) in known {
+            insert(&conn, hash, HashKind::of(hash).unwrap(), *verdict, None, source, "2026-01-01T00:00:00+00:00").unwrap();
+        }
+        Self::from_db(conn).unwrap()
+    }
+}
+
+/// `<hash> [name]` per line, `#` starts a comment. Unparsable lines come back as they are and get skipped.
+fn parse_text<R: BufRead>(reader: R) -> std::io::Result<Vec<(String, Option<String>)>> {
+    let mut entries = vec![];
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (hash, name) = match line.split_once(char::is_whitespace) {
+            Some((hash, name)) => (hash, Some(name.trim().to_string())),
+            None => (line, None),
+        };
+        entries.push((hash.to_lowercase(), name));
+    }
+    Ok(entries)
+}
+
+/// Columns named like `sha256`, `sha256_hash`, `sha1` or `md5` hold hashes, one like `signature` or `name` the name.
+/// The header may be commented out, as in abuse.ch feeds.
+fn parse_csv<R: std::io::Read>(reader: R) -> std::io::Result<Vec<(String, Option<String>)>> {
+    let mut csv = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+
+    let mut columns: Option<(Vec<usize>, Option<usize>)> = None;
+    let mut entries = vec![];
+    for record in csv.records() {
+        let record = record.map_err(std::io::Error::other)?;
+        let first = record.get(0).unwrap_or_default();
+        let Some((hash_columns, name_column)) = &columns else {
+            let header = record.iter()
+                .map(|field| unquote(field.trim_start_matches('#')).to_lowercase())
+                .collect::<Vec<String>>();
+            let hash_columns = header.iter()
+                .enumerate()
+                .filter(|(_, column)| ["sha256", "sha1", "md5"].iter().any(|kind| column.starts_with(kind)))
+                .map(|(i, _)| i)
+                .collect::<Vec<usize>>();
+            if !hash_columns.is_empty() {
+                let name_column = ["signature", "name", "malware", "family", "file_name"].iter()
+                    .find_map(|name| header.iter().position(|column| column == name));
+                columns = Some((hash_columns, name_column));
+            }
+            continue;
+        };
+        if first.starts_with('#') {
+            continue;
+        }
+
+        let name = name_column.and_then(|i| record.get(i)).map(unquote).filter(|name| !name.is_empty() && *name != "n/a").map(str::to_string);
+        for i in hash_columns {
+            if let Some(hash) = record.get(*i).map(unquote).filter(|hash| !hash.is_empty()) {
+                entries.push((hash.to_lowercase(), name.clone()));
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// abuse.ch puts a space between the comma and the quote, which leaves the quotes in the field
+fn unquote(field: &str) -> &str {
+    field.trim_matches(|c: char| c == '"' || c.is_whitespace())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_text_lists_and_csv_feeds() {
+        let text = "# known bad\nE3B0C44298FC1C149AFBF4C8996FB92427AE41E4649B934CA495991B7852B855 empty file\n\nd41d8cd98f00b204e9800998ecf8427e\nnot-a-hash\n";
+        let entries = parse_text(text.as_bytes()).unwrap();
+        assert_eq!(entries[0], ("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855".to_string(), Some("empty file".to_string())));
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries.iter().filter(|(hash, _)| HashKind::of(hash).is_some()).count(), 2);
+
+        let csv = "# abuse.ch\n# \"first_seen_utc\",\"sha256_hash\",\"md5_hash\",\"signature\"\n\"2024-01-01 00:00:00\", \"e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855\", \"d41d8cd98f00b204e9800998ecf8427e\", \"Mirai\"\n# end\n";
+        let entries = parse_csv(csv.as_bytes()).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries.iter().all(|(_, name)| name.as_deref() == Some("Mirai")));
+        assert_eq!(HashKind::of(&entries[1].0), Some(HashKind::Md5));
+    }
+
+    #[test]
+    fn known_bad_wins_over_known_good() {
+        let hashes = FileHashes {
+            sha1: Some(hex::encode(Sha1::digest(b"payload"))),
+            md5: Some(hex::encode(Md5::digest(b"payload"))),
+            ..FileHashes::sha256(b"payload")
+        };
+        let reputation = HashReputation::in_memory(&[
+            (&hashes.sha256, HashVerdict::Good, "vendor"),
+            (hashes.md5.as_deref().unwrap(), HashVerdict::Bad, "feed"),
+        ]);
+        let known = reputation.lookup(&hashes).unwrap().unwrap();
+        assert_eq!((known.verdict, known.kind, known.source.as_str()), (HashVerdict::Bad, HashKind::Md5, "feed"));
+
+        // one list trusting a hash doesn't hide another flagging it
+        reputation.add(&hashes.sha256, HashVerdict::Bad, None, "manual").unwrap();
+        let only_sha256 = FileHashes::sha256(b"payload");
+        assert_eq!(reputation.lookup(&only_sha256).unwrap().unwrap().source, "manual");
+        assert_eq!(reputation.get(&hashes.sha256).unwrap().len(), 2);
+        reputation.remove(&hashes.sha256).unwrap();
+        assert_eq!(reputation.lookup(&only_sha256).unwrap(), None);
+        reputation.add(&hashes.sha256, HashVerdict::Good, None, "vendor").unwrap();
+
+        let only_good = FileHashes { md5: None, ..hashes.clone() };
+        assert_eq!(reputation.lookup(&only_good).unwrap().unwrap().verdict, HashVerdict::Good);
+        assert_eq!(reputation.lookup(&FileHashes::sha256(b"other")).unwrap(), None);
+        assert!(!reputation.is_empty() && HashReputation::in_memory(&[]).is_empty());
+        // only the kinds the store has are computed
+        assert_eq!(reputation.hashes(b"payload").md5, hashes.md5);
+        assert_eq!(reputation.hashes(b"payload").sha1, None);
+    }
+}
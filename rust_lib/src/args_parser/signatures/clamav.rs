@@ -0,0 +1,268 @@
+// ClamAV signature databases, see "Creating signatures for ClamAV" in its manual.
+// Hash signatures: `.hdb` is `md5:size:name`, `.hsb` is `sha1-or-sha256:size:name[:flevel]`.
+// Body signatures: `.ndb` is `name:target:offset:hexsig[:min_flevel[:max_flevel]]`.
+// The `.hdu`/`.hsu`/`.ndu` variants (potentially unwanted) have the same format.
+use std::{iter::Peekable, path::Path, str::Chars};
+
+use crate::args_parser::reputation::HashKind;
+use crate::rules::{find_hex, match_hex, parse_hex_sequence, parse_jump, HexToken, MAX_HEX_STEPS};
+
+/// Longest a `*` reaches, like a YARA `[-]` jump
+const MAX_WILDCARD: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DatabaseKind {
+    /// MD5 hashes
+    Hdb,
+    /// SHA-1 or SHA-256 hashes
+    Hsb,
+    /// Hex body signatures
+    Ndb,
+}
+
+impl DatabaseKind {
+    /// Told apart by extension, the way ClamAV does
+    pub fn of(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "hdb" | "hdu" => Some(DatabaseKind::Hdb),
+            "hsb" | "hsu" => Some(DatabaseKind::Hsb),
+            "ndb" | "ndu" => Some(DatabaseKind::Ndb),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DatabaseKind::Hdb => "hdb",
+            DatabaseKind::Hsb => "hsb",
+            DatabaseKind::Ndb => "ndb",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HashSignature {
+    /// Lowercase hex
+    pub hash: String,
+    pub kind: HashKind,
+    pub name: String,
+}
+
+/// The size field isn't kept, the hash alone is what the reputation store looks up
+pub fn parse_hash_signature(line: &str, kind: DatabaseKind) -> Result<HashSignature, String> {
+    let mut fields = line.split(':');
+    let (Some(hash), Some(size), Some(name)) = (fields.next(), fields.next(), fields.next()) else {
+        return Err("expected hash:size:name".to_string());
+    };
+    if size != "*" && size.parse::<u64>().is_err() {
+        return Err(format!("invalid size {size:?}"));
+    }
+
+    let hash = hash.to_lowercase();
+    let hash_kind = HashKind::of(&hash).ok_or_else(|| format!("invalid hash {hash:?}"))?;
+    match (kind, hash_kind) {
+        (DatabaseKind::Hdb, HashKind::Md5) | (DatabaseKind::Hsb, HashKind::Sha1 | HashKind::Sha256) => {}
+        _ => return Err(format!("{hash_kind} hash in a .{} database", kind.as_str())),
+    }
+    Ok(HashSignature { hash, kind: hash_kind, name: name.to_string() })
+}
+
+/// The file types a body signature applies to. The ones needing ClamAV's normalization (HTML, mail, text)
+/// or its parsers (OLE2, PDF, graphics...) aren't supported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Any,
+    Pe,
+    Elf,
+    MachO,
+}
+
+impl Target {
+    fn from_code(code: &str) -> Result<Self, String> {
+        match code {
+            "0" => Ok(Target::Any),
+            "1" => Ok(Target::Pe),
+            "6" => Ok(Target::Elf),
+            "9" => Ok(Target::MachO),
+            "3" | "4" | "7" => Err(format!("target type {code} needs ClamAV's normalization")),
+            _ => Err(format!("target type {code} isn't supported")),
+        }
+    }
+
+    pub fn code(&self) -> u8 {
+        match self {
+            Target::Any => 0,
+            Target::Pe => 1,
+            Target::Elf => 6,
+            Target::MachO => 9,
+        }
+    }
+
+    fn accepts(&self, data: &[u8]) -> bool {
+        match self {
+            Target::Any => true,
+            Target::Pe => data.starts_with(b"MZ"),
+            Target::Elf => data.starts_with(b"\x7fELF"),
+            Target::MachO => matches!(
+                data.get(..4),
+                Some([0xfe, 0xed, 0xfa, 0xce | 0xcf] | [0xce | 0xcf, 0xfa, 0xed, 0xfe] | [0xca, 0xfe, 0xba, 0xbe])
+            ),
+        }
+    }
+}
+
+/// Where a body signature has to start
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Offset {
+    /// `*`
+    Any,
+    /// `n` or `n,maxshift`: anywhere from `n` to `n + maxshift`
+    At(usize, usize),
+    /// `EOF-n` or `EOF-n,maxshift`
+    FromEnd(usize, usize),
+}
+
+impl Offset {
+    fn parse(offset: &str) -> Result<Self, String> {
+        if offset == "*" {
+            return Ok(Offset::Any);
+        }
+        let (start, shift) = match offset.split_once(',') {
+            Some((start, shift)) => (start, shift.parse::<usize>().map_err(|_| format!("invalid offset {offset:?}"))?),
+            None => (offset, 0),
+        };
+        if let Some(from_end) = start.strip_prefix("EOF-") {
+            return from_end.parse().map(|n| Offset::FromEnd(n, shift)).map_err(|_| format!("invalid offset {offset:?}"));
+        }
+        if start.starts_with("EP") || start.starts_with('S') || start.starts_with("VI") {
+            return Err("offsets relative to the entry point or sections aren't supported".to_string());
+        }
+        start.parse().map(|n| Offset::At(n, shift)).map_err(|_| format!("invalid offset {offset:?}"))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BodySignature {
+    pub name: String,
+    pub target: Target,
+    pub offset: Offset,
+    pub pattern: Vec<HexToken>,
+}
+
+impl BodySignature {
+    /// A `.ndb` line
+    pub fn parse_line(line: &str) -> Result<Self, String> {
+        let fields = line.split(':').collect::<Vec<&str>>();
+        match fields[..] {
+            [name, target, offset, hexsig, ..] => Self::parse(name, target, offset, hexsig),
+            _ => Err("expected name:target:offset:hexsig".to_string()),
+        }
+    }
+
+    pub fn parse(name: &str, target: &str, offset: &str, hexsig: &str) -> Result<Self, String> {
+        Ok(Self {
+            name: name.to_string(),
+            target: Target::from_code(target)?,
+            offset: Offset::parse(offset)?,
+            pattern: parse_hexsig(hexsig)?,
+        })
+    }
+
+    /// The longest run of fixed bytes outside alternatives, every match contains it
+    pub fn atom(&self) -> Vec<u8> {
+        self.pattern
+            .split(|token| !matches!(token, HexToken::Byte { mask: 0xff, .. }))
+            .max_by_key(|run| run.len())
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|token| match token {
+                HexToken::Byte { value, .. } => Some(*value),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn matches(&self, data: &[u8]) -> bool {
+        if !self.target.accepts(data) {
+            return false;
+        }
+        let (start, shift) = match self.offset {
+            Offset::Any => return find_hex(&self.pattern, data).next().is_some(),
+            Offset::At(start, shift) => (start, shift),
+            Offset::FromEnd(from_end, shift) => match data.len().checked_sub(from_end) {
+                Some(start) => (start, shift),
+                None => return false,
+            },
+        };
+        let mut steps = MAX_HEX_STEPS;
+        (start..=start.saturating_add(shift).min(data.len())).any(|pos| match_hex(&self.pattern, data, pos, &mut steps).is_some())
+    }
+}
+
+/// `deadbeef??0a*cafe{4-8}(01|0203)`, the YARA hex string syntax with `{n-m}` and `*` for jumps
+fn parse_hexsig(hexsig: &str) -> Result<Vec<HexToken>, String> {
+    if hexsig.contains('!') {
+        return Err("negated alternatives aren't supported".to_string());
+    }
+    if hexsig.contains('[') {
+        return Err("anchored byte ranges aren't supported".to_string());
+    }
+    if hexsig.split('(').skip(1).any(|after| after.starts_with(|c: char| c.is_ascii_uppercase())) {
+        return Err("word and line boundary markers aren't supported".to_string());
+    }
+    let tokens = parse_hex_sequence(&mut hexsig.chars().peekable(), false, &|chars: &mut Peekable<Chars>| match chars.peek()? {
+        '*' => {
+            chars.next();
+            Some(Ok(HexToken::Jump(0, Some(MAX_WILDCARD))))
+        }
+        '{' => {
+            chars.next();
+            let range = chars.by_ref().take_while(|c| *c != '}').collect::<String>();
+            Some(match parse_jump(&range) {
+                Some((min, max)) => Ok(HexToken::Jump(min, Some(max.unwrap_or(min.saturating_add(MAX_WILDCARD))))),
+                None => Err(format!("invalid wildcard {{{range}}}")),
+            })
+        }
+        _ => None,
+    })?;
+    if !tokens.iter().any(|token| matches!(token, HexToken::Byte { mask: 0xff, .. })) {
+        return Err("signature without a single fixed byte".to_string());
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_matches_clamav_signatures() {
+        let md5 = parse_hash_signature("44D88612FEA8A8F36DE82E1278ABB02F:68:Eicar-Test-Signature", DatabaseKind::Hdb).unwrap();
+        assert_eq!((md5.hash.as_str(), md5.kind, md5.name.as_str()), ("44d88612fea8a8f36de82e1278abb02f", HashKind::Md5, "Eicar-Test-Signature"));
+        assert!(parse_hash_signature("44d88612fea8a8f36de82e1278abb02f:68:Eicar", DatabaseKind::Hsb).is_err());
+        assert!(parse_hash_signature(&format!("{}:*:Test:73", "a".repeat(64)), DatabaseKind::Hsb).is_ok());
+
+        let any = BodySignature::parse_line("Test.Any:0:*:6576696c??21*6f7574(0a|0d0a)").unwrap();
+        assert!(any.matches(b"xx evil!! then out\r\n"));
+        assert!(!any.matches(b"xx evil!! then out"));
+
+        let elf = BodySignature::parse_line("Unix.Test:6:4,2:0201{2-3}aabb:18").unwrap();
+        assert!(elf.matches(b"\x7fELF\x02\x01\0\0\xaa\xbb"));
+        assert!(elf.matches(b"\x7fELF\0\x02\x01\0\0\0\xaa\xbb"));
+        assert!(!elf.matches(b"\x7fELF\0\0\0\x02\x01\0\0\xaa\xbb"));
+        assert!(!elf.matches(b"MZ\0\0\x02\x01\0\0\xaa\xbb"));
+
+        let tail = BodySignature::parse_line("Win.Test:1:EOF-4:deadbeef").unwrap();
+        assert!(tail.matches(b"MZ..\xde\xad\xbe\xef"));
+        assert_eq!(any.atom(), b"evil");
+        assert!(!tail.matches(b"MZ\xde\xad\xbe\xef.."));
+
+        assert!(BodySignature::parse_line("Html.Test:3:*:3c736372697074").is_err());
+        assert!(BodySignature::parse_line("Win.Test:1:EP+0:e800000000").is_err());
+        assert!(BodySignature::parse_line("Win.Test:0:*:aabb!(cc|dd)").is_err());
+        assert!(BodySignature::parse_line("Win.Test:0:*:????").is_err());
+        assert!(BodySignature::parse_line("Win.Test:0:*:aabb{8-4}ccdd").is_err());
+        assert!(BodySignature::parse_line("Win.Test:0:*:aabb(B)ccdd").is_err());
+        assert!(BodySignature::parse_line("Win.Test:0:*:aabb[1-2]ccdd").is_err());
+    }
+}
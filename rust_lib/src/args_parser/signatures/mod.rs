@@ -0,0 +1,300 @@
+mod clamav;
+
+pub use clamav::{BodySignature, DatabaseKind, HashSignature, Offset, Target};
+
+use std::{collections::BTreeMap, fs, path::{Path, PathBuf}};
+use aho_corasick::AhoCorasick;
+use chrono::{DateTime, Local};
+use clap::Subcommand;
+use rusqlite::{Connection, Result};
+
+use crate::args_parser::reputation::{self, HashVerdict};
+
+#[derive(Subcommand, Clone)]
+pub enum SignatureCommands {
+    /// Import ClamAV .hdb/.hsb hash and .ndb body signature databases.
+    /// Importing a database again replaces what it brought last time
+    Import {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// The imported databases
+    List,
+    /// Drop every signature an imported database brought
+    Remove {
+        /// The database file name, as shown by `list`
+        source: String,
+    },
+}
+
+/// What importing one database did
+#[derive(Debug, Clone, Default)]
+pub struct Imported {
+    pub source: String,
+    pub hashes: usize,
+    pub bodies: usize,
+    /// How many signatures were skipped, by reason
+    pub skipped: BTreeMap<String, usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SignatureDatabase {
+    pub source: String,
+    pub kind: String,
+    pub hashes: usize,
+    pub bodies: usize,
+    pub skipped: usize,
+    pub imported_date: Option<DateTime<Local>>,
+}
+
+/// Signature databases imported into `scanner.db`. Hash signatures go to the hash reputation store
+/// as known bad hashes, body signatures to `body_signatures`, matched by `BodySignatures`.
+pub struct SignatureStore {
+    db: Connection,
+}
+
+impl SignatureStore {
+    pub fn from_db(conn: Connection) -> Self {
+        Self { db: conn }
+    }
+
+    /// Everything in one transaction, a database that can't be read leaves the store as it was
+    pub fn import(&mut self, path: &Path) -> std::result::Result<Imported, String> {
+        let kind = DatabaseKind::of(path)
+            .ok_or_else(|| format!("{:?} isn't a ClamAV .hdb, .hsb or .ndb database", path))?;
+        let source = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+        let content = fs::read(path).map_err(|e| format!("Couldn't read {:?}: {e}", path))?;
+        let db_error = |e: rusqlite::Error| format!("Couldn't import {:?}: {e}", path);
+
+        let tx = self.db.transaction().map_err(db_error)?;
+        forget(&tx, &source).map_err(db_error)?;
+        let added_date = Local::now().to_rfc3339();
+        let mut imported = Imported { source: source.clone(), ..Imported::default() };
+        for line in String::from_utf8_lossy(&content).lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+            let outcome = match kind {
+                DatabaseKind::Hdb | DatabaseKind::Hsb => clamav::parse_hash_signature(line, kind).map(|signature| {
+                    reputation::insert(&tx, &signature.hash, signature.kind, HashVerdict::Bad, Some(&signature.name), &source, &added_date)
+                        .map(|_| imported.hashes += 1)
+                }),
+                DatabaseKind::Ndb => BodySignature::parse_line(line).map(|_| {
+                    let fields = line.splitn(5, ':').collect::<Vec<&str>>();
+                    tx.prepare_cached(
+                        "INSERT INTO body_signatures (name, target, offset, pattern, source, added_date) VALUES ($1, $2, $3, $4, $5, $6)",
+                    )
+                    .and_then(|mut stmt| stmt.execute(rusqlite::params![fields[0], fields[1], fields[2], fields[3], source, added_date]))
+                    .map(|_| imported.bodies += 1)
+                }),
+            };
+            match outcome {
+                Ok(inserted) => inserted.map_err(db_error)?,
+                Err(reason) => *imported.skipped.entry(reason).or_default() += 1,
+            }
+        }
+
+        tx.execute(
+            "INSERT INTO signature_databases (source, path, kind, hashes, bodies, skipped, imported_date) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            rusqlite::params![
+                source,
+                path.to_string_lossy(),
+                kind.as_str(),
+                imported.hashes as i64,
+                imported.bodies as i64,
+                imported.skipped.values().sum::<usize>() as i64,
+                added_date,
+            ],
+        ).map_err(db_error)?;
+        tx.commit().map_err(db_error)?;
+        Ok(imported)
+    }
+
+    pub fn list(&self) -> Result<Vec<SignatureDatabase>> {
+        let mut stmt = self.db.prepare(
+            "SELECT source, kind, hashes, bodies, skipped, imported_date FROM signature_databases ORDER BY source",
+        )?;
+        stmt.query_map([], |row| Ok(SignatureDatabase {
+            source: row.get(0)?,
+            kind: row.get(1)?,
+            hashes: row.get::<_, i64>(2)? as usize,
+            bodies: row.get::<_, i64>(3)? as usize,
+            skipped: row.get::<_, i64>(4)? as usize,
+            imported_date: row.get::<_, String>(5).ok()
+                .and_then(|date| DateTime::parse_from_rfc3339(&date).ok())
+                .map(|date| date.with_timezone(&Local)),
+        }))?.collect()
+    }
+
+    /// Returns false if no database was imported under that name
+    pub fn remove(&mut self, source: &str) -> Result<bool> {
+        let tx = self.db.transaction()?;
+        let removed = forget(&tx, source)?;
+        tx.commit()?;
+        Ok(removed)
+    }
+}
+
+/// Drops the signatures of a database and its `signature_databases` row
+fn forget(db: &Connection, source: &str) -> Result<bool> {
+    let removed = db.execute("DELETE FROM signature_databases WHERE source = $1", [source])?;
+    db.execute("DELETE FROM hash_reputation WHERE source = $1", [source])?;
+    db.execute("DELETE FROM body_signatures WHERE source = $1", [source])?;
+    Ok(removed > 0)
+}
+
+/// Atoms shorter than this occur in about every file, their signatures are matched against all of them
+const MIN_ATOM: usize = 3;
+
+/// The body signatures of `scanner.db`, compiled once and matched by `FileScanner` against every file.
+/// A file is only matched against the signatures whose atom it contains, found in one pass over it.
+#[derive(Debug, Clone, Default)]
+pub struct BodySignatures {
+    signatures: Vec<BodySignature>,
+    atoms: Option<AhoCorasick>,
+    /// The signature each atom comes from
+    atom_signatures: Vec<usize>,
+    /// Signatures without an atom worth looking for
+    unanchored: Vec<usize>,
+    version: String,
+}
+
+impl BodySignatures {
+    pub fn from_db(conn: &Connection) -> Result<Self> {
+        let mut stmt = conn.prepare("SELECT name, target, offset, pattern FROM body_signatures ORDER BY id")?;
+        let signatures = stmt.query_map([], |row| Ok(BodySignature::parse(
+            &row.get::<_, String>(0)?,
+            &row.get::<_, String>(1)?,
+            &row.get::<_, String>(2)?,
+            &row.get::<_, String>(3)?,
+        )))?
+            // checked on import, a row only fails if it was edited by hand
+            .filter_map(|signature| signature.map(Result::ok).transpose())
+            .collect::<Result<Vec<BodySignature>>>()?;
+        let version = conn.query_row(
+            "SELECT count(*), coalesce(max(id), 0) FROM body_signatures",
+            [],
+            |row| Ok(format!("{}-{}", row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)),
+        )?;
+        Ok(Self::new(signatures, version))
+    }
+
+    fn new(signatures: Vec<BodySignature>, version: String) -> Self {
+        let (mut atoms, mut atom_signatures, mut unanchored) = (vec![], vec![], vec![]);
+        for (i, signature) in signatures.iter().enumerate() {
+            let atom = signature.atom();
+            if atom.len() >= MIN_ATOM {
+                atoms.push(atom);
+                atom_signatures.push(i);
+            } else {
+                unanchored.push(i);
+            }
+        }
+        match AhoCorasick::new(&atoms) {
+            Ok(automaton) => Self { signatures, atoms: Some(automaton), atom_signatures, unanchored, version },
+            // too many atoms for an automaton, every signature gets matched
+            Err(_) => Self { unanchored: (0..signatures.len()).collect(), signatures, atoms: None, atom_signatures: vec![], version },
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.signatures.is_empty()
+    }
+
+    /// Changes whenever a database is imported or removed
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// Names of the signatures matching `data`
+    pub fn scan(&self, data: &[u8]) -> Vec<String> {
+        let mut candidates = vec![false; self.signatures.len()];
+        for i in &self.unanchored {
+            candidates[*i] = true;
+        }
+        if let Some(atoms) = &self.atoms {
+            for found in atoms.find_overlapping_iter(data) {
+                candidates[self.atom_signatures[found.pattern().as_usize()]] = true;
+            }
+        }
+        self.signatures.iter()
+            .zip(candidates)
+            .filter(|(signature, candidate)| *candidate && signature.matches(data))
+            .map(|(signature, _)| signature.name.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args_parser::reputation::{FileHashes, HashReputation};
+
+    fn store() -> SignatureStore {
+        let conn = Connection::open_in_memory().unwrap();
+        reputation::create_table(&conn).unwrap();
+        conn.execute(
+            "CREATE TABLE body_signatures (
+                    id INTEGER PRIMARY KEY,
+                    name TEXT NOT NULL,
+                    target TEXT NOT NULL,
+                    offset TEXT NOT NULL,
+                    pattern TEXT NOT NULL,
+                    source TEXT NOT NULL,
+                    added_date TEXT NOT NULL
+                )",
+            [],
+        ).unwrap();
+        conn.execute(
+            "CREATE TABLE signature_databases (
+                    source TEXT PRIMARY KEY,
+                    path TEXT NOT NULL,
+                    kind TEXT NOT NULL,
+                    hashes INTEGER NOT NULL,
+                    bodies INTEGER NOT NULL,
+                    skipped INTEGER NOT NULL,
+                    imported_date TEXT NOT NULL
+                )",
+            [],
+        ).unwrap();
+        SignatureStore::from_db(conn)
+    }
+
+    #[test]
+    fn imports_replaces_and_removes_databases() {
+        let dir = std::env::temp_dir().join(format!("sentinel_signatures_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (hdb, ndb) = (dir.join("test.hdb"), dir.join("test.ndb"));
+        let eicar = "44d88612fea8a8f36de82e1278abb02f";
+        fs::write(&hdb, format!("# hashes\n{eicar}:68:Eicar-Test-Signature\n{eicar}:68:Eicar-Again\nnot-a-hash:1:Broken\n")).unwrap();
+        fs::write(&ndb, "Test.Evil:0:*:6576696c??21\nHtml.Test:3:*:3c736372697074\nTest.Short:0:*:(aa|bb)cc\n").unwrap();
+        let mut store = store();
+
+        let imported = store.import(&hdb).unwrap();
+        assert_eq!((imported.source.as_str(), imported.hashes, imported.skipped.values().sum::<usize>()), ("test.hdb", 2, 1));
+        let imported = store.import(&ndb).unwrap();
+        assert_eq!((imported.bodies, imported.skipped.len()), (2, 1));
+        // a hash added by hand is kept whatever happens to the databases
+        reputation::insert(&store.db, eicar, reputation::HashKind::Md5, HashVerdict::Bad, None, "manual", "2026-01-01T00:00:00+00:00").unwrap();
+
+        // importing again replaces instead of piling up
+        store.import(&hdb).unwrap();
+        let listed = store.list().unwrap();
+        assert_eq!(listed.iter().map(|db| (db.source.as_str(), db.hashes, db.bodies)).collect::<Vec<_>>(), vec![("test.hdb", 2, 0), ("test.ndb", 0, 2)]);
+        let count = |db: &Connection, table: &str| db.query_row(&format!("SELECT count(*) FROM {table}"), [], |row| row.get::<_, i64>(0)).unwrap();
+        assert_eq!((count(&store.db, "hash_reputation"), count(&store.db, "body_signatures")), (2, 2));
+
+        let bodies = BodySignatures::from_db(&store.db).unwrap();
+        assert_eq!(bodies.scan(b"all evil!! here \xbb\xcc"), vec!["Test.Evil", "Test.Short"]);
+        assert!(bodies.scan(b"all evil here").is_empty());
+
+        assert!(store.remove("test.hdb").unwrap());
+        assert!(store.remove("test.ndb").unwrap());
+        assert!(!store.remove("test.ndb").unwrap());
+        assert_eq!(count(&store.db, "body_signatures"), 0);
+        assert!(BodySignatures::from_db(&store.db).unwrap().is_empty());
+        let SignatureStore { db } = store;
+        let known = HashReputation::from_db(db).unwrap()
+            .lookup(&FileHashes { md5: Some(eicar.to_string()), ..FileHashes::sha256(b"") })
+            .unwrap();
+        assert_eq!(known.map(|known| known.source), Some("manual".to_string()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,101 @@
+use std::{fmt, fs, io, os::unix::fs::MetadataExt, path::{Path, PathBuf}};
+use rusqlite::{Connection, OpenFlags};
+
+/// Databases root acts on: whoever can write them decides what root restores, shreds or skips.
+/// Only root writes here, everyone else reads.
+pub const STATE_DIR: &str = "/var/lib/sentinel";
+
+#[derive(Debug)]
+pub enum StateError {
+    Io(PathBuf, io::Error),
+    /// Writable by someone else than root, so nothing in it can be trusted
+    Insecure(PathBuf),
+    /// Not created yet, and only root creates it
+    Missing(PathBuf),
+    Db(PathBuf, rusqlite::Error),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::Io(path, e) => write!(f, "Couldn't open {:?}: {e}", path),
+            StateError::Insecure(path) => {
+                write!(f, "{:?} is writable by someone else than root, refusing to use it", path)
+            }
+            StateError::Missing(path) => write!(f, "{:?} doesn't exist yet, run sentinel as root once to create it", path),
+            StateError::Db(path, e) => write!(f, "Couldn't open the database {:?}: {e}", path),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+/// Opens the database `name` in `STATE_DIR`, see `open_root_only`
+pub fn open_db(name: &str) -> Result<Connection, StateError> {
+    open_root_only(&Path::new(STATE_DIR).join(name))
+}
+
+/// Opens a database only root writes. Root creates it and its directory, everyone else gets it read-only.
+/// Refused if the file or its directory is writable by anyone but root.
+pub fn open_root_only(path: &Path) -> Result<Connection, StateError> {
+    let root = unsafe { libc::geteuid() == 0 };
+    let dir = path.parent().unwrap_or(Path::new("/"));
+    if root {
+        fs::create_dir_all(dir).map_err(|e| StateError::Io(dir.to_path_buf(), e))?;
+    }
+    check_root_only(dir)?;
+
+    match fs::symlink_metadata(path) {
+        Ok(_) => check_root_only(path)?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound && !root => return Err(StateError::Missing(path.to_path_buf())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(StateError::Io(path.to_path_buf(), e)),
+    }
+
+    let flags = match root {
+        true => OpenFlags::default(),
+        false => OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX | OpenFlags::SQLITE_OPEN_URI,
+    };
+    Connection::open_with_flags(path, flags).map_err(|e| StateError::Db(path.to_path_buf(), e))
+}
+
+/// Owned by root, not a symlink, and not group or world writable
+fn check_root_only(path: &Path) -> Result<(), StateError> {
+    let metadata = fs::symlink_metadata(path)
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => StateError::Missing(path.to_path_buf()),
+            _ => StateError::Io(path.to_path_buf(), e),
+        })?;
+    if metadata.file_type().is_symlink() || metadata.uid() != 0 || metadata.mode() & 0o022 != 0 {
+        return Err(StateError::Insecure(path.to_path_buf()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+    use super::*;
+
+    #[test]
+    fn refuses_databases_others_can_write() {
+        let dir = std::env::temp_dir().join(format!("sentinel_state_{}", std::process::id()));
+        let db = dir.join("state.db");
+        if unsafe { libc::geteuid() } != 0 {
+            assert!(matches!(open_root_only(&db), Err(StateError::Missing(_))));
+            return;
+        }
+
+        open_root_only(&db).unwrap().execute("CREATE TABLE t (x)", []).unwrap();
+        fs::set_permissions(&db, fs::Permissions::from_mode(0o666)).unwrap();
+        assert!(matches!(open_root_only(&db), Err(StateError::Insecure(_))));
+
+        fs::set_permissions(&db, fs::Permissions::from_mode(0o644)).unwrap();
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o777)).unwrap();
+        assert!(matches!(open_root_only(&db), Err(StateError::Insecure(_))));
+
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(open_root_only(&db).is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,367 @@
+use std::fmt;
+use goblin::pe::PE;
+
+pub const ELF_FEATURES: usize = 10;
+pub const PE_FEATURES: usize = 9;
+
+const BLOCK_SIZE: usize = 1024;
+const MIN_STRING_LEN: usize = 4;
+
+#[derive(Debug)]
+pub enum FeatureError {
+    /// The models were never trained on empty files and most stats are undefined for them
+    Empty,
+    Parse(goblin::error::Error),
+    UnsupportedFormat,
+}
+
+impl fmt::Display for FeatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FeatureError::Empty => write!(f, "File is empty"),
+            FeatureError::Parse(e) => write!(f, "Couldn't parse binary: {e}"),
+            FeatureError::UnsupportedFormat => write!(f, "File is neither an ELF, a PE nor a Mach-O"),
+        }
+    }
+}
+
+impl std::error::Error for FeatureError {}
+
+impl From<goblin::error::Error> for FeatureError {
+    fn from(e: goblin::error::Error) -> Self {
+        FeatureError::Parse(e)
+    }
+}
+
+/// The feature columns the models were trained on, in the same order as the
+/// old `extract_features_from_file_elf` / `extract_features_from_file_pe`.
+///
+/// ELF: size, histogram mean, std, max, min, entropy, string count, average string length,
+/// block entropy mean and max.
+///
+/// PE: size, has imports, has signatures, has sections, histogram mean, std, max, entropy, string count.
+///
+/// Mach-O: the ELF byte statistics, there was never a C extractor for it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FeatureVector {
+    Elf([f32; ELF_FEATURES]),
+    Pe([f32; PE_FEATURES]),
+    MachO([f32; ELF_FEATURES]),
+}
+
+impl FeatureVector {
+    /// The ELF features are purely byte statistics, so `data` doesn't have to parse as an ELF
+    pub fn elf_from_bytes(data: &[u8]) -> Result<Self, FeatureError> {
+        Ok(FeatureVector::Elf(byte_features(data)?))
+    }
+
+    /// Universal binaries included, their statistics are the ones of every architecture together
+    pub fn macho_from_bytes(data: &[u8]) -> Result<Self, FeatureError> {
+        Ok(FeatureVector::MachO(byte_features(data)?))
+    }
+
+    pub fn pe_from_bytes(data: &[u8]) -> Result<Self, FeatureError> {
+        let pe = PE::parse(data)?;
+        Self::pe_from_parsed(data, &pe)
+    }
+
+    fn pe_from_parsed(data: &[u8], pe: &PE) -> Result<Self, FeatureError> {
+        let stats = ByteStats::new(data)?;
+        Ok(FeatureVector::Pe([
+            stats.len,
+            flag(!pe.imports.is_empty()),
+            flag(!pe.certificates.is_empty()),
+            flag(!pe.sections.is_empty()),
+            mean(&stats.histogram),
+            std(&stats.histogram),
+            max(&stats.histogram),
+            stats.entropy,
+            stats.n_strings,
+        ]))
+    }
+
+    /// Shannon entropy of the whole file, in bits per byte
+    pub fn entropy(&self) -> f32 {
+        match self {
+            FeatureVector::Elf(features) | FeatureVector::MachO(features) => features[5],
+            FeatureVector::Pe(features) => features[7],
+        }
+    }
+
+    pub fn as_slice(&self) -> &[f32] {
+        match self {
+            FeatureVector::Elf(features) | FeatureVector::MachO(features) => features,
+            FeatureVector::Pe(features) => features,
+        }
+    }
+}
+
+fn byte_features(data: &[u8]) -> Result<[f32; ELF_FEATURES], FeatureError> {
+    Ok(ByteStats::new(data)?.elf_features())
+}
+
+fn flag(value: bool) -> f32 {
+    if value { 1.0 } else { 0.0 }
+}
+
+// Everything below mirrors c_code/*/predict.c and c_code/helper.c, including the f32 arithmetic,
+// so the models see the same inputs they were validated against.
+struct ByteStats {
+    len: f32,
+    histogram: [f32; 256],
+    entropy: f32,
+    n_strings: f32,
+    avg_string_len: f32,
+    block_entropies: Vec<f32>,
+}
+
+impl ByteStats {
+    fn new(data: &[u8]) -> Result<Self, FeatureError> {
+        let mut stream = ByteStream::default();
+        stream.update(data);
+        stream.finish()
+    }
+
+    fn elf_features(&self) -> [f32; ELF_FEATURES] {
+        [
+            self.len,
+            mean(&self.histogram),
+            std(&self.histogram),
+            max(&self.histogram),
+            min(&self.histogram),
+            self.entropy,
+            self.n_strings,
+            self.avg_string_len,
+            mean(&self.block_entropies),
+            max(&self.block_entropies),
+        ]
+    }
+}
+
+/// Everything `ByteStats` needs in one pass over the bytes, fed in chunks of any size
+struct ByteStream {
+    len: usize,
+    histogram: [f32; 256],
+    /// Length of the printable run the last chunk ended in, it may go on in the next one
+    run: usize,
+    n_strings: usize,
+    total_strlen: usize,
+    /// Every block entropy comes from the start of the file, see `finish`
+    first_block: Vec<u8>,
+}
+
+impl Default for ByteStream {
+    fn default() -> Self {
+        Self { len: 0, histogram: [0.0; 256], run: 0, n_strings: 0, total_strlen: 0, first_block: Vec::with_capacity(BLOCK_SIZE) }
+    }
+}
+
+impl ByteStream {
+    fn update(&mut self, chunk: &[u8]) {
+        self.len += chunk.len();
+        let missing = BLOCK_SIZE - self.first_block.len();
+        self.first_block.extend_from_slice(&chunk[..missing.min(chunk.len())]);
+        for byte in chunk {
+            // counted in f32 like the C code, so huge files saturate at the same counts
+            self.histogram[*byte as usize] += 1.0;
+            if (0x20..=0x7e).contains(byte) {
+                self.run += 1;
+            } else {
+                self.end_run();
+            }
+        }
+    }
+
+    /// Counts the printable run that just ended if it's at least `MIN_STRING_LEN` bytes long
+    fn end_run(&mut self) {
+        if self.run >= MIN_STRING_LEN {
+            self.n_strings += 1;
+            self.total_strlen += self.run;
+        }
+        self.run = 0;
+    }
+
+    fn finish(mut self) -> Result<ByteStats, FeatureError> {
+        if self.len == 0 {
+            return Err(FeatureError::Empty);
+        }
+        self.end_run();
+        let avg_string_len = self.total_strlen.checked_div(self.n_strings).unwrap_or(0);
+
+        // The C code computes the entropy of `data[..block_len]` for every block instead of
+        // `data[i..i + block_len]`, so every full block has the entropy of the first one.
+        // The models were validated against that, so it stays.
+        let full_block = entropy(&self.first_block);
+        let mut block_entropies = vec![full_block; self.len / BLOCK_SIZE];
+        if !self.len.is_multiple_of(BLOCK_SIZE) {
+            block_entropies.push(entropy(&self.first_block[..self.len % BLOCK_SIZE]));
+        }
+
+        Ok(ByteStats {
+            len: self.len as f32,
+            entropy: entropy_from_histogram(&self.histogram, self.len),
+            histogram: self.histogram,
+            n_strings: self.n_strings as f32,
+            avg_string_len: avg_string_len as f32,
+            block_entropies,
+        })
+    }
+}
+
+fn histogram(data: &[u8]) -> [f32; 256] {
+    let mut counts = [0f32; 256];
+    for byte in data {
+        counts[*byte as usize] += 1.0;
+    }
+    counts
+}
+
+fn entropy(data: &[u8]) -> f32 {
+    entropy_from_histogram(&histogram(data), data.len())
+}
+
+fn entropy_from_histogram(counts: &[f32; 256], len: usize) -> f32 {
+    let len = len as f32;
+    let mut entropy = 0f32;
+    for count in counts.iter().filter(|c| **c > 0.0) {
+        let prob = count / len;
+        entropy -= prob * prob.log2();
+    }
+    entropy
+}
+
+fn mean(values: &[f32]) -> f32 {
+    values.iter().fold(0f32, |sum, v| sum + v) / values.len() as f32
+}
+
+fn std(values: &[f32]) -> f32 {
+    let m = mean(values);
+    let sum_sq_diff = values.iter().fold(0f32, |sum, v| sum + (v - m) * (v - m));
+    (sum_sq_diff / values.len() as f32).sqrt()
+}
+
+fn max(values: &[f32]) -> f32 {
+    values.iter().copied().fold(values[0], |max, v| if v > max { v } else { max })
+}
+
+fn min(values: &[f32]) -> f32 {
+    values.iter().copied().fold(values[0], |min, v| if v < min { v } else { min })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // xorshift noise with a printable run sprinkled in every ~7 bytes
+    fn fixture(seed: u32, len: usize) -> Vec<u8> {
+        let mut state = seed;
+        let mut buf = Vec::with_capacity(len);
+        while buf.len() < len {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            if state.is_multiple_of(7) {
+                buf.extend_from_slice(b"/lib64/ld-linux-x86-64.so.2\0");
+            } else {
+                buf.push((state >> 24) as u8);
+            }
+        }
+        buf.truncate(len);
+        buf[len - 1] = 0;
+        buf
+    }
+
+    // The smallest PE32+ goblin and LIEF both take: one section holding an import of KERNEL32!ExitProcess,
+    // then `fixture` noise up to 4 KiB
+    fn pe_fixture(seed: u32) -> Vec<u8> {
+        const SECTION_RVA: u32 = 0x1000;
+        const HEADERS_SIZE: usize = 0x200;
+        let put = |buf: &mut Vec<u8>, at: usize, bytes: &[u8]| buf[at..at + bytes.len()].copy_from_slice(bytes);
+
+        let mut pe = vec![0; HEADERS_SIZE];
+        put(&mut pe, 0, b"MZ");
+        put(&mut pe, 0x3c, &0x40u32.to_le_bytes());
+        put(&mut pe, 0x40, b"PE\0\0");
+        // COFF header: x86-64, one section, a PE32+ optional header, executable
+        put(&mut pe, 0x44, &0x8664u16.to_le_bytes());
+        put(&mut pe, 0x46, &1u16.to_le_bytes());
+        put(&mut pe, 0x54, &0xf0u16.to_le_bytes());
+        put(&mut pe, 0x56, &0x22u16.to_le_bytes());
+        // optional header
+        put(&mut pe, 0x58, &0x20bu16.to_le_bytes());
+        put(&mut pe, 0x68, &SECTION_RVA.to_le_bytes()); // entry point
+        put(&mut pe, 0x6c, &SECTION_RVA.to_le_bytes()); // base of code
+        put(&mut pe, 0x70, &0x1_4000_0000u64.to_le_bytes());
+        put(&mut pe, 0x78, &0x1000u32.to_le_bytes()); // section alignment
+        put(&mut pe, 0x7c, &0x200u32.to_le_bytes()); // file alignment
+        put(&mut pe, 0x88, &6u16.to_le_bytes()); // subsystem version
+        put(&mut pe, 0x90, &0x2000u32.to_le_bytes()); // size of image
+        put(&mut pe, 0x94, &(HEADERS_SIZE as u32).to_le_bytes());
+        put(&mut pe, 0x9c, &3u16.to_le_bytes()); // console subsystem
+        put(&mut pe, 0xc4, &16u32.to_le_bytes()); // data directories
+        put(&mut pe, 0xd0, &SECTION_RVA.to_le_bytes()); // import directory
+        put(&mut pe, 0xd4, &40u32.to_le_bytes());
+        // the section
+        put(&mut pe, 0x148, b".text\0\0\0");
+        put(&mut pe, 0x150, &0x1000u32.to_le_bytes());
+        put(&mut pe, 0x154, &SECTION_RVA.to_le_bytes());
+        put(&mut pe, 0x158, &0x1000u32.to_le_bytes());
+        put(&mut pe, 0x15c, &(HEADERS_SIZE as u32).to_le_bytes());
+        put(&mut pe, 0x16c, &0xe000_0020u32.to_le_bytes());
+
+        let mut section = fixture(seed, 0x1000);
+        // import descriptor, a null one ends the list
+        section[..40].fill(0);
+        put(&mut section, 0, &(SECTION_RVA + 0x28).to_le_bytes());
+        put(&mut section, 12, &(SECTION_RVA + 0x58).to_le_bytes());
+        put(&mut section, 16, &(SECTION_RVA + 0x38).to_le_bytes());
+        // lookup and address tables, then the hint/name entry and the DLL name
+        for table in [0x28, 0x38] {
+            put(&mut section, table, &u64::from(SECTION_RVA + 0x48).to_le_bytes());
+            put(&mut section, table + 8, &0u64.to_le_bytes());
+        }
+        put(&mut section, 0x48, b"\0\0ExitProcess\0\0\0");
+        put(&mut section, 0x58, b"KERNEL32.dll\0\0\0\0");
+        pe.extend(section);
+        pe
+    }
+
+    // golden values produced by extract_features_from_file_elf on the same bytes, printed with %.9g
+    #[test]
+    #[allow(clippy::excessive_precision)]
+    fn elf_features_match_c_implementation() {
+        let corpus: [(Vec<u8>, [f32; ELF_FEATURES]); 6] = [
+            (fixture(1, 37), [37.0, 0.14453125, 0.506413341, 3.0, 0.0, 4.42514086, 1.0, 28.0, 4.42514086, 4.42514086]),
+            (fixture(2, 1024), [1024.0, 4.0, 12.8974323, 86.0, 0.0, 5.3284936, 31.0, 25.0, 5.3284936, 5.3284936]),
+            (fixture(3, 4113), [4113.0, 16.0664062, 56.4610901, 375.0, 0.0, 5.05632257, 129.0, 26.0, 4.62955666, 4.8644166]),
+            (fixture(4, 70000), [70000.0, 273.4375, 934.754822, 6220.0, 23.0, 5.24713659, 2139.0, 26.0, 5.07632017, 5.16091013]),
+            (b"hello world\n".repeat(300), [3600.0, 14.0625, 82.6649399, 900.0, 0.0, 3.02205515, 300.0, 11.0, 3.02137041, 3.02205515]),
+            (vec![0; 2048], [2048.0, 8.0, 127.749756, 2048.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]),
+        ];
+
+        for (data, expected) in corpus {
+            assert_eq!(FeatureVector::elf_from_bytes(&data).unwrap(), FeatureVector::Elf(expected));
+        }
+    }
+
+    // golden values produced by extract_features_from_file_pe on the same bytes, printed with %.9g.
+    // LIEF's import, signature and section flags are the ones the fixture is built with
+    #[test]
+    #[allow(clippy::excessive_precision)]
+    fn pe_features_match_c_implementation() {
+        let corpus: [(Vec<u8>, [f32; PE_FEATURES]); 2] = [
+            (pe_fixture(7), [4608.0, 1.0, 0.0, 1.0, 18.0, 67.8707428, 672.0, 4.97174311, 124.0]),
+            (pe_fixture(11), [4608.0, 1.0, 0.0, 1.0, 18.0, 66.2863693, 664.0, 5.11101103, 125.0]),
+        ];
+
+        for (data, expected) in corpus {
+            assert_eq!(FeatureVector::pe_from_bytes(&data).unwrap(), FeatureVector::Pe(expected));
+        }
+    }
+
+    #[test]
+    fn rejects_empty_and_non_pe_input() {
+        assert!(matches!(FeatureVector::elf_from_bytes(&[]), Err(FeatureError::Empty)));
+        assert!(matches!(FeatureVector::pe_from_bytes(&fixture(5, 512)), Err(FeatureError::Parse(_))));
+    }
+}
@@ -1,6 +1,10 @@
 // use rusqlite::{Connection, Result};
 //
 pub mod args_parser;
+pub mod features;
+pub mod palette;
+pub mod rules;
+pub mod xgboost;
 //
 // fn init_db_passwd(conn: &Connection) -> Result<()> {
 //     conn.execute(
@@ -0,0 +1,105 @@
+use std::sync::OnceLock;
+use colored::{Color, ColoredString, Colorize};
+
+use crate::args_parser::file_scanner::Colorblindness;
+
+static PALETTE: OnceLock<Palette> = OnceLock::new();
+
+// Okabe-Ito colors, picked to stay apart for every kind of color vision deficiency
+const ORANGE: Color = Color::TrueColor { r: 230, g: 159, b: 0 };
+const SKY_BLUE: Color = Color::TrueColor { r: 86, g: 180, b: 233 };
+const BLUISH_GREEN: Color = Color::TrueColor { r: 0, g: 158, b: 115 };
+const YELLOW: Color = Color::TrueColor { r: 240, g: 228, b: 66 };
+const BLUE: Color = Color::TrueColor { r: 0, g: 114, b: 178 };
+const VERMILLION: Color = Color::TrueColor { r: 213, g: 94, b: 0 };
+const REDDISH_PURPLE: Color = Color::TrueColor { r: 204, g: 121, b: 167 };
+
+/// Colors by meaning rather than by hue, so every colored line follows `--colorblindness`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Palette {
+    /// Malware, errors
+    pub danger: Color,
+    /// Skipped files, things that need a look
+    pub warning: Color,
+    /// Clean results
+    pub safe: Color,
+    /// Scores and other details
+    pub info: Color,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            danger: Color::Red,
+            warning: Color::Yellow,
+            safe: Color::Green,
+            info: Color::Cyan,
+        }
+    }
+}
+
+impl Palette {
+    pub fn new(colorblindness: Option<Colorblindness>) -> Self {
+        match colorblindness {
+            None => Self::default(),
+            // red and green are the ones that collapse, lean on orange against blue instead
+            Some(Colorblindness::Protanopia) | Some(Colorblindness::Deuteranopia) => Self {
+                danger: ORANGE,
+                warning: YELLOW,
+                safe: BLUE,
+                info: SKY_BLUE,
+            },
+            // blue and yellow are the ones that collapse, reds stay distinct
+            Some(Colorblindness::Tritanopia) => Self {
+                danger: VERMILLION,
+                warning: REDDISH_PURPLE,
+                safe: BLUISH_GREEN,
+                info: Color::White,
+            },
+        }
+    }
+
+    /// Sets the palette used by `palette::current()` for the rest of the process.
+    /// Only the first call wins.
+    pub fn install(self) {
+        let _ = PALETTE.set(self);
+    }
+
+    pub fn danger(&self, text: &str) -> ColoredString {
+        text.color(self.danger).bold()
+    }
+
+    pub fn warning(&self, text: &str) -> ColoredString {
+        text.color(self.warning)
+    }
+
+    pub fn safe(&self, text: &str) -> ColoredString {
+        text.color(self.safe)
+    }
+
+    pub fn info(&self, text: &str) -> ColoredString {
+        text.color(self.info)
+    }
+}
+
+/// The installed palette, or the default one if nothing was installed yet
+pub fn current() -> Palette {
+    PALETTE.get().copied().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_each_colorblindness_to_its_palette() {
+        assert_eq!(Palette::new(None), Palette::default());
+        let red_green = Palette { danger: ORANGE, warning: YELLOW, safe: BLUE, info: SKY_BLUE };
+        assert_eq!(Palette::new(Some(Colorblindness::Protanopia)), red_green);
+        assert_eq!(Palette::new(Some(Colorblindness::Deuteranopia)), red_green);
+        assert_eq!(
+            Palette::new(Some(Colorblindness::Tritanopia)),
+            Palette { danger: VERMILLION, warning: REDDISH_PURPLE, safe: BLUISH_GREEN, info: Color::White },
+        );
+    }
+}
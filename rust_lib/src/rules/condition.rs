@@ -0,0 +1,174 @@
+/// A parsed `condition:`. Strings are referred to by their index in the rule,
+/// rules by their index in the rule set.
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Bool(bool),
+    Int(i64),
+    Filesize,
+    /// `$a`
+    Matched(usize),
+    /// `$a at 0`
+    MatchedAt(usize, Box<Expr>),
+    /// `$a in (0..1024)`
+    MatchedIn(usize, Box<Expr>, Box<Expr>),
+    /// `#a`
+    Count(usize),
+    /// `@a` or `@a[2]`, 1-based
+    Offset(usize, Box<Expr>),
+    /// `uint32(0)`, `int16be(@a + 2)`
+    Read(IntRead, Box<Expr>),
+    /// `2 of ($a, $b*)`, `any of them`
+    Of(Quantifier, Vec<usize>),
+    Rule(usize),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Neg(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quantifier {
+    All,
+    Any,
+    None,
+    AtLeast(i64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    BitAnd,
+    BitOr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IntRead {
+    /// In bytes: 1, 2 or 4
+    pub size: usize,
+    pub signed: bool,
+    pub big_endian: bool,
+}
+
+impl IntRead {
+    /// `uint8` to `int32be`
+    pub fn from_name(name: &str) -> Option<Self> {
+        let (name, big_endian) = match name.strip_suffix("be") {
+            Some(name) => (name, true),
+            None => (name, false),
+        };
+        let (bits, signed) = match name.strip_prefix("u") {
+            Some(name) => (name.strip_prefix("int")?, false),
+            None => (name.strip_prefix("int")?, true),
+        };
+        let size = match bits {
+            "8" => 1,
+            "16" => 2,
+            "32" => 4,
+            _ => return None,
+        };
+        Some(Self { size, signed, big_endian })
+    }
+
+    fn read(self, data: &[u8], offset: i64) -> Option<i64> {
+        let start = usize::try_from(offset).ok()?;
+        let bytes = data.get(start..start.checked_add(self.size)?)?;
+        let mut word = [0; 4];
+        let value = if self.big_endian {
+            word[4 - self.size..].copy_from_slice(bytes);
+            u32::from_be_bytes(word)
+        } else {
+            word[..self.size].copy_from_slice(bytes);
+            u32::from_le_bytes(word)
+        };
+        if !self.signed {
+            return Some(value as i64);
+        }
+        // sign extend from the top bit of the read size
+        let shift = 32 - 8 * self.size as u32;
+        Some(((value << shift) as i32 >> shift) as i64)
+    }
+}
+
+/// What a condition is evaluated against
+pub struct Context<'a> {
+    pub data: &'a [u8],
+    /// Offsets of every string of the rule, in definition order
+    pub matches: &'a [Vec<usize>],
+    /// Results of the rules evaluated so far
+    pub rules: &'a [bool],
+}
+
+impl Expr {
+    pub fn is_true(&self, ctx: &Context) -> bool {
+        self.eval(ctx).is_some_and(|value| value != 0)
+    }
+
+    /// Booleans are 0 or 1, `None` is YARA's undefined: reading past the end of the file,
+    /// an offset of a string that didn't match or a division by zero. Undefined is false.
+    fn eval(&self, ctx: &Context) -> Option<i64> {
+        let boolean = |b: bool| Some(b as i64);
+        match self {
+            Expr::Bool(b) => boolean(*b),
+            Expr::Int(n) => Some(*n),
+            Expr::Filesize => Some(ctx.data.len() as i64),
+            Expr::Matched(i) => boolean(!ctx.matches[*i].is_empty()),
+            Expr::MatchedAt(i, offset) => {
+                let offset = offset.eval(ctx)?;
+                boolean(ctx.matches[*i].iter().any(|m| *m as i64 == offset))
+            }
+            Expr::MatchedIn(i, start, end) => {
+                let (start, end) = (start.eval(ctx)?, end.eval(ctx)?);
+                boolean(ctx.matches[*i].iter().any(|m| (start..=end).contains(&(*m as i64))))
+            }
+            Expr::Count(i) => Some(ctx.matches[*i].len() as i64),
+            Expr::Offset(i, nth) => {
+                let nth = usize::try_from(nth.eval(ctx)?).ok()?;
+                ctx.matches[*i].get(nth.checked_sub(1)?).map(|m| *m as i64)
+            }
+            Expr::Read(read, offset) => read.read(ctx.data, offset.eval(ctx)?),
+            Expr::Of(quantifier, strings) => {
+                let matched = strings.iter().filter(|i| !ctx.matches[**i].is_empty()).count() as i64;
+                boolean(match quantifier {
+                    Quantifier::All => matched == strings.len() as i64,
+                    Quantifier::Any => matched > 0,
+                    Quantifier::None => matched == 0,
+                    Quantifier::AtLeast(n) => matched >= *n,
+                })
+            }
+            Expr::Rule(i) => boolean(ctx.rules[*i]),
+            Expr::Not(e) => boolean(e.eval(ctx)? == 0),
+            Expr::And(a, b) => boolean(a.is_true(ctx) && b.is_true(ctx)),
+            Expr::Or(a, b) => boolean(a.is_true(ctx) || b.is_true(ctx)),
+            Expr::Neg(e) => e.eval(ctx)?.checked_neg(),
+            Expr::Binary(op, a, b) => {
+                let (a, b) = (a.eval(ctx)?, b.eval(ctx)?);
+                match op {
+                    BinOp::Eq => boolean(a == b),
+                    BinOp::Ne => boolean(a != b),
+                    BinOp::Lt => boolean(a < b),
+                    BinOp::Le => boolean(a <= b),
+                    BinOp::Gt => boolean(a > b),
+                    BinOp::Ge => boolean(a >= b),
+                    BinOp::Add => a.checked_add(b),
+                    BinOp::Sub => a.checked_sub(b),
+                    BinOp::Mul => a.checked_mul(b),
+                    BinOp::Div => a.checked_div(b),
+                    BinOp::Mod => a.checked_rem(b),
+                    BinOp::BitAnd => Some(a & b),
+                    BinOp::BitOr => Some(a | b),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,254 @@
+// Splits rule sources into tokens. Hex strings and regular expressions only
+// make sense right after the `=` of a string definition, so that's the only
+// place where `{` and `/` start one.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Ident(String),
+    /// `$name`, or just `$` for anonymous strings
+    StringId(String),
+    /// `#name`
+    Count(String),
+    /// `@name`
+    Offset(String),
+    Int(i64),
+    Text(Vec<u8>),
+    /// What's between the braces of `{ 4D 5A ?? }`
+    Hex(String),
+    /// Pattern and the flags after the closing `/`
+    Regex(String, String),
+    LBrace,
+    RBrace,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Colon,
+    Comma,
+    Assign,
+    DotDot,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Plus,
+    Minus,
+    Star,
+    Backslash,
+    Percent,
+    Amp,
+    Pipe,
+}
+
+/// Tokens along with the line they start on, or the line of the error and what's wrong
+pub fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, (usize, String)> {
+    let mut lexer = Lexer { chars: source.chars().collect(), pos: 0, line: 1 };
+    let mut tokens: Vec<(Token, usize)> = vec![];
+    while let Some(c) = lexer.skip_blanks()? {
+        let line = lexer.line;
+        let after_assign = matches!(tokens.last(), Some((Token::Assign, _)));
+        let token = match c {
+            '{' if after_assign => Token::Hex(lexer.until('}', "hex string")?),
+            '/' if after_assign => lexer.regex()?,
+            '"' => lexer.text()?,
+            '$' | '#' | '@' => {
+                lexer.pos += 1;
+                let name = lexer.word();
+                match c {
+                    '$' => Token::StringId(name),
+                    _ if name.is_empty() => return Err((line, format!("Expected a string name after {c:?}"))),
+                    '#' => Token::Count(name),
+                    _ => Token::Offset(name),
+                }
+            }
+            c if c.is_ascii_digit() => lexer.number()?,
+            c if c.is_ascii_alphabetic() || c == '_' => Token::Ident(lexer.word()),
+            _ => lexer.punctuation()?,
+        };
+        tokens.push((token, line));
+    }
+    Ok(tokens)
+}
+
+struct Lexer {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+}
+
+impl Lexer {
+    fn peek(&self, ahead: usize) -> Option<char> {
+        self.chars.get(self.pos + ahead).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek(0)?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    /// Skips whitespace and comments, returns the next character without consuming it
+    fn skip_blanks(&mut self) -> Result<Option<char>, (usize, String)> {
+        loop {
+            match (self.peek(0), self.peek(1)) {
+                (Some(c), _) if c.is_whitespace() => {
+                    self.bump();
+                }
+                (Some('/'), Some('/')) => {
+                    while self.peek(0).is_some_and(|c| c != '\n') {
+                        self.bump();
+                    }
+                }
+                (Some('/'), Some('*')) => {
+                    let line = self.line;
+                    self.pos += 2;
+                    while !(self.peek(0) == Some('*') && self.peek(1) == Some('/')) {
+                        if self.bump().is_none() {
+                            return Err((line, "Unterminated comment".to_string()));
+                        }
+                    }
+                    self.pos += 2;
+                }
+                (c, _) => return Ok(c),
+            }
+        }
+    }
+
+    fn word(&mut self) -> String {
+        let start = self.pos;
+        while self.peek(0).is_some_and(|c| c.is_ascii_alphanumeric() || c == '_') {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    /// Everything up to `end`, which is consumed along with the opening character
+    fn until(&mut self, end: char, what: &str) -> Result<String, (usize, String)> {
+        let line = self.line;
+        self.pos += 1;
+        let mut content = String::new();
+        loop {
+            match self.bump() {
+                Some(c) if c == end => return Ok(content),
+                Some(c) => content.push(c),
+                None => return Err((line, format!("Unterminated {what}"))),
+            }
+        }
+    }
+
+    fn regex(&mut self) -> Result<Token, (usize, String)> {
+        let line = self.line;
+        self.pos += 1;
+        let mut pattern = String::new();
+        loop {
+            match self.bump() {
+                Some('/') => break,
+                Some('\\') if self.peek(0) == Some('/') => {
+                    self.pos += 1;
+                    pattern.push('/');
+                }
+                Some('\\') => {
+                    pattern.push('\\');
+                    pattern.extend(self.bump());
+                }
+                Some('\n') | None => return Err((line, "Unterminated regular expression".to_string())),
+                Some(c) => pattern.push(c),
+            }
+        }
+        let flags = self.word();
+        Ok(Token::Regex(pattern, flags))
+    }
+
+    fn text(&mut self) -> Result<Token, (usize, String)> {
+        let line = self.line;
+        self.pos += 1;
+        let mut bytes = vec![];
+        loop {
+            match self.bump() {
+                Some('"') => return Ok(Token::Text(bytes)),
+                Some('\\') => match self.bump() {
+                    Some('n') => bytes.push(b'\n'),
+                    Some('r') => bytes.push(b'\r'),
+                    Some('t') => bytes.push(b'\t'),
+                    Some('"') => bytes.push(b'"'),
+                    Some('\\') => bytes.push(b'\\'),
+                    Some('x') => {
+                        let digits = [self.bump(), self.bump()].into_iter().flatten().collect::<String>();
+                        let byte = u8::from_str_radix(&digits, 16)
+                            .map_err(|_| (line, format!("Invalid escape \\x{digits}")))?;
+                        bytes.push(byte);
+                    }
+                    c => return Err((line, format!("Invalid escape \\{}", c.map(String::from).unwrap_or_default()))),
+                },
+                Some('\n') | None => return Err((line, "Unterminated string".to_string())),
+                Some(c) => bytes.extend(c.encode_utf8(&mut [0; 4]).as_bytes()),
+            }
+        }
+    }
+
+    /// Decimal or `0x` numbers, optionally followed by `KB` or `MB`
+    fn number(&mut self) -> Result<Token, (usize, String)> {
+        let (radix, digits) = if self.peek(0) == Some('0') && matches!(self.peek(1), Some('x' | 'X')) {
+            self.pos += 2;
+            (16, self.word())
+        } else {
+            (10, self.word())
+        };
+        let (digits, multiplier) = match (radix, digits.strip_suffix("KB"), digits.strip_suffix("MB")) {
+            (10, Some(digits), _) => (digits.to_string(), 1 << 10),
+            (10, _, Some(digits)) => (digits.to_string(), 1 << 20),
+            _ => (digits, 1),
+        };
+        i64::from_str_radix(&digits, radix)
+            .ok()
+            .and_then(|n| n.checked_mul(multiplier))
+            .map(Token::Int)
+            .ok_or_else(|| (self.line, format!("Invalid number {digits:?}")))
+    }
+
+    fn punctuation(&mut self) -> Result<Token, (usize, String)> {
+        let c = self.bump().expect("Called on a character");
+        let two = |lexer: &mut Self, next: char, long: Token, short: Token| {
+            if lexer.peek(0) == Some(next) {
+                lexer.pos += 1;
+                long
+            } else {
+                short
+            }
+        };
+        Ok(match c {
+            '{' => Token::LBrace,
+            '}' => Token::RBrace,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            '[' => Token::LBracket,
+            ']' => Token::RBracket,
+            ':' => Token::Colon,
+            ',' => Token::Comma,
+            '+' => Token::Plus,
+            '-' => Token::Minus,
+            '*' => Token::Star,
+            '\\' => Token::Backslash,
+            '%' => Token::Percent,
+            '&' => Token::Amp,
+            '|' => Token::Pipe,
+            '=' => two(self, '=', Token::Eq, Token::Assign),
+            '<' => two(self, '=', Token::Le, Token::Lt),
+            '>' => two(self, '=', Token::Ge, Token::Gt),
+            '!' if self.peek(0) == Some('=') => {
+                self.pos += 1;
+                Token::Ne
+            }
+            '.' if self.peek(0) == Some('.') => {
+                self.pos += 1;
+                Token::DotDot
+            }
+            c => return Err((self.line, format!("Unexpected character {c:?}"))),
+        })
+    }
+}
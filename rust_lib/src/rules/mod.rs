@@ -0,0 +1,221 @@
+// YARA-compatible rules, enough of the syntax for the public rule feeds that stick to strings and simple conditions:
+// text strings (nocase, wide, ascii, fullword), hex strings with wildcards, jumps and alternatives,
+// regular expressions, and conditions over them, `filesize`, `uint32(0)` and friends and earlier rules.
+// Modules, `for` loops, `global` rules and the xor/base64 modifiers are rejected with a syntax error.
+mod condition;
+mod lexer;
+mod parser;
+mod pattern;
+
+use std::{fmt, fs, io, path::{Path, PathBuf}};
+use sha2::{Digest, Sha256};
+
+use condition::{Context, Expr};
+use pattern::Pattern;
+pub use pattern::{find_hex, match_hex, HexToken, MAX_HEX_STEPS};
+pub(crate) use pattern::{parse_hex_sequence, parse_jump};
+
+/// Extensions picked up when `--rules` points to a directory
+const RULE_EXTENSIONS: [&str; 2] = ["yar", "yara"];
+
+#[derive(Debug)]
+pub enum RuleError {
+    Io(PathBuf, io::Error),
+    Syntax {
+        /// None for rules that didn't come from a file
+        path: Option<PathBuf>,
+        line: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuleError::Io(path, e) => write!(f, "Couldn't read rules from {:?}: {e}", path),
+            RuleError::Syntax { path: Some(path), line, message } => write!(f, "{}:{line}: {message}", path.display()),
+            RuleError::Syntax { path: None, line, message } => write!(f, "Line {line}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for RuleError {}
+
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub name: String,
+    pub tags: Vec<String>,
+    /// Private rules only exist for other rules to use, they're never reported
+    pub private: bool,
+    pub meta: Vec<(String, String)>,
+    strings: Vec<StringDef>,
+    condition: Expr,
+}
+
+#[derive(Debug, Clone)]
+struct StringDef {
+    /// Without the `$`, empty for anonymous strings
+    id: String,
+    pattern: Pattern,
+}
+
+/// Rules from any number of sources, matched in the order they were added
+#[derive(Debug, Clone, Default)]
+pub struct RuleSet {
+    rules: Vec<Rule>,
+    /// SHA-256 chained over every source, changes whenever a rule does
+    version: String,
+}
+
+impl RuleSet {
+    /// Reads rule files, and the `.yar`/`.yara` files of directories, in name order.
+    /// Later files may refer to the rules of earlier ones.
+    pub fn load<P: AsRef<Path>>(paths: &[P]) -> Result<Self, RuleError> {
+        let mut rule_set = Self::default();
+        for path in paths {
+            let path = path.as_ref();
+            let io_error = |e| RuleError::Io(path.to_path_buf(), e);
+            let files = if path.is_dir() {
+                let mut files = fs::read_dir(path).map_err(io_error)?
+                    .map(|entry| entry.map(|entry| entry.path()))
+                    .collect::<io::Result<Vec<PathBuf>>>()
+                    .map_err(io_error)?;
+                files.retain(|file| file.extension().is_some_and(|ext| RULE_EXTENSIONS.iter().any(|rule_ext| ext == *rule_ext)));
+                files.sort();
+                files
+            } else {
+                vec![path.to_path_buf()]
+            };
+
+            for file in files {
+                let source = fs::read_to_string(&file).map_err(|e| RuleError::Io(file.clone(), e))?;
+                rule_set.add_source(&source, Some(&file))?;
+            }
+        }
+        Ok(rule_set)
+    }
+
+    pub fn parse(source: &str) -> Result<Self, RuleError> {
+        let mut rule_set = Self::default();
+        rule_set.add_source(source, None)?;
+        Ok(rule_set)
+    }
+
+    /// Nothing is added if any rule of `source` is invalid
+    pub fn add_source(&mut self, source: &str, path: Option<&Path>) -> Result<(), RuleError> {
+        let syntax_error = |(line, message)| RuleError::Syntax { path: path.map(Path::to_path_buf), line, message };
+        let tokens = lexer::tokenize(source).map_err(syntax_error)?;
+        let rules = parser::Parser::new(tokens, &self.rules).parse().map_err(syntax_error)?;
+
+        self.rules.extend(rules);
+        let mut hasher = Sha256::new();
+        hasher.update(&self.version);
+        hasher.update(source);
+        self.version = hex::encode(hasher.finalize());
+        Ok(())
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// Names of the public rules matching `data`
+    pub fn scan(&self, data: &[u8]) -> Vec<String> {
+        let mut results = Vec::with_capacity(self.rules.len());
+        let mut matched = vec![];
+        for rule in &self.rules {
+            let matches = rule.strings.iter().map(|s| s.pattern.find_all(data)).collect::<Vec<Vec<usize>>>();
+            let is_match = rule.condition.is_true(&Context { data, matches: &matches, rules: &results });
+            if is_match && !rule.private {
+                matched.push(rule.name.clone());
+            }
+            results.push(is_match);
+        }
+        matched
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: &str = r#"
+        private rule is_pe {
+            condition:
+                uint16(0) == 0x5A4D and uint32(uint32(0x3C)) == 0x00004550
+        }
+
+        rule tiny_pe : packer {
+            meta:
+                author = "sentinel"
+                score = 80
+            strings:
+                $upx = "UPX!"
+                $section = { 55 50 58 ( 30 | 31 ) }
+            condition:
+                is_pe and filesize < 1KB and any of them
+        }
+
+        rule dropper /* comment */ {
+            strings:
+                $url = /https?:\/\/[a-z]+\.example/ nocase
+                $ua = "Mozilla" wide ascii
+                $cmd1 = "cmd.exe" fullword
+                $cmd2 = "powershell" nocase
+            condition:
+                $url and (2 of ($cmd*) or #ua >= 2) and @url[1] > 0x40
+        }
+    "#;
+
+    fn pe(body: &[u8]) -> Vec<u8> {
+        let mut data = vec![0; 0x80];
+        data[..2].copy_from_slice(b"MZ");
+        data[0x3C] = 0x40;
+        data[0x40..0x44].copy_from_slice(b"PE\0\0");
+        data.extend(body);
+        data
+    }
+
+    #[test]
+    fn matches_strings_and_conditions() {
+        let rules = RuleSet::parse(RULES).unwrap();
+        assert_eq!(rules.rules().len(), 3);
+        assert_eq!(rules.rules()[1].tags, ["packer"]);
+
+        assert_eq!(rules.scan(&pe(b"UPX0")), ["tiny_pe"]);
+        assert!(rules.scan(b"UPX0").is_empty());
+        assert!(rules.scan(&pe(&[b'x'; 1024])).is_empty());
+
+        let dropper = pe(b"HTTP://EVIL.example cmd.exe POWERSHELL");
+        assert_eq!(rules.scan(&dropper), ["dropper"]);
+        let wide_ua = pe(b"http://evil.example Mozilla M\0o\0z\0i\0l\0l\0a\0 xcmd.exe powershell");
+        assert_eq!(rules.scan(&wide_ua), ["dropper"]);
+        assert!(rules.scan(&pe(b"http://evil.example xcmd.exe powershell")).is_empty());
+        assert!(rules.scan(b"http://evil.example cmd.exe powershell").is_empty());
+    }
+
+    #[test]
+    fn reports_where_rules_are_wrong() {
+        let error = |source: &str| RuleSet::parse(source).unwrap_err().to_string();
+        assert_eq!(error("rule a {\n  condition:\n    $b\n}"), "Line 3: Undefined string $b");
+        assert_eq!(error("import \"pe\"\nrule a { condition: true }"), "Line 1: `import` isn't supported");
+        assert_eq!(error("rule a { condition: true }\nrule a { condition: a }"), "Line 2: Duplicate rule a");
+        assert_eq!(error("rule a { strings: $a = { 4D [2] } condition: $a }"), "Line 1: Hex strings can't start or end with a jump");
+
+        let mut rules = RuleSet::parse("rule a { condition: filesize > 2 }").unwrap();
+        let version = rules.version().to_string();
+        assert!(rules.add_source("rule b { condition: c }", None).is_err());
+        rules.add_source("rule b { condition: not a }", None).unwrap();
+        assert_ne!(rules.version(), version);
+        assert_eq!(rules.scan(b"abc"), ["a"]);
+        assert_eq!(rules.scan(b"ab"), ["b"]);
+    }
+}
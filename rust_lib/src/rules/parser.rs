@@ -0,0 +1,369 @@
+// Recursive descent parser for the YARA subset `RuleSet` understands.
+// Condition operators bind like YARA's, loosest first:
+// `or`, `and`, `not`, comparisons, `|`, `&`, `+ -`, `* \ %`, unary `-`.
+use super::condition::{BinOp, Expr, IntRead, Quantifier};
+use super::lexer::Token;
+use super::pattern::{parse_hex, Pattern};
+use super::{Rule, StringDef};
+
+type ParseResult<T> = Result<T, (usize, String)>;
+
+const KEYWORDS: [&str; 17] = [
+    "rule", "private", "global", "meta", "strings", "condition", "import", "include",
+    "and", "or", "not", "of", "them", "at", "in", "filesize", "for",
+];
+
+pub struct Parser<'a> {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    /// Rules parsed from earlier sources, conditions may refer to them
+    defined: &'a [Rule],
+    rules: Vec<Rule>,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(tokens: Vec<(Token, usize)>, defined: &'a [Rule]) -> Self {
+        Self { tokens, pos: 0, defined, rules: vec![] }
+    }
+
+    pub fn parse(mut self) -> ParseResult<Vec<Rule>> {
+        while self.peek().is_some() {
+            let rule = self.rule()?;
+            self.rules.push(rule);
+        }
+        Ok(self.rules)
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    /// Line of the last token consumed, errors are found right after reading the offending token
+    fn line(&self) -> usize {
+        self.tokens.get(self.pos.saturating_sub(1)).map_or(1, |(_, line)| *line)
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> ParseResult<T> {
+        Err((self.line(), message.into()))
+    }
+
+    fn unexpected<T>(&mut self, expected: &str) -> ParseResult<T> {
+        let found = self.next()?;
+        self.error(format!("Expected {expected}, found {found:?}"))
+    }
+
+    fn next(&mut self) -> ParseResult<Token> {
+        match self.tokens.get(self.pos) {
+            Some((token, _)) => {
+                self.pos += 1;
+                Ok(token.clone())
+            }
+            None => self.error("Unexpected end of file"),
+        }
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        let found = self.peek() == Some(token);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        self.eat(&Token::Ident(keyword.to_string()))
+    }
+
+    fn expect(&mut self, token: Token) -> ParseResult<()> {
+        match self.next()? {
+            found if found == token => Ok(()),
+            found => self.error(format!("Expected {token:?}, found {found:?}")),
+        }
+    }
+
+    fn identifier(&mut self, what: &str) -> ParseResult<String> {
+        match self.next()? {
+            Token::Ident(name) if !KEYWORDS.contains(&name.as_str()) => Ok(name),
+            found => self.error(format!("Expected {what}, found {found:?}")),
+        }
+    }
+
+    fn rule(&mut self) -> ParseResult<Rule> {
+        let private = self.eat_keyword("private");
+        for unsupported in ["global", "import", "include"] {
+            if self.eat_keyword(unsupported) {
+                return self.error(format!("`{unsupported}` isn't supported"));
+            }
+        }
+        if !self.eat_keyword("rule") {
+            return self.unexpected("a rule");
+        }
+
+        let name = self.identifier("a rule name")?;
+        if self.rule_index(&name).is_some() {
+            return self.error(format!("Duplicate rule {name}"));
+        }
+        let mut tags = vec![];
+        if self.eat(&Token::Colon) {
+            while let Some(Token::Ident(_)) = self.peek() {
+                tags.push(self.identifier("a tag")?);
+            }
+        }
+        self.expect(Token::LBrace)?;
+
+        let mut meta = vec![];
+        if self.eat_keyword("meta") {
+            self.expect(Token::Colon)?;
+            while let Some(Token::Ident(key)) = self.peek().cloned()
+                && !["strings", "condition"].contains(&key.as_str())
+            {
+                self.pos += 1;
+                self.expect(Token::Assign)?;
+                let value = match self.next()? {
+                    Token::Text(bytes) => String::from_utf8_lossy(&bytes).to_string(),
+                    Token::Int(n) => n.to_string(),
+                    Token::Minus => match self.next()? {
+                        Token::Int(n) => (-n).to_string(),
+                        found => return self.error(format!("Invalid meta value {found:?}")),
+                    },
+                    Token::Ident(b) if b == "true" || b == "false" => b,
+                    found => return self.error(format!("Invalid meta value {found:?}")),
+                };
+                meta.push((key, value));
+            }
+        }
+
+        let mut strings = vec![];
+        if self.eat_keyword("strings") {
+            self.expect(Token::Colon)?;
+            while let Some(Token::StringId(id)) = self.peek().cloned() {
+                self.pos += 1;
+                if !id.is_empty() && strings.iter().any(|s: &StringDef| s.id == id) {
+                    return self.error(format!("Duplicate string ${id}"));
+                }
+                self.expect(Token::Assign)?;
+                let pattern = self.string_pattern()?;
+                strings.push(StringDef { id, pattern });
+            }
+        }
+
+        if !self.eat_keyword("condition") {
+            return self.unexpected("condition");
+        }
+        self.expect(Token::Colon)?;
+        let condition = self.or_expr(&strings)?;
+        self.expect(Token::RBrace)?;
+
+        Ok(Rule { name, tags, private, meta, strings, condition })
+    }
+
+    fn string_pattern(&mut self) -> ParseResult<Pattern> {
+        let definition = self.next()?;
+        let line = self.line();
+        let (mut nocase, mut ascii, mut wide, mut fullword) = (false, false, false, false);
+        while let Some(Token::Ident(modifier)) = self.peek().cloned() {
+            match modifier.as_str() {
+                "nocase" => nocase = true,
+                "ascii" => ascii = true,
+                "wide" => wide = true,
+                "fullword" => fullword = true,
+                // private strings only matter for YARA's own match output
+                "private" => {}
+                "xor" | "base64" | "base64wide" => return self.error(format!("The {modifier} modifier isn't supported")),
+                _ => break,
+            }
+            self.pos += 1;
+        }
+
+        match definition {
+            Token::Text(bytes) => Ok(Pattern::Text { bytes, nocase, ascii: ascii || !wide, wide, fullword }),
+            Token::Hex(_) if nocase || wide || fullword => Err((line, "Hex strings don't take modifiers".to_string())),
+            Token::Regex(..) if wide || fullword => Err((line, "Regular expressions only take the nocase modifier".to_string())),
+            Token::Hex(hex) => parse_hex(&hex).map(Pattern::Hex).map_err(|e| (line, e)),
+            Token::Regex(regex, flags) => Pattern::regex(&regex, &flags, nocase).map_err(|e| (line, e)),
+            found => Err((line, format!("Expected a string, found {found:?}"))),
+        }
+    }
+
+    fn rule_index(&self, name: &str) -> Option<usize> {
+        self.defined.iter().chain(&self.rules).position(|rule| rule.name == name)
+    }
+
+    fn string_index(&self, strings: &[StringDef], id: &str) -> ParseResult<usize> {
+        if id.is_empty() {
+            return self.error("Anonymous strings can only be used with `them`");
+        }
+        match strings.iter().position(|s| s.id == id) {
+            Some(i) => Ok(i),
+            None => self.error(format!("Undefined string ${id}")),
+        }
+    }
+
+    fn or_expr(&mut self, strings: &[StringDef]) -> ParseResult<Expr> {
+        let mut expr = self.and_expr(strings)?;
+        while self.eat_keyword("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and_expr(strings)?));
+        }
+        Ok(expr)
+    }
+
+    fn and_expr(&mut self, strings: &[StringDef]) -> ParseResult<Expr> {
+        let mut expr = self.not_expr(strings)?;
+        while self.eat_keyword("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.not_expr(strings)?));
+        }
+        Ok(expr)
+    }
+
+    fn not_expr(&mut self, strings: &[StringDef]) -> ParseResult<Expr> {
+        if self.eat_keyword("not") {
+            return Ok(Expr::Not(Box::new(self.not_expr(strings)?)));
+        }
+        self.comparison(strings)
+    }
+
+    fn comparison(&mut self, strings: &[StringDef]) -> ParseResult<Expr> {
+        let expr = self.binary(strings, 0)?;
+        let op = match self.peek() {
+            Some(Token::Eq) => BinOp::Eq,
+            Some(Token::Ne) => BinOp::Ne,
+            Some(Token::Lt) => BinOp::Lt,
+            Some(Token::Le) => BinOp::Le,
+            Some(Token::Gt) => BinOp::Gt,
+            Some(Token::Ge) => BinOp::Ge,
+            _ => return Ok(expr),
+        };
+        self.pos += 1;
+        Ok(Expr::Binary(op, Box::new(expr), Box::new(self.binary(strings, 0)?)))
+    }
+
+    /// Arithmetic and bitwise operators, `level` indexes `LEVELS` from the loosest
+    fn binary(&mut self, strings: &[StringDef], level: usize) -> ParseResult<Expr> {
+        const LEVELS: [&[(Token, BinOp)]; 4] = [
+            &[(Token::Pipe, BinOp::BitOr)],
+            &[(Token::Amp, BinOp::BitAnd)],
+            &[(Token::Plus, BinOp::Add), (Token::Minus, BinOp::Sub)],
+            &[(Token::Star, BinOp::Mul), (Token::Backslash, BinOp::Div), (Token::Percent, BinOp::Mod)],
+        ];
+        let Some(operators) = LEVELS.get(level) else { return self.unary(strings) };
+
+        let mut expr = self.binary(strings, level + 1)?;
+        while let Some((_, op)) = operators.iter().find(|(token, _)| self.peek() == Some(token)) {
+            self.pos += 1;
+            expr = Expr::Binary(*op, Box::new(expr), Box::new(self.binary(strings, level + 1)?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self, strings: &[StringDef]) -> ParseResult<Expr> {
+        if self.eat(&Token::Minus) {
+            return Ok(Expr::Neg(Box::new(self.unary(strings)?)));
+        }
+        self.primary(strings)
+    }
+
+    fn primary(&mut self, strings: &[StringDef]) -> ParseResult<Expr> {
+        match self.next()? {
+            Token::LParen => {
+                let expr = self.or_expr(strings)?;
+                self.expect(Token::RParen)?;
+                Ok(expr)
+            }
+            Token::Int(n) if self.eat_keyword("of") => Ok(Expr::Of(Quantifier::AtLeast(n), self.string_set(strings)?)),
+            Token::Int(n) => Ok(Expr::Int(n)),
+            Token::StringId(id) => {
+                let i = self.string_index(strings, &id)?;
+                if self.eat_keyword("at") {
+                    return Ok(Expr::MatchedAt(i, Box::new(self.binary(strings, 0)?)));
+                }
+                if self.eat_keyword("in") {
+                    self.expect(Token::LParen)?;
+                    let start = self.binary(strings, 0)?;
+                    self.expect(Token::DotDot)?;
+                    let end = self.binary(strings, 0)?;
+                    self.expect(Token::RParen)?;
+                    return Ok(Expr::MatchedIn(i, Box::new(start), Box::new(end)));
+                }
+                Ok(Expr::Matched(i))
+            }
+            Token::Count(id) => Ok(Expr::Count(self.string_index(strings, &id)?)),
+            Token::Offset(id) => {
+                let i = self.string_index(strings, &id)?;
+                let nth = if self.eat(&Token::LBracket) {
+                    let nth = self.or_expr(strings)?;
+                    self.expect(Token::RBracket)?;
+                    nth
+                } else {
+                    Expr::Int(1)
+                };
+                Ok(Expr::Offset(i, Box::new(nth)))
+            }
+            Token::Ident(word) => match word.as_str() {
+                "true" => Ok(Expr::Bool(true)),
+                "false" => Ok(Expr::Bool(false)),
+                "filesize" => Ok(Expr::Filesize),
+                "all" | "any" | "none" if self.eat_keyword("of") => {
+                    let quantifier = match word.as_str() {
+                        "all" => Quantifier::All,
+                        "any" => Quantifier::Any,
+                        _ => Quantifier::None,
+                    };
+                    Ok(Expr::Of(quantifier, self.string_set(strings)?))
+                }
+                "for" => self.error("`for` loops aren't supported"),
+                name if self.peek() == Some(&Token::LParen) => {
+                    let Some(read) = IntRead::from_name(name) else {
+                        return self.error(format!("Unknown function {name}"));
+                    };
+                    self.pos += 1;
+                    let offset = self.or_expr(strings)?;
+                    self.expect(Token::RParen)?;
+                    Ok(Expr::Read(read, Box::new(offset)))
+                }
+                name => match self.rule_index(name) {
+                    Some(i) => Ok(Expr::Rule(i)),
+                    None => self.error(format!("Undefined identifier {name}")),
+                },
+            },
+            found => self.error(format!("Unexpected {found:?} in condition")),
+        }
+    }
+
+    /// `them` or `($a, $b*)`, as string indexes
+    fn string_set(&mut self, strings: &[StringDef]) -> ParseResult<Vec<usize>> {
+        if self.eat_keyword("them") {
+            if strings.is_empty() {
+                return self.error("`them` without any strings");
+            }
+            return Ok((0..strings.len()).collect());
+        }
+
+        self.expect(Token::LParen)?;
+        let mut set = vec![];
+        loop {
+            let id = match self.next()? {
+                Token::StringId(id) => id,
+                found => return self.error(format!("Expected a string, found {found:?}")),
+            };
+            if self.eat(&Token::Star) {
+                let matching = strings.iter().enumerate()
+                    .filter(|(_, s)| !s.id.is_empty() && s.id.starts_with(&id))
+                    .map(|(i, _)| i)
+                    .collect::<Vec<usize>>();
+                if matching.is_empty() {
+                    return self.error(format!("No string matches ${id}*"));
+                }
+                set.extend(matching);
+            } else {
+                set.push(self.string_index(strings, &id)?);
+            }
+            if !self.eat(&Token::Comma) {
+                break;
+            }
+        }
+        self.expect(Token::RParen)?;
+        set.sort_unstable();
+        set.dedup();
+        Ok(set)
+    }
+}
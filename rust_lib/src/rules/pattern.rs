@@ -0,0 +1,263 @@
+use std::iter::Peekable;
+use memchr::memmem;
+use regex::bytes::{Regex, RegexBuilder};
+
+/// Matches past this many are ignored, `#a` saturates there
+pub const MAX_MATCHES: usize = 1000;
+
+/// Longest a `[n-]` or `[-]` jump reaches, past that hex strings would scan whole files from every offset
+const MAX_JUMP: usize = 64 * 1024;
+
+/// Tokens a hex string may try against one file before giving up, nested jumps backtrack exponentially otherwise
+pub const MAX_HEX_STEPS: usize = 1 << 22;
+
+/// The bytes a string definition looks for
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Text {
+        bytes: Vec<u8>,
+        nocase: bool,
+        ascii: bool,
+        /// UTF-16LE, every byte followed by a zero
+        wide: bool,
+        /// Not preceded nor followed by an alphanumeric character
+        fullword: bool,
+    },
+    Hex(Vec<HexToken>),
+    Regex(Regex),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HexToken {
+    /// A byte where only the bits set in `mask` have to be equal, `??` is a zero mask
+    Byte { value: u8, mask: u8 },
+    /// `[n-m]` arbitrary bytes, unbounded for `[n-]`
+    Jump(usize, Option<usize>),
+    /// `( 01 02 | 03 )`
+    Alternatives(Vec<Vec<HexToken>>),
+}
+
+impl Pattern {
+    /// `pattern` as written between the slashes, `flags` are YARA's `i` and `s`
+    pub fn regex(pattern: &str, flags: &str, nocase: bool) -> Result<Self, String> {
+        if let Some(flag) = flags.chars().find(|flag| !matches!(flag, 'i' | 's')) {
+            return Err(format!("Unknown regular expression flag {flag:?}"));
+        }
+        RegexBuilder::new(pattern)
+            .unicode(false)
+            .case_insensitive(nocase || flags.contains('i'))
+            .dot_matches_new_line(flags.contains('s'))
+            .build()
+            .map(Pattern::Regex)
+            .map_err(|e| format!("Invalid regular expression: {e}"))
+    }
+
+    /// Start offsets of the first `MAX_MATCHES` matches
+    pub fn find_all(&self, data: &[u8]) -> Vec<usize> {
+        match self {
+            Pattern::Text { bytes, nocase, ascii, wide, fullword } => {
+                let mut offsets = vec![];
+                if *ascii {
+                    offsets.extend(find_text(data, bytes, *nocase, *fullword, 1));
+                }
+                if *wide {
+                    let wide_bytes = bytes.iter().flat_map(|b| [*b, 0]).collect::<Vec<u8>>();
+                    offsets.extend(find_text(data, &wide_bytes, *nocase, *fullword, 2));
+                }
+                if *ascii && *wide {
+                    offsets.sort_unstable();
+                    offsets.truncate(MAX_MATCHES);
+                }
+                offsets
+            }
+            Pattern::Hex(tokens) => find_hex(tokens, data).take(MAX_MATCHES).collect(),
+            Pattern::Regex(regex) => regex.find_iter(data).map(|m| m.start()).take(MAX_MATCHES).collect(),
+        }
+    }
+}
+
+/// `width` is 2 for wide strings, the characters around them are UTF-16 too
+fn find_text(data: &[u8], needle: &[u8], nocase: bool, fullword: bool, width: usize) -> Vec<usize> {
+    if needle.is_empty() {
+        return vec![];
+    }
+    let is_word = |offset: Option<usize>| offset.and_then(|offset| data.get(offset)).is_some_and(u8::is_ascii_alphanumeric);
+    let whole_word = |start: usize| !fullword || (!is_word(start.checked_sub(width)) && !is_word(Some(start + needle.len())));
+
+    let found: Box<dyn Iterator<Item = usize>> = if nocase {
+        let first = needle[0];
+        Box::new(
+            memchr::memchr2_iter(first.to_ascii_lowercase(), first.to_ascii_uppercase(), data)
+                .filter(|start| data.get(*start..*start + needle.len()).is_some_and(|window| window.eq_ignore_ascii_case(needle))),
+        )
+    } else {
+        Box::new(memmem::find_iter(data, needle))
+    };
+    found.filter(|start| whole_word(*start)).take(MAX_MATCHES).collect()
+}
+
+/// Start offsets of every match of a hex string, within `MAX_HEX_STEPS`
+pub fn find_hex<'a>(tokens: &'a [HexToken], data: &'a [u8]) -> impl Iterator<Item = usize> + 'a {
+    let starts: Box<dyn Iterator<Item = usize>> = match anchor(tokens) {
+        Some((offset, value)) => Box::new(memchr::memchr_iter(value, data).filter_map(move |at| at.checked_sub(offset))),
+        None => Box::new(0..data.len()),
+    };
+    let mut steps = MAX_HEX_STEPS;
+    starts.filter(move |start| match_hex(tokens, data, *start, &mut steps).is_some())
+}
+
+/// The first fully known byte before any jump or alternative and how far into the match it is,
+/// only offsets where it occurs can start a match
+fn anchor(tokens: &[HexToken]) -> Option<(usize, u8)> {
+    tokens.iter()
+        .map_while(|token| match token {
+            HexToken::Byte { value, mask } => Some((*value, *mask)),
+            _ => None,
+        })
+        .position(|(_, mask)| mask == 0xff)
+        .map(|offset| match tokens[offset] {
+            HexToken::Byte { value, .. } => (offset, value),
+            _ => unreachable!(),
+        })
+}
+
+/// Where the match starting at `pos` ends, trying the shortest jumps first.
+/// Every token tried takes one of `steps`, none left is no match.
+pub fn match_hex(tokens: &[HexToken], data: &[u8], pos: usize, steps: &mut usize) -> Option<usize> {
+    let Some((token, rest)) = tokens.split_first() else { return Some(pos) };
+    *steps = steps.checked_sub(1)?;
+    match token {
+        HexToken::Byte { value, mask } => {
+            let byte = data.get(pos)?;
+            if byte & mask == value & mask {
+                match_hex(rest, data, pos + 1, steps)
+            } else {
+                None
+            }
+        }
+        HexToken::Jump(min, max) => {
+            let max = max.unwrap_or(min.saturating_add(MAX_JUMP)).min(data.len().saturating_sub(pos));
+            (*min..=max).find_map(|skip| match_hex(rest, data, pos + skip, steps))
+        }
+        HexToken::Alternatives(alternatives) => alternatives.iter()
+            .find_map(|alternative| match_hex(alternative, data, pos, steps).and_then(|end| match_hex(rest, data, end, steps))),
+    }
+}
+
+/// `4D 5A ?? ?0 [2-4] ( 01 | 02 03 )`, whitespace is optional between bytes
+pub fn parse_hex(source: &str) -> Result<Vec<HexToken>, String> {
+    let mut chars = source.chars().filter(|c| !c.is_whitespace()).peekable();
+    let tokens = parse_hex_sequence(&mut chars, false, &|_| None)?;
+    if tokens.iter().all(|token| matches!(token, HexToken::Jump(..))) {
+        return Err("Hex strings need at least one byte".to_string());
+    }
+    if matches!(tokens.first(), Some(HexToken::Jump(..))) || matches!(tokens.last(), Some(HexToken::Jump(..))) {
+        return Err("Hex strings can't start or end with a jump".to_string());
+    }
+    Ok(tokens)
+}
+
+/// Bytes, `??` wildcards, `[n-m]` jumps and `( .. | .. )` alternatives until the end, or the end of the alternative
+/// when `nested`. `extra` gets the first look at every token, for syntax only other dialects have, and returns
+/// `None` without consuming anything when it's none of its own.
+pub(crate) fn parse_hex_sequence<I, E>(chars: &mut Peekable<I>, nested: bool, extra: &E) -> Result<Vec<HexToken>, String>
+where
+    I: Iterator<Item = char>,
+    E: Fn(&mut Peekable<I>) -> Option<Result<HexToken, String>>,
+{
+    let mut tokens = vec![];
+    while let Some(&c) = chars.peek() {
+        if let Some(token) = extra(chars) {
+            tokens.push(token?);
+            continue;
+        }
+        match c {
+            '|' | ')' if nested => break,
+            '[' => {
+                chars.next();
+                let range = chars.by_ref().take_while(|c| *c != ']').collect::<String>();
+                let (min, max) = parse_jump(&range).ok_or_else(|| format!("Invalid jump [{range}]"))?;
+                tokens.push(HexToken::Jump(min, max));
+            }
+            '(' => {
+                chars.next();
+                let mut alternatives = vec![parse_hex_sequence(chars, true, extra)?];
+                loop {
+                    match chars.next() {
+                        Some('|') => alternatives.push(parse_hex_sequence(chars, true, extra)?),
+                        Some(')') => break,
+                        _ => return Err("Unterminated alternative".to_string()),
+                    }
+                }
+                if alternatives.iter().any(Vec::is_empty) {
+                    return Err("Empty alternative".to_string());
+                }
+                tokens.push(HexToken::Alternatives(alternatives));
+            }
+            _ => {
+                let (high, low) = (chars.next(), chars.next());
+                let nibble = |c: Option<char>| match c {
+                    Some('?') => Ok((0, 0)),
+                    Some(c) if c.is_ascii_hexdigit() => Ok((c.to_digit(16).unwrap() as u8, 0xf)),
+                    _ => Err(format!("Invalid hex byte {}{}", high.unwrap_or(' '), low.unwrap_or(' '))),
+                };
+                let ((high, high_mask), (low, low_mask)) = (nibble(high)?, nibble(low)?);
+                tokens.push(HexToken::Byte { value: high << 4 | low, mask: high_mask << 4 | low_mask });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// `n`, `n-m`, `n-`, `-m` or `-` between the brackets of a jump, `None` for no upper bound
+pub(crate) fn parse_jump(range: &str) -> Option<(usize, Option<usize>)> {
+    let bound = |s: &str| s.parse::<usize>().ok();
+    let (min, max) = match range.split_once('-') {
+        None => (bound(range)?, Some(bound(range)?)),
+        Some((min, max)) => (
+            if min.is_empty() { 0 } else { bound(min)? },
+            if max.is_empty() { None } else { Some(bound(max)?) },
+        ),
+    };
+    match max {
+        Some(max) if max < min => None,
+        _ => Some((min, max)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_strings_match_wildcards_jumps_and_alternatives() {
+        let tokens = parse_hex("4D 5A ?0 [1-2] ( 01 | 02 0? ) FF").unwrap();
+        let hex = Pattern::Hex(tokens);
+        assert_eq!(hex.find_all(b"xxMZ\x30\x00\x02\x05\xff"), vec![2]);
+        assert_eq!(hex.find_all(b"MZ\x30\x00\x00\x00\x01\xff"), Vec::<usize>::new());
+        assert_eq!(hex.find_all(b"MZ\x31\x00\x01\xffMZ\x10\x00\x00\x01\xff"), vec![6]);
+
+        assert!(parse_hex("[2] 4D").is_err());
+        assert!(parse_hex("4D 5").is_err());
+        assert!(parse_hex("4D [3-1] 5A").is_err());
+
+        let text = Pattern::Text { bytes: b"evil".to_vec(), nocase: true, ascii: true, wide: true, fullword: true };
+        assert_eq!(text.find_all(b"EVIL \0e\0v\0i\0l\0 \0devil"), vec![0, 6]);
+    }
+
+    #[test]
+    fn hex_strings_give_up_on_runaway_backtracking() {
+        let anchored = Pattern::Hex(parse_hex("?? ?5 4D 5A").unwrap());
+        assert_eq!(anchored.find_all(b"MZ\x00\x15MZ\x05MZ\x06MZ"), vec![2, 5]);
+
+        // every A starts a match that tries every jump length of every jump
+        let runaway = parse_hex("41 [0-] 41 [0-] 41 [0-] 42").unwrap();
+        let data = vec![b'A'; 200_000];
+        let started = std::time::Instant::now();
+        assert_eq!(find_hex(&runaway, &data).next(), None);
+        assert!(started.elapsed() < std::time::Duration::from_secs(10));
+
+        let mut steps = MAX_HEX_STEPS;
+        assert_eq!(match_hex(&[HexToken::Byte { value: 0x41, mask: 0xff }, HexToken::Jump(usize::MAX, None), HexToken::Byte { value: 0x42, mask: 0xff }], b"AB", 0, &mut steps), None);
+    }
+}
@@ -0,0 +1,384 @@
+mod registry;
+mod ubjson;
+
+pub use registry::ModelRegistry;
+
+use std::{fmt, fs, io, path::Path};
+use serde_json::Value;
+
+#[derive(Debug)]
+pub enum ModelError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    Ubjson(String),
+    Invalid(String),
+    Unsupported(String),
+}
+
+impl fmt::Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModelError::Io(e) => write!(f, "Couldn't read model: {e}"),
+            ModelError::Json(e) => write!(f, "Couldn't parse JSON model: {e}"),
+            ModelError::Ubjson(e) => write!(f, "Couldn't parse UBJSON model: {e}"),
+            ModelError::Invalid(e) => write!(f, "Invalid model: {e}"),
+            ModelError::Unsupported(e) => write!(f, "Unsupported model: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ModelError {}
+
+impl From<io::Error> for ModelError {
+    fn from(e: io::Error) -> Self {
+        ModelError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ModelError {
+    fn from(e: serde_json::Error) -> Self {
+        ModelError::Json(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Objective {
+    /// `binary:logistic`, `reg:logistic`
+    Logistic,
+    /// `binary:logitraw`, logistic base score but the margin is the prediction
+    LogitRaw,
+    /// `reg:squarederror` and friends
+    Identity,
+}
+
+impl Objective {
+    fn from_name(name: &str) -> Result<Self, ModelError> {
+        match name {
+            "binary:logistic" | "reg:logistic" => Ok(Self::Logistic),
+            "binary:logitraw" => Ok(Self::LogitRaw),
+            "reg:squarederror" | "reg:linear" | "reg:absoluteerror" => Ok(Self::Identity),
+            _ => Err(ModelError::Unsupported(format!("objective {name}"))),
+        }
+    }
+
+    // same as xgboost's ObjFunction::ProbToMargin
+    fn base_margin(self, base_score: f32) -> f32 {
+        match self {
+            Self::Logistic | Self::LogitRaw => -(1.0f32 / base_score - 1.0f32).ln(),
+            Self::Identity => base_score,
+        }
+    }
+
+    fn transform(self, margin: f32) -> f32 {
+        match self {
+            Self::Logistic => 1.0f32 / (1.0f32 + (-margin).exp()),
+            Self::LogitRaw | Self::Identity => margin,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Tree {
+    left_children: Vec<i32>,
+    right_children: Vec<i32>,
+    split_indices: Vec<usize>,
+    split_conditions: Vec<f32>,
+    default_left: Vec<bool>,
+}
+
+impl Tree {
+    fn from_value(tree: &Value, num_feature: usize) -> Result<Self, ModelError> {
+        if tree.get("split_type").and_then(Value::as_array).is_some_and(|t| t.iter().any(|t| t.as_u64() != Some(0))) {
+            return Err(ModelError::Unsupported("categorical splits".to_string()));
+        }
+        if tree.pointer("/tree_param/size_leaf_vector").and_then(as_number).is_some_and(|n| n > 1.0) {
+            return Err(ModelError::Unsupported("vector leaves".to_string()));
+        }
+
+        let left_children = numbers(tree, "left_children")?.into_iter().map(|n| n as i32).collect::<Vec<_>>();
+        let right_children = numbers(tree, "right_children")?.into_iter().map(|n| n as i32).collect::<Vec<_>>();
+        let split_indices = numbers(tree, "split_indices")?.into_iter().map(|n| n as usize).collect::<Vec<_>>();
+        let split_conditions = numbers(tree, "split_conditions")?.into_iter().map(|n| n as f32).collect::<Vec<_>>();
+        let default_left = numbers(tree, "default_left")?.into_iter().map(|n| n != 0.0).collect::<Vec<_>>();
+
+        let num_nodes = left_children.len();
+        if num_nodes == 0 {
+            return Err(ModelError::Invalid("tree without nodes".to_string()));
+        }
+        if [right_children.len(), split_indices.len(), split_conditions.len(), default_left.len()].iter().any(|len| *len != num_nodes) {
+            return Err(ModelError::Invalid("tree arrays have different lengths".to_string()));
+        }
+
+        // validate once here so prediction can't index out of bounds or loop forever
+        for node in 0..num_nodes {
+            let (left, right) = (left_children[node], right_children[node]);
+            if left == -1 {
+                continue;
+            }
+            let in_bounds = |child: i32| child > node as i32 && (child as usize) < num_nodes;
+            if !in_bounds(left) || !in_bounds(right) {
+                return Err(ModelError::Invalid(format!("node {node} has invalid children {left}, {right}")));
+            }
+            if split_indices[node] >= num_feature {
+                return Err(ModelError::Invalid(format!("node {node} splits on unknown feature {}", split_indices[node])));
+            }
+        }
+
+        Ok(Self {
+            left_children,
+            right_children,
+            split_indices,
+            split_conditions,
+            default_left,
+        })
+    }
+
+    fn leaf_value(&self, features: &[f32], missing: f32) -> f32 {
+        let mut node = 0;
+        while self.left_children[node] != -1 {
+            let value = features[self.split_indices[node]];
+            let go_left = if value.is_nan() || value == missing {
+                self.default_left[node]
+            } else {
+                value < self.split_conditions[node]
+            };
+            node = if go_left { self.left_children[node] } else { self.right_children[node] } as usize;
+        }
+        // leaves store their weight in split_conditions
+        self.split_conditions[node]
+    }
+}
+
+/// A gradient boosted tree model loaded from xgboost's JSON or UBJSON format,
+/// evaluated natively instead of going through libxgboost.
+#[derive(Debug, Clone)]
+pub struct Booster {
+    trees: Vec<Tree>,
+    base_margin: f32,
+    objective: Objective,
+    num_feature: usize,
+    missing: f32,
+}
+
+impl Booster {
+    /// Loads `.ubj` files as UBJSON and everything else as JSON
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, ModelError> {
+        let path = path.as_ref();
+        let buf = fs::read(path)?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("ubj") => Self::from_ubjson_slice(&buf),
+            _ => Self::from_json_slice(&buf),
+        }
+    }
+
+    pub fn from_json_slice(buf: &[u8]) -> Result<Self, ModelError> {
+        Self::from_value(&serde_json::from_slice(buf)?)
+    }
+
+    pub fn from_ubjson_slice(buf: &[u8]) -> Result<Self, ModelError> {
+        Self::from_value(&ubjson::parse(buf).map_err(ModelError::Ubjson)?)
+    }
+
+    fn from_value(model: &Value) -> Result<Self, ModelError> {
+        let learner = model.get("learner")
+            .ok_or_else(|| ModelError::Invalid("missing learner".to_string()))?;
+
+        let booster_name = learner.pointer("/gradient_booster/name").and_then(Value::as_str);
+        if booster_name != Some("gbtree") {
+            return Err(ModelError::Unsupported(format!("gradient booster {booster_name:?}")));
+        }
+
+        let objective = learner.pointer("/objective/name")
+            .and_then(Value::as_str)
+            .ok_or_else(|| ModelError::Invalid("missing objective".to_string()))
+            .and_then(Objective::from_name)?;
+
+        let params = learner.get("learner_model_param")
+            .ok_or_else(|| ModelError::Invalid("missing learner_model_param".to_string()))?;
+        let num_class = params.get("num_class").and_then(as_number).unwrap_or(0.0);
+        let num_target = params.get("num_target").and_then(as_number).unwrap_or(1.0);
+        if num_class > 1.0 || num_target > 1.0 {
+            return Err(ModelError::Unsupported("multi-class or multi-target models".to_string()));
+        }
+        let num_feature = params.get("num_feature")
+            .and_then(as_number)
+            .ok_or_else(|| ModelError::Invalid("missing num_feature".to_string()))? as usize;
+        let base_score = params.get("base_score")
+            .and_then(as_number)
+            .ok_or_else(|| ModelError::Invalid("missing base_score".to_string()))? as f32;
+
+        let trees = learner.pointer("/gradient_booster/model/trees")
+            .and_then(Value::as_array)
+            .ok_or_else(|| ModelError::Invalid("missing trees".to_string()))?
+            .iter()
+            .map(|tree| Tree::from_value(tree, num_feature))
+            .collect::<Result<Vec<Tree>, ModelError>>()?;
+
+        Ok(Self {
+            trees,
+            base_margin: objective.base_margin(base_score),
+            objective,
+            num_feature,
+            missing: f32::NAN,
+        })
+    }
+
+    /// Treat `missing` like NaN when walking the trees, the same as the `missing`
+    /// argument of `XGDMatrixCreateFromMat`
+    pub fn with_missing(mut self, missing: f32) -> Self {
+        self.missing = missing;
+        self
+    }
+
+    pub fn num_feature(&self) -> usize {
+        self.num_feature
+    }
+
+    pub fn num_trees(&self) -> usize {
+        self.trees.len()
+    }
+
+    /// Raw sum of the leaves plus the base margin, before the objective transform
+    pub fn predict_margin(&self, features: &[f32]) -> Result<f32, ModelError> {
+        if features.len() != self.num_feature {
+            return Err(ModelError::Invalid(format!("expected {} features, got {}", self.num_feature, features.len())));
+        }
+
+        let mut margin = self.base_margin;
+        for tree in &self.trees {
+            margin += tree.leaf_value(features, self.missing);
+        }
+        Ok(margin)
+    }
+
+    pub fn predict(&self, features: &[f32]) -> Result<f32, ModelError> {
+        Ok(self.objective.transform(self.predict_margin(features)?))
+    }
+}
+
+// xgboost stores most scalars as strings, sometimes wrapped in brackets ("[3.1750414E-1]")
+fn as_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::Bool(b) => Some(*b as u8 as f64),
+        Value::String(s) => s.trim_matches(|c| c == '[' || c == ']').parse().ok(),
+        Value::Array(items) if items.len() == 1 => as_number(&items[0]),
+        _ => None,
+    }
+}
+
+fn numbers(value: &Value, key: &str) -> Result<Vec<f64>, ModelError> {
+    value.get(key)
+        .and_then(Value::as_array)
+        .ok_or_else(|| ModelError::Invalid(format!("missing {key}")))?
+        .iter()
+        .map(|n| as_number(n).ok_or_else(|| ModelError::Invalid(format!("non-numeric value in {key}"))))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // this file is built both from rust_lib and from the root crate
+    fn model_path(name: &str) -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .ancestors()
+            .map(|dir| dir.join("model"))
+            .find(|dir| dir.is_dir())
+            .unwrap()
+            .join(name)
+    }
+
+    #[test]
+    fn ubj_and_json_models_agree() {
+        for format in ["elf", "exe"] {
+            let json = Booster::from_file(model_path(&format!("{format}/model.json"))).unwrap();
+            let ubj = Booster::from_file(model_path(&format!("{format}/model.ubj"))).unwrap();
+            assert_eq!(json.num_trees(), ubj.num_trees());
+            assert_eq!(json.num_feature(), ubj.num_feature());
+
+            let features = (0..json.num_feature()).map(|i| (i as f32 + 1.0) * 37.5).collect::<Vec<f32>>();
+            assert_eq!(json.predict(&features).unwrap(), ubj.predict(&features).unwrap());
+        }
+    }
+
+    #[test]
+    fn predicts_probabilities() {
+        let booster = Booster::from_file(model_path("elf/model.ubj")).unwrap().with_missing(0.0);
+        let pred = booster.predict(&[16384.0, 64.0, 120.0, 900.0, 0.0, 5.1, 150.0, 9.0, 5.0, 5.2]).unwrap();
+        assert!((0.0..=1.0).contains(&pred));
+        assert!(booster.predict(&[1.0; 3]).is_err());
+    }
+
+    #[test]
+    fn registry_routes_by_format() {
+        let registry = ModelRegistry::load(model_path("")).unwrap();
+        let elf = crate::features::FeatureVector::elf_from_bytes(&b"hello world\n".repeat(300)).unwrap();
+        let booster = Booster::from_file(model_path("elf/model.ubj")).unwrap().with_missing(0.0);
+        assert_eq!(registry.predict(&elf).unwrap(), Some(booster.predict(elf.as_slice()).unwrap()));
+        // no library models in the repository, libraries fall back to the executable ones
+        assert_eq!(registry.predict_library(&elf).unwrap(), registry.predict(&elf).unwrap());
+        assert_eq!(registry.library_name(&elf), registry.name(&elf));
+
+        // nor a Mach-O model, and the ELF one wasn't trained on Mach-O binaries
+        let macho = crate::features::FeatureVector::macho_from_bytes(&b"hello world\n".repeat(300)).unwrap();
+        assert_eq!((registry.predict(&macho).unwrap(), registry.name(&macho)), (None, None));
+        let registry = registry.with_macho_model(Some(Booster::from_file(model_path("elf/model.ubj")).unwrap())).unwrap();
+        assert_eq!(registry.predict(&macho).unwrap(), registry.predict(&elf).unwrap());
+
+        let swapped = ModelRegistry::from_boosters(
+            Booster::from_file(model_path("exe/model.ubj")).unwrap(),
+            Booster::from_file(model_path("elf/model.ubj")).unwrap(),
+        );
+        assert!(swapped.is_err());
+    }
+
+    #[test]
+    fn embedded_models_are_the_repository_ones() {
+        let (embedded, loaded) = (ModelRegistry::embedded().unwrap(), ModelRegistry::load(model_path("")).unwrap());
+        assert_eq!(embedded.version(), loaded.version());
+        let elf = crate::features::FeatureVector::elf_from_bytes(&b"hello world\n".repeat(300)).unwrap();
+        assert_eq!(embedded.predict(&elf).unwrap(), loaded.predict(&elf).unwrap());
+        assert_eq!(embedded.name(&elf), Some("built-in ELF model"));
+    }
+
+    // model/parity.json is written by model/parity.py, its "source" tells whether libxgboost scored it
+    #[test]
+    fn matches_the_parity_fixture() {
+        let parity: Value = serde_json::from_slice(&fs::read(model_path("parity.json")).unwrap()).unwrap();
+        for format in ["elf", "exe"] {
+            let booster = Booster::from_file(model_path(&format!("{format}/model.json"))).unwrap().with_missing(0.0);
+            let rows = parity[format].as_array().unwrap();
+            assert!(!rows.is_empty());
+            for row in rows {
+                let features = row["features"].as_array().unwrap().iter().map(|n| n.as_f64().unwrap() as f32).collect::<Vec<f32>>();
+                let expected = row["probability"].as_f64().unwrap() as f32;
+                let pred = booster.predict(&features).unwrap();
+                assert!((pred - expected).abs() <= 1e-6, "{format} {features:?}: {pred} != {expected}");
+            }
+        }
+    }
+
+    #[test]
+    fn missing_values_take_default_branch() {
+        let model = br#"{"learner": {
+            "gradient_booster": {"name": "gbtree", "model": {"trees": [{
+                "left_children": [1, -1, -1],
+                "right_children": [2, -1, -1],
+                "split_indices": [0, 0, 0],
+                "split_conditions": [0.5, -1.0, 1.0],
+                "default_left": [0, 0, 0],
+                "split_type": [0, 0, 0],
+                "tree_param": {"size_leaf_vector": "1"}
+            }]}},
+            "learner_model_param": {"base_score": "[5E-1]", "num_feature": "1"},
+            "objective": {"name": "binary:logitraw"}
+        }}"#;
+        let booster = Booster::from_json_slice(model).unwrap();
+        assert_eq!(booster.predict(&[0.25]).unwrap(), -1.0);
+        assert_eq!(booster.predict(&[f32::NAN]).unwrap(), 1.0);
+        assert_eq!(booster.clone().with_missing(0.0).predict(&[0.0]).unwrap(), 1.0);
+        assert_eq!(booster.predict(&[0.0]).unwrap(), -1.0);
+    }
+}
@@ -0,0 +1,159 @@
+use std::{fs, path::Path, sync::Arc};
+use sha2::{Digest, Sha256};
+use crate::features::{ELF_FEATURES, FeatureVector, PE_FEATURES};
+use super::{Booster, ModelError};
+
+// built into the binary so scans don't depend on where they're started from or on an installed model directory
+static ELF_MODEL: &[u8] = include_bytes!("../../../model/elf/model.ubj");
+static PE_MODEL: &[u8] = include_bytes!("../../../model/exe/model.ubj");
+
+/// The ELF and PE models, loaded once and shared by every file of a scan.
+/// Cloning only bumps the reference counts, so each worker thread can keep its own handle.
+#[derive(Debug, Clone)]
+pub struct ModelRegistry {
+    elf: Arc<Booster>,
+    pe: Arc<Booster>,
+    /// Trained on `.so` files and DLLs, the executable models score libraries when there's none
+    elf_library: Option<Arc<Booster>>,
+    pe_library: Option<Arc<Booster>>,
+    /// Mach-O features are the ELF ones, but the ELF model never saw a Mach-O binary: without a Mach-O model they're unscored
+    macho: Option<Arc<Booster>>,
+    elf_name: String,
+    pe_name: String,
+    elf_library_name: String,
+    pe_library_name: String,
+    macho_name: String,
+    /// SHA-256 of every model file, None for boosters that didn't come from files
+    version: Option<String>,
+}
+
+impl ModelRegistry {
+    /// Loads `elf/model.ubj` and `exe/model.ubj` from `model_dir`, and the library models
+    /// `so/model.ubj` and `dll/model.ubj` and the Mach-O model `macho/model.ubj` if they're there
+    pub fn load<P: AsRef<Path>>(model_dir: P) -> Result<Self, ModelError> {
+        let model_dir = model_dir.as_ref();
+        let (elf_path, pe_path) = (model_dir.join("elf/model.ubj"), model_dir.join("exe/model.ubj"));
+        let mut registry = Self::from_boosters(Booster::from_file(&elf_path)?, Booster::from_file(&pe_path)?)?;
+        registry.elf_name = elf_path.to_string_lossy().to_string();
+        registry.pe_name = pe_path.to_string_lossy().to_string();
+
+        let (elf_library_path, pe_library_path) = (model_dir.join("so/model.ubj"), model_dir.join("dll/model.ubj"));
+        let macho_path = model_dir.join("macho/model.ubj");
+        let optional = |path: &Path| path.is_file().then(|| Booster::from_file(path)).transpose();
+        registry = registry
+            .with_library_models(optional(&elf_library_path)?, optional(&pe_library_path)?)?
+            .with_macho_model(optional(&macho_path)?)?;
+        if registry.elf_library.is_some() {
+            registry.elf_library_name = elf_library_path.to_string_lossy().to_string();
+        }
+        if registry.pe_library.is_some() {
+            registry.pe_library_name = pe_library_path.to_string_lossy().to_string();
+        }
+        if registry.macho.is_some() {
+            registry.macho_name = macho_path.to_string_lossy().to_string();
+        }
+
+        // without the optional models, the version stays what it was before they existed
+        let mut hasher = Sha256::new();
+        let optional_paths = [&elf_library_path, &pe_library_path, &macho_path].into_iter().filter(|path| path.is_file());
+        for path in [&elf_path, &pe_path].into_iter().chain(optional_paths) {
+            hasher.update(fs::read(path).map_err(ModelError::Io)?);
+        }
+        registry.version = Some(hex::encode(hasher.finalize()));
+        Ok(registry)
+    }
+
+    /// The ELF and PE models the binary was built with, without library or Mach-O models.
+    /// Same version as loading them from the repository's `model` directory
+    pub fn embedded() -> Result<Self, ModelError> {
+        let mut registry = Self::from_boosters(Booster::from_ubjson_slice(ELF_MODEL)?, Booster::from_ubjson_slice(PE_MODEL)?)?;
+        registry.elf_name = "built-in ELF model".to_string();
+        registry.pe_name = "built-in PE model".to_string();
+        registry.version = Some(hex::encode(Sha256::new().chain_update(ELF_MODEL).chain_update(PE_MODEL).finalize()));
+        Ok(registry)
+    }
+
+    pub fn from_boosters(elf: Booster, pe: Booster) -> Result<Self, ModelError> {
+        for (name, booster, expected) in [("ELF", &elf, ELF_FEATURES), ("PE", &pe, PE_FEATURES)] {
+            if booster.num_feature() != expected {
+                return Err(ModelError::Invalid(format!("{name} model expects {} features, not {expected}", booster.num_feature())));
+            }
+        }
+
+        // the C predictors built their DMatrix with missing = 0, keep treating zeros as missing
+        Ok(Self {
+            elf: Arc::new(elf.with_missing(0.0)),
+            pe: Arc::new(pe.with_missing(0.0)),
+            elf_library: None,
+            pe_library: None,
+            macho: None,
+            elf_name: "ELF model".to_string(),
+            pe_name: "PE model".to_string(),
+            elf_library_name: "ELF library model".to_string(),
+            pe_library_name: "PE library model".to_string(),
+            macho_name: "Mach-O model".to_string(),
+            version: None,
+        })
+    }
+
+    /// Library models take the same features as the executable ones
+    pub fn with_library_models(mut self, elf: Option<Booster>, pe: Option<Booster>) -> Result<Self, ModelError> {
+        for (name, booster, expected) in [("ELF library", &elf, ELF_FEATURES), ("PE library", &pe, PE_FEATURES)] {
+            if let Some(booster) = booster
+                && booster.num_feature() != expected
+            {
+                return Err(ModelError::Invalid(format!("{name} model expects {} features, not {expected}", booster.num_feature())));
+            }
+        }
+        self.elf_library = elf.map(|booster| Arc::new(booster.with_missing(0.0)));
+        self.pe_library = pe.map(|booster| Arc::new(booster.with_missing(0.0)));
+        Ok(self)
+    }
+
+    /// Mach-O models take the ELF features
+    pub fn with_macho_model(mut self, macho: Option<Booster>) -> Result<Self, ModelError> {
+        if let Some(booster) = &macho
+            && booster.num_feature() != ELF_FEATURES
+        {
+            return Err(ModelError::Invalid(format!("Mach-O model expects {} features, not {ELF_FEATURES}", booster.num_feature())));
+        }
+        self.macho = macho.map(|booster| Arc::new(booster.with_missing(0.0)));
+        Ok(self)
+    }
+
+    /// The model file that scores `features`, for reports and quarantine reasons. None when no model does
+    pub fn name(&self, features: &FeatureVector) -> Option<&str> {
+        self.model(features, false).map(|(_, name)| name)
+    }
+
+    /// The model file that scores `features` of a library
+    pub fn library_name(&self, features: &FeatureVector) -> Option<&str> {
+        self.model(features, true).map(|(_, name)| name)
+    }
+
+    /// Changes whenever either model file does, so cached verdicts of older models can be told apart
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    /// None for Mach-O binaries without a Mach-O model
+    pub fn predict(&self, features: &FeatureVector) -> Result<Option<f32>, ModelError> {
+        // the feature counts were checked in from_boosters and with_library_models, this only fails if they drift apart
+        self.model(features, false).map(|(booster, _)| booster.predict(features.as_slice())).transpose()
+    }
+
+    /// Scored by the library model of the format, or by the executable one without it
+    pub fn predict_library(&self, features: &FeatureVector) -> Result<Option<f32>, ModelError> {
+        self.model(features, true).map(|(booster, _)| booster.predict(features.as_slice())).transpose()
+    }
+
+    fn model(&self, features: &FeatureVector, library: bool) -> Option<(&Booster, &str)> {
+        match (features, library) {
+            (FeatureVector::Elf(_), true) if let Some(booster) = &self.elf_library => Some((booster, &self.elf_library_name)),
+            (FeatureVector::Pe(_), true) if let Some(booster) = &self.pe_library => Some((booster, &self.pe_library_name)),
+            (FeatureVector::MachO(_), _) => self.macho.as_deref().map(|booster| (booster, self.macho_name.as_str())),
+            (FeatureVector::Elf(_), _) => Some((&self.elf, &self.elf_name)),
+            (FeatureVector::Pe(_), _) => Some((&self.pe, &self.pe_name)),
+        }
+    }
+}
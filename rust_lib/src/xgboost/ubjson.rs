@@ -0,0 +1,176 @@
+// Minimal UBJSON reader, just enough to load the models XGBoost writes with `save_model("*.ubj")`.
+// Everything is decoded into a serde_json::Value so the JSON and UBJSON loaders share the same code.
+use serde_json::{Map, Number, Value};
+
+pub fn parse(buf: &[u8]) -> Result<Value, String> {
+    let mut reader = Reader { buf, pos: 0 };
+    let marker = reader.next_marker()?;
+    reader.value(marker)
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8], String> {
+        let end = self.pos.checked_add(n)
+            .filter(|end| *end <= self.buf.len())
+            .ok_or_else(|| format!("Unexpected end of UBJSON data at offset {}", self.pos))?;
+        let bytes = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.buf.get(self.pos).copied()
+    }
+
+    // skips the no-op marker, which is allowed anywhere a value can appear
+    fn next_marker(&mut self) -> Result<u8, String> {
+        loop {
+            let marker = self.byte()?;
+            if marker != b'N' {
+                return Ok(marker);
+            }
+        }
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let mut out = [0u8; N];
+        out.copy_from_slice(self.take(N)?);
+        Ok(out)
+    }
+
+    fn int(&mut self, marker: u8) -> Result<i64, String> {
+        Ok(match marker {
+            b'i' => i8::from_be_bytes(self.array()?) as i64,
+            b'U' => u8::from_be_bytes(self.array()?) as i64,
+            b'I' => i16::from_be_bytes(self.array()?) as i64,
+            b'l' => i32::from_be_bytes(self.array()?) as i64,
+            b'L' => i64::from_be_bytes(self.array()?),
+            _ => return Err(format!("Expected an integer marker, got {:?}", marker as char)),
+        })
+    }
+
+    fn length(&mut self) -> Result<usize, String> {
+        let marker = self.next_marker()?;
+        let len = self.int(marker)?;
+        usize::try_from(len).map_err(|_| format!("Invalid UBJSON length {len}"))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.length()?;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|e| format!("Invalid UTF-8 in UBJSON string: {e}"))
+    }
+
+    fn float(value: f64) -> Result<Value, String> {
+        Number::from_f64(value)
+            .map(Value::Number)
+            .ok_or_else(|| format!("Non-finite float {value} in UBJSON data"))
+    }
+
+    fn value(&mut self, marker: u8) -> Result<Value, String> {
+        match marker {
+            b'Z' => Ok(Value::Null),
+            b'T' => Ok(Value::Bool(true)),
+            b'F' => Ok(Value::Bool(false)),
+            b'i' | b'U' | b'I' | b'l' | b'L' => Ok(Value::from(self.int(marker)?)),
+            b'd' => Self::float(f32::from_be_bytes(self.array()?) as f64),
+            b'D' => Self::float(f64::from_be_bytes(self.array()?)),
+            b'C' => Ok(Value::String((self.byte()? as char).to_string())),
+            b'S' => Ok(Value::String(self.string()?)),
+            b'H' => {
+                let digits = self.string()?;
+                digits.parse::<f64>()
+                    .map_err(|e| format!("Invalid high-precision number {digits:?}: {e}"))
+                    .and_then(Self::float)
+            }
+            b'[' => self.list(),
+            b'{' => self.object(),
+            _ => Err(format!("Unknown UBJSON marker {:?} at offset {}", marker as char, self.pos - 1)),
+        }
+    }
+
+    // reads the optional `$type` / `#count` header of a container
+    fn container_header(&mut self) -> Result<(Option<u8>, Option<usize>), String> {
+        let mut elem_type = None;
+        if self.peek() == Some(b'$') {
+            self.pos += 1;
+            elem_type = Some(self.byte()?);
+        }
+
+        let mut count = None;
+        if self.peek() == Some(b'#') {
+            self.pos += 1;
+            count = Some(self.length()?);
+        } else if elem_type.is_some() {
+            return Err("UBJSON typed container without a count".to_string());
+        }
+
+        Ok((elem_type, count))
+    }
+
+    fn list(&mut self) -> Result<Value, String> {
+        let (elem_type, count) = self.container_header()?;
+        let mut items = vec![];
+
+        match count {
+            Some(count) => {
+                // don't trust the count for the allocation, a truncated file would still fail in `take`
+                items.reserve(count.min(self.buf.len()));
+                for _ in 0..count {
+                    let marker = match elem_type {
+                        Some(marker) => marker,
+                        None => self.next_marker()?,
+                    };
+                    items.push(self.value(marker)?);
+                }
+            }
+            None => loop {
+                let marker = self.next_marker()?;
+                if marker == b']' {
+                    break;
+                }
+                items.push(self.value(marker)?);
+            },
+        }
+
+        Ok(Value::Array(items))
+    }
+
+    fn object(&mut self) -> Result<Value, String> {
+        let (elem_type, count) = self.container_header()?;
+        let mut map = Map::new();
+
+        match count {
+            Some(count) => {
+                for _ in 0..count {
+                    let key = self.string()?;
+                    let marker = match elem_type {
+                        Some(marker) => marker,
+                        None => self.next_marker()?,
+                    };
+                    map.insert(key, self.value(marker)?);
+                }
+            }
+            None => loop {
+                if self.peek() == Some(b'}') {
+                    self.pos += 1;
+                    break;
+                }
+                let key = self.string()?;
+                let marker = self.next_marker()?;
+                map.insert(key, self.value(marker)?);
+            },
+        }
+
+        Ok(Value::Object(map))
+    }
+}
@@ -1,17 +1,22 @@
-use std::env::home_dir;
-use std::path::PathBuf;
 use std::time::Duration;
-use std::{io, panic, process};
-use chrono::Local;
+use std::{io, panic, process, sync::{Arc, Mutex}};
 use clap::Parser;
 use colored::Colorize;
+use rust_lib::args_parser::exclusions::{ExclusionCommands, ExclusionList};
 use rust_lib::args_parser::process_behaviors_analyzer::ProcessBehaviorsAnalyzer;
-use rust_lib::args_parser::quarantine::{QuarantinedFile, Quarantinizer, ViewMode};
+use rust_lib::args_parser::reputation::{HashReputation, ReputationCommands};
+use rust_lib::args_parser::signatures::{BodySignatures, SignatureCommands, SignatureStore};
+use rust_lib::args_parser::quarantine::{self, Finding, ListFilter, ListedFile, QuarantineCommands, QuarantineError, QuarantinedFile, Quarantinizer, Verification, ViewMode};
+use rust_lib::args_parser::state::{self, StateError};
 use rust_lib::args_parser::unauthorized_changes_scanner::UnauthorizedChangesScanner;
 use rust_lib::args_parser::{file_scanner::FileScanner, Args};
-use rust_lib::args_parser::Commands::{ScanDir, CheckUnauthorizedChanges, AnalyzeProcessBehaviors, Quarantine};
+use rust_lib::palette::{self, Palette};
+use rust_lib::args_parser::Commands::{ScanDir, CheckUnauthorizedChanges, AnalyzeProcessBehaviors, Quarantine, Exclusions, Reputation, Signatures};
 use rusqlite::{Connection, Result};
 
+/// `scan-dir` found malware. Errors panic and exit with 1
+const EXIT_DETECTIONS: i32 = 2;
+
 fn init_db_passwd(conn: &Connection) -> Result<()> {
     conn.execute(
     "CREATE TABLE IF NOT EXISTS passwd_checks (
@@ -36,33 +41,294 @@ fn init_db_quarantine(conn: &Connection) -> Result<()> {
             )",
         []
     )?;
+    // the original file's metadata, NULL for files quarantined before it was recorded
+    add_missing_columns(conn, "quarantined_files", &[
+        "mode INTEGER",
+        "uid INTEGER",
+        "gid INTEGER",
+        "size INTEGER",
+        "accessed INTEGER",
+        "modified INTEGER",
+        "xattrs TEXT",
+        "sha256 TEXT",
+        "quarantined_by INTEGER",
+        "score REAL",
+    ])?;
+    // problems found by `quarantine verify`, one row per vault file and finding,
+    // from the check that first found it to the last one that still did
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS quarantine_verifications (
+                id INTEGER PRIMARY KEY,
+                checked_date TEXT NOT NULL,
+                quarantined_file_id INTEGER,
+                quarantine_path TEXT NOT NULL,
+                finding TEXT NOT NULL
+            )",
+        []
+    )?;
+    add_missing_columns(conn, "quarantine_verifications", &["last_checked_date TEXT"])?;
+    // older versions added a row on every pass
+    conn.execute(
+        "DELETE FROM quarantine_verifications WHERE id NOT IN
+                (SELECT MIN(id) FROM quarantine_verifications GROUP BY quarantine_path, finding)",
+        []
+    )?;
+    conn.execute(
+        "CREATE UNIQUE INDEX IF NOT EXISTS quarantine_verifications_finding ON quarantine_verifications (quarantine_path, finding)",
+        []
+    )?;
+    // the policy stored with `quarantine retention`, a single row
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS quarantine_retention (
+                id INTEGER PRIMARY KEY CHECK (id = 1),
+                max_size INTEGER,
+                max_age INTEGER,
+                action TEXT NOT NULL,
+                archive_dir TEXT NOT NULL,
+                updated_date TEXT NOT NULL
+            )",
+        []
+    )?;
+    // files retired by the retention policy or `quarantine purge`
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS quarantine_purges (
+                id INTEGER PRIMARY KEY,
+                purged_date TEXT NOT NULL,
+                quarantined_file_id INTEGER NOT NULL,
+                original_path TEXT NOT NULL,
+                quarantine_path TEXT NOT NULL,
+                sha256 TEXT,
+                size INTEGER,
+                action TEXT NOT NULL,
+                archive_path TEXT,
+                cause TEXT NOT NULL
+            )",
+        []
+    )?;
+    Ok(())
+}
+
+/// `CREATE TABLE IF NOT EXISTS` leaves older tables alone, add the columns they're missing
+fn add_missing_columns(conn: &Connection, table: &str, columns: &[&str]) -> Result<()> {
+    let existing = conn.prepare(&format!("PRAGMA table_info({table})"))?
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<Result<Vec<String>>>()?;
+
+    for column in columns {
+        let name = column.split_whitespace().next().unwrap_or_default();
+        if !existing.iter().any(|existing| existing == name) {
+            conn.execute(&format!("ALTER TABLE {table} ADD COLUMN {column}"), [])?;
+        }
+    }
+    Ok(())
+}
+
+fn init_db_scanner(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS scan_exclusions (
+                id INTEGER PRIMARY KEY,
+                pattern TEXT NOT NULL UNIQUE,
+                added_date TEXT NOT NULL
+            )",
+        []
+    )?;
+    // the models' and the rules' last word on every scanned file, see `ScanCache`
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS scan_cache (
+                dev INTEGER NOT NULL,
+                inode INTEGER NOT NULL,
+                size INTEGER NOT NULL,
+                mtime INTEGER NOT NULL,
+                ctime INTEGER NOT NULL,
+                sha256 TEXT NOT NULL,
+                model_version TEXT NOT NULL,
+                signature TEXT,
+                model TEXT NOT NULL,
+                score REAL,
+                entropy REAL,
+                is_malware INTEGER NOT NULL,
+                scanned_date TEXT NOT NULL,
+                PRIMARY KEY (dev, inode)
+            )",
+        []
+    )?;
+    add_missing_columns(conn, "scan_cache", &[
+        "rules TEXT NOT NULL DEFAULT ''",
+        "body_signatures TEXT NOT NULL DEFAULT ''",
+        "constructor INTEGER NOT NULL DEFAULT 0",
+        "interposed TEXT NOT NULL DEFAULT ''",
+    ])?;
+    conn.execute("CREATE INDEX IF NOT EXISTS scan_cache_sha256 ON scan_cache (sha256)", [])?;
+    // keyed by the hash alone at first, so removing a signature database dropped what was added by hand
+    let keyed_by_hash = conn.query_row(
+        "SELECT count(*) FROM pragma_table_info('hash_reputation') WHERE pk > 0", [], |row| row.get::<_, i64>(0),
+    )? == 1;
+    if keyed_by_hash {
+        conn.execute("ALTER TABLE hash_reputation RENAME TO hash_reputation_by_hash", [])?;
+    }
+    // known bad and known good SHA-256, SHA-1 and MD5 hashes, once per list they come from
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS hash_reputation (
+                hash TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                verdict TEXT NOT NULL,
+                name TEXT,
+                source TEXT NOT NULL,
+                added_date TEXT NOT NULL,
+                PRIMARY KEY (hash, source)
+            )",
+        []
+    )?;
+    if keyed_by_hash {
+        conn.execute("INSERT INTO hash_reputation SELECT hash, kind, verdict, name, source, added_date FROM hash_reputation_by_hash", [])?;
+        conn.execute("DROP TABLE hash_reputation_by_hash", [])?;
+    }
+    // ClamAV .ndb signatures, their hash signatures go to hash_reputation
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS body_signatures (
+                id INTEGER PRIMARY KEY,
+                name TEXT NOT NULL,
+                target TEXT NOT NULL,
+                offset TEXT NOT NULL,
+                pattern TEXT NOT NULL,
+                source TEXT NOT NULL,
+                added_date TEXT NOT NULL
+            )",
+        []
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS signature_databases (
+                source TEXT PRIMARY KEY,
+                path TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                hashes INTEGER NOT NULL,
+                bodies INTEGER NOT NULL,
+                skipped INTEGER NOT NULL,
+                imported_date TEXT NOT NULL
+            )",
+        []
+    )?;
     Ok(())
 }
 
+/// `scanner.db`, root-only like the quarantine records since it decides what scans skip and flag.
+/// Only read through this, users scanning or listing before root ever did get an empty one in memory.
+fn scanner_db() -> Connection {
+    let conn = match state::open_db("scanner.db") {
+        Ok(conn) => conn,
+        Err(StateError::Missing(_)) => Connection::open_in_memory().expect("Couldn't open an in-memory database"),
+        Err(e) => panic!("{e}"),
+    };
+    if !conn.is_readonly(rusqlite::MAIN_DB).unwrap_or(true) {
+        init_db_scanner(&conn).expect("Couldn't initialize database for the scanner");
+    }
+    conn
+}
+
+/// `scanner.db` to change `store` in. Refused unless it's root's to write,
+/// rather than changing an in-memory one or failing on a read-only one.
+fn writable_scanner_db(store: &str) -> Connection {
+    let conn = match state::open_db("scanner.db") {
+        Ok(conn) if !conn.is_readonly(rusqlite::MAIN_DB).unwrap_or(true) => conn,
+        Ok(_) | Err(StateError::Missing(_)) => panic!("The {store} is root-only, nothing was changed. Rerun with sudo"),
+        Err(e) => panic!("{e}"),
+    };
+    init_db_scanner(&conn).expect("Couldn't initialize database for the scanner");
+    conn
+}
+
+/// One pass of `quarantine verify`: retires what the retention policy says, then checks and records the vault.
+/// Only a failed check is an error, what couldn't be retired or recorded is logged.
+fn verify_vault(quarantinizer: &mut Quarantinizer) -> Result<(), QuarantineError> {
+    // the daemon is what retires files when nothing gets quarantined for a while
+    match quarantinizer.enforce_retention() {
+        Ok(retired) => for retired in retired {
+            println!("Retired {} {} ({}, {})", format!("[{}]", retired.file.id.unwrap_or_default()).bold(),
+                retired.file.original_path, retired.action.as_str(), retired.cause);
+        },
+        Err(e) => eprintln!("{} Couldn't enforce the retention policy: {e}", palette::current().danger("[ERROR]")),
+    }
+    let verifications = quarantinizer.verify()?;
+    if let Err(e) = quarantinizer.record_verifications(&verifications) {
+        eprintln!("{} Couldn't record the verification: {e}", palette::current().danger("[ERROR]"));
+    }
+
+    let problems = verifications.iter()
+        .filter(|verification| verification.finding != Finding::Ok)
+        .collect::<Vec<&Verification>>();
+    for problem in &problems {
+        let id = problem.id.map(|id| format!("[{id}]")).unwrap_or_else(|| "[-]".to_string());
+        println!("{} {} {}", palette::current().danger(&id), problem.quarantine_path, problem.finding);
+    }
+    let summary = format!("Verified {} quarantined files, {} problems", verifications.len(), problems.len());
+    println!("{}", if problems.is_empty() { palette::current().safe(&summary) } else { palette::current().danger(&summary) });
+    Ok(())
+}
+
+/// The quarantinizer of `--vault`, retiring by the stored retention policy with the command line's flags on top
+fn quarantinizer(conn: Option<Connection>, args: &Args) -> Quarantinizer {
+    let quarantinizer = match conn {
+        Some(conn) => Quarantinizer::from_db(conn).expect("Couldn't load the quarantined files"),
+        None => Quarantinizer::new(),
+    };
+    let retention = args.retention_policy(quarantinizer.retention().clone());
+    quarantinizer.with_vault(args.vault.clone()).with_retention(retention)
+}
+
 fn main() -> io::Result<()> {
     panic::set_hook(Box::new(|panic_info| {
         let location = panic_info.location();
         if let Some(location) = location {
-            eprintln!("{} {}\nat file {}\nat line {}", "[ERROR]".red().bold(), panic_info.payload_as_str().unwrap(), location.file(), location.line());
+            eprintln!("{} {}\nat file {}\nat line {}", palette::current().danger("[ERROR]"), panic_info.payload_as_str().unwrap(), location.file(), location.line());
         } else {
-            eprintln!("{} {}", "[ERROR]".red().bold(), panic_info.payload_as_str().unwrap());
+            eprintln!("{} {}", palette::current().danger("[ERROR]"), panic_info.payload_as_str().unwrap());
         }
         process::exit(1);
     }));
 
-    let home_dir = home_dir().unwrap_or(PathBuf::from("/tmp"));
 
     let args = Args::parse();
+    Palette::new(args.colorblindness).install();
 
     let conn_passwd = Connection::open("/usr/local/share/sentinel/passwd.db").unwrap();
-    let conn_quarantine = Connection::open("/usr/local/share/sentinel/quarantined_files.db").unwrap();
+    // root-only, the records say what root restores and shreds
+    let conn_quarantine = match state::open_db("quarantined_files.db") {
+        Ok(conn) => Some(conn),
+        // nothing was quarantined yet
+        Err(StateError::Missing(_)) => None,
+        Err(e) => panic!("{e}"),
+    };
     init_db_passwd(&conn_passwd).expect("Couldn't initialize database for passwd");
-    init_db_quarantine(&conn_quarantine).expect("Couldn't initialize database for quarantine");
+    // everyone but root reads the tables root created
+    if let Some(conn_quarantine) = &conn_quarantine
+        && !conn_quarantine.is_readonly(rusqlite::MAIN_DB).unwrap_or(true)
+    {
+        init_db_quarantine(conn_quarantine).expect("Couldn't initialize database for quarantine");
+    }
 
     match args.clone().command {
         Some(ScanDir { .. }) => {
-            let file_scanner = FileScanner::new(args.clone());
-            file_scanner.scan_files().unwrap();
+            // opened once, the hash reputation store and the cache share it
+            let scanner_db = Arc::new(Mutex::new(scanner_db()));
+            let (exclusions, signatures) = {
+                let conn = scanner_db.lock().unwrap();
+                (ExclusionList::patterns(&conn).expect("Couldn't load the scan exclusions"),
+                    BodySignatures::from_db(&conn).expect("Couldn't load the body signatures"))
+            };
+            let quarantinizer = quarantinizer(conn_quarantine, &args);
+            let mut file_scanner = FileScanner::new(args.clone())
+                .with_exclusions(exclusions)
+                .expect("Invalid scan exclusion in the database")
+                .with_quarantinizer(quarantinizer)
+                .with_reputation(HashReputation::from_shared(scanner_db.clone())
+                    .expect("Couldn't open the hash reputation store"))
+                .with_signatures(signatures)
+                .with_cache(scanner_db)
+                .expect("Couldn't open the scan cache");
+            let found = file_scanner.scan_files().unwrap_or_else(|e| panic!("{e}"));
+            if found > 0 {
+                process::exit(EXIT_DETECTIONS);
+            }
         }
         Some(CheckUnauthorizedChanges { .. }) => {
             let mut unauthorized_changes_scanner = UnauthorizedChangesScanner::from_db(conn_passwd);
@@ -80,33 +346,174 @@ fn main() -> io::Result<()> {
                 process_behaviors_analyzer.analyze();
             }
         }
-        Some(Quarantine { file, view, view_mode } ) => {
-            let mut quarantinizer = Quarantinizer::from_db(conn_quarantine).unwrap();
-            let quarantine_path = home_dir.join(".sentinel_quarantine");
-            if view {
-                let maybe_files = match view_mode {
-                    Some(ViewMode::Database) => quarantinizer.get_quarantined_files().ok(),
-                    Some(ViewMode::Local) => quarantinizer.get_local_files(&quarantine_path).ok(),
-                    None => None,
-                    _ => todo!()
-                };
-                if let Some(files) = maybe_files {
-                    // specifically, quarantined file paths
-                    let paths = files.iter().map(|f| f.quarantine_path.clone()).collect::<Vec<String>>();
-
-                    for (i, path) in paths.iter().enumerate() {
-                        println!("{} {path}", format!("File {i}").bold());
+        Some(Quarantine { file, view, view_mode, format, since, until, reason, action } ) => {
+            let mut quarantinizer = quarantinizer(conn_quarantine, &args);
+            if let Some(action) = action {
+                match action {
+                    QuarantineCommands::Restore { id, on_conflict, to } => {
+                        let restored = quarantinizer.restore(id, on_conflict, to.as_deref()).unwrap_or_else(|e| panic!("{e}"));
+                        println!("Restored {restored:?}");
+                    }
+                    QuarantineCommands::Delete { id } => {
+                        quarantinizer.delete(id).unwrap_or_else(|e| panic!("{e}"));
+                        println!("Deleted quarantined file {id}");
                     }
+                    QuarantineCommands::Purge { older_than } => {
+                        let purged = quarantinizer.purge(older_than).unwrap_or_else(|e| panic!("{e}"));
+                        for qf in &purged {
+                            println!("Deleted {} {}", format!("[{}]", qf.id.unwrap_or_default()).bold(), qf.original_path);
+                        }
+                        println!("Purged {} quarantined files", purged.len());
+                    }
+                    QuarantineCommands::Export { ids, out, password_file } => {
+                        let password = quarantine::read_password(password_file.as_deref(), true).unwrap_or_else(|e| panic!("{e}"));
+                        let exported = quarantinizer.export(&ids, &out, &password).unwrap_or_else(|e| panic!("{e}"));
+                        println!("Exported {} quarantined files into {:?}", exported.len(), out);
+                    }
+                    QuarantineCommands::Import { bundle, password_file } => {
+                        let password = quarantine::read_password(password_file.as_deref(), false).unwrap_or_else(|e| panic!("{e}"));
+                        let imported = quarantinizer.import(&bundle, &password).unwrap_or_else(|e| panic!("{e}"));
+                        for qf in &imported {
+                            println!("Imported {} {}", format!("[{}]", qf.id.unwrap_or_default()).bold(), qf.original_path);
+                        }
+                        println!("Imported {} quarantined files from {:?}", imported.len(), bundle);
+                    }
+                    QuarantineCommands::Retention { clear } => {
+                        if clear {
+                            quarantinizer.clear_retention().unwrap_or_else(|e| panic!("{e}"));
+                        } else if args.sets_retention() {
+                            quarantinizer.store_retention().unwrap_or_else(|e| panic!("{e}"));
+                        }
+                        println!("Retention policy: {}", quarantinizer.retention());
+                    }
+                    QuarantineCommands::Verify { every } => loop {
+                        let verified = verify_vault(&mut quarantinizer);
+                        match every {
+                            // the daemon outlives a failed pass, the next one may go through
+                            Some(every) => {
+                                if let Err(e) = verified {
+                                    eprintln!("{} {e}", palette::current().danger("[ERROR]"));
+                                }
+                                std::thread::sleep(every.to_std().unwrap_or(Duration::from_secs(3600)));
+                            }
+                            None => break verified.unwrap_or_else(|e| panic!("{e}")),
+                        }
+                    },
                 }
+            } else if view {
+                let filter = ListFilter { since, until, reason };
+                let files = quarantinizer.list(view_mode.unwrap_or(ViewMode::All))
+                    .unwrap_or_else(|e| panic!("{e}"))
+                    .into_iter()
+                    .filter(|listed| filter.matches(&listed.file))
+                    .collect::<Vec<ListedFile>>();
+                print!("{}", quarantine::render(&files, format));
             } else {
-                quarantinizer.push_quarantined(
-                    QuarantinedFile {
-                        original_path: file.unwrap().to_str().unwrap().to_string(),
-                        quarantine_path: quarantine_path.to_string_lossy().to_string(),
-                        reason: "No reason".to_string(),
-                        quarantined_date: Some(Local::now()),
-                    }
-                ).unwrap();
+                let quarantined = quarantinizer.push_quarantined(QuarantinedFile::new(&file.unwrap(), "No reason"))
+                    .unwrap_or_else(|e| panic!("{e}"));
+                for qf in quarantined {
+                    println!("Quarantined {:?} into {:?}", qf.original_path, qf.quarantine_path);
+                }
+            }
+        }
+        Some(Reputation { action }) => {
+            let conn = match action {
+                ReputationCommands::Lookup { .. } => scanner_db(),
+                _ => writable_scanner_db("hash reputation store"),
+            };
+            let reputation = HashReputation::from_db(conn).expect("Couldn't open the hash reputation store");
+            match action {
+                ReputationCommands::Add { hash, verdict, name } => {
+                    if reputation.add(&hash, verdict, name.as_deref(), "manual").expect("Couldn't store hash") {
+                        println!("Marked {hash} as known {}", verdict.as_str());
+                    } else {
+                        panic!("{hash:?} isn't a SHA-256, SHA-1 or MD5");
+                    }
+                }
+                ReputationCommands::Remove { hash } => {
+                    if reputation.remove(&hash).expect("Couldn't remove hash") {
+                        println!("Removed {hash} from the reputation store");
+                    } else {
+                        eprintln!("{hash} isn't in the reputation store");
+                    }
+                }
+                ReputationCommands::Import { file, verdict, format, source } => {
+                    let (imported, skipped) = reputation.import(&file, verdict, format, source.as_deref())
+                        .unwrap_or_else(|e| panic!("{e}"));
+                    println!("Imported {imported} hashes from {:?}", file);
+                    if skipped > 0 {
+                        eprintln!("{}", palette::current().warning(&format!("Skipped {skipped} lines that aren't a SHA-256, SHA-1 or MD5")));
+                    }
+                }
+                ReputationCommands::Lookup { hash } => {
+                    let known = reputation.get(&hash).expect("Couldn't look up hash");
+                    if known.is_empty() {
+                        println!("{hash} is unknown");
+                    }
+                    for known in known {
+                        println!("{} {known}", known.hash);
+                    }
+                }
+            }
+        }
+        Some(Signatures { action }) => {
+            let conn = match action {
+                SignatureCommands::List => scanner_db(),
+                _ => writable_scanner_db("signature store"),
+            };
+            let mut signatures = SignatureStore::from_db(conn);
+            match action {
+                SignatureCommands::Import { files } => {
+                    for file in files {
+                        let imported = signatures.import(&file).unwrap_or_else(|e| panic!("{e}"));
+                        println!("Imported {} hash and {} body signatures from {}", imported.hashes, imported.bodies, imported.source);
+                        for (reason, count) in &imported.skipped {
+                            eprintln!("{}", palette::current().warning(&format!("Skipped {count} signatures: {reason}")));
+                        }
+                    }
+                }
+                SignatureCommands::List => {
+                    for database in signatures.list().expect("Couldn't list signature databases") {
+                        let date = database.imported_date.map(|d| d.format("%Y-%m-%d %H:%M").to_string()).unwrap_or_default();
+                        println!("{} {} {} hash, {} body signatures ({} skipped) {}", format!("[{}]", database.kind).bold(),
+                            database.source, database.hashes, database.bodies, database.skipped, date.dimmed());
+                    }
+                }
+                SignatureCommands::Remove { source } => {
+                    if signatures.remove(&source).expect("Couldn't remove signature database") {
+                        println!("Removed the signatures of {source}");
+                    } else {
+                        eprintln!("{source} was never imported");
+                    }
+                }
+            }
+        }
+        Some(Exclusions { action }) => {
+            let exclusions = ExclusionList::from_db(match action {
+                ExclusionCommands::List => scanner_db(),
+                _ => writable_scanner_db("scan exclusion list"),
+            });
+            match action {
+                ExclusionCommands::Add { pattern } => {
+                    if exclusions.add(&pattern).unwrap_or_else(|e| panic!("{e}")) {
+                        println!("Excluded {pattern:?} from scans");
+                    } else {
+                        println!("{pattern:?} is already excluded");
+                    }
+                }
+                ExclusionCommands::Remove { pattern } => {
+                    if exclusions.remove(&pattern).expect("Couldn't remove exclusion") {
+                        println!("Removed {pattern:?} from the exclusions");
+                    } else {
+                        eprintln!("{pattern:?} isn't excluded");
+                    }
+                }
+                ExclusionCommands::List => {
+                    for exclusion in exclusions.list().expect("Couldn't list exclusions") {
+                        let date = exclusion.added_date.map(|d| d.format("%Y-%m-%d %H:%M").to_string()).unwrap_or_default();
+                        println!("{} {} {}", format!("[{}]", exclusion.id).bold(), exclusion.pattern, date.dimmed());
+                    }
+                }
             }
         }
         None => {